@@ -0,0 +1,633 @@
+//! Exporting a net and its rulebook to a standalone C program, for running on
+//! machines without a Rust toolchain.
+//!
+//! The program is a single C99 file with no dependencies beyond the standard
+//! library. It's made of:
+//!
+//! - a table of the kinds of agents that can show up, numbered in the same
+//!   order as [`AgentKind`] sorts, so rules get their agents in the same order
+//!   as they do from [`Rulebook::rewrite`],
+//! - the reducer in `src/c/runtime.c`, which keeps agents in flat arrays,
+//! - the rulebook, compiled into a `switch` over pairs of kinds,
+//! - and the net, as arrays of agents, ports and connections.
+//!
+//! When it's run, it reduces the net and prints the normal form with one
+//! connection per line, in the format from [`crate::net::text`]. IDs are
+//! renumbered from zero, and aren't the same as the ones a [`Runtime`] would
+//! give, but the shape of the net is.
+//!
+//! Dynamic and native rules are Rust code, so they can't be exported, and
+//! neither can references, since definitions aren't.
+//!
+//! [`Rulebook::rewrite`]: crate::rule::rulebook::Rulebook::rewrite
+//! [`Runtime`]: crate::runtime::Runtime
+
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    io::{self, Write},
+};
+
+use crate::{
+    net::{
+        connection::Connection,
+        id,
+        term::{AgentKind, Operator, Term},
+    },
+    rule::{
+        builtin::Builtin,
+        compiled::{CompiledRule, Instruction},
+        rulebook::Rulebook,
+        Rule,
+    },
+};
+
+const RUNTIME: &str = include_str!("c/runtime.c");
+
+#[derive(Debug)]
+pub enum ExportError {
+    Io(io::Error),
+    /// The net or rulebook uses something that can't be exported.
+    Unsupported(String),
+}
+
+impl From<io::Error> for ExportError {
+    fn from(value: io::Error) -> Self {
+        Self::Io(value)
+    }
+}
+
+/// Writes a Rust string as a C string literal.
+fn c_string(s: &str) -> String {
+    let mut literal = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => literal.push_str("\\\""),
+            '\\' => literal.push_str("\\\\"),
+            '\n' => literal.push_str("\\n"),
+            c if c.is_ascii_graphic() || c == ' ' => literal.push(c),
+            c => {
+                let mut bytes = [0; 4];
+                for byte in c.encode_utf8(&mut bytes).bytes() {
+                    // octal escapes can't swallow the characters after them
+                    write!(literal, "\\{byte:03o}").unwrap();
+                }
+            }
+        }
+    }
+    literal.push('"');
+    literal
+}
+
+/// C expression applying an operator to two `uint64_t`s.
+fn c_apply(op: Operator, left: &str, right: &str) -> String {
+    match op {
+        Operator::Add => format!("{left} + {right}"),
+        Operator::Sub => format!("{left} - {right}"),
+        Operator::Mul => format!("{left} * {right}"),
+        Operator::Div => format!("({right} ? {left} / {right} : 0)"),
+        Operator::Eq => format!("(uint64_t)({left} == {right})"),
+        Operator::Ne => format!("(uint64_t)({left} != {right})"),
+        Operator::Lt => format!("(uint64_t)({left} < {right})"),
+        Operator::Le => format!("(uint64_t)({left} <= {right})"),
+        Operator::Gt => format!("(uint64_t)({left} > {right})"),
+        Operator::Ge => format!("(uint64_t)({left} >= {right})"),
+    }
+}
+
+/// The kinds of agents that show up in a program, numbered in order.
+#[derive(Default)]
+struct Kinds(BTreeMap<AgentKind, usize>);
+
+impl Kinds {
+    fn add(&mut self, kind: AgentKind) -> Result<(), ExportError> {
+        if let AgentKind::Reference(_) = kind {
+            return Err(ExportError::Unsupported(format!(
+                "references like {kind:?} can't be exported"
+            )));
+        }
+
+        self.0.insert(kind, 0);
+        Ok(())
+    }
+
+    fn add_term(&mut self, term: &Term) -> Result<(), ExportError> {
+        if let Term::Agent(agent) = term {
+            self.add(agent.kind)?;
+            for port in agent.ports.iter() {
+                self.add_term(port)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn number(&mut self) {
+        for (number, index) in self.0.values_mut().enumerate() {
+            *index = number;
+        }
+    }
+
+    /// C expression for a kind, which has to have been added.
+    fn code(&self, kind: AgentKind) -> String {
+        format!("{} /* {kind:?} */", self.0[&kind])
+    }
+}
+
+/// Writes the body of a case of `rewrite()` for a builtin rule. The agents are
+/// `a` and `b`, and their ports and data are in `ap`, `ad`, `bp` and `bd`.
+fn write_builtin(out: &mut String, kinds: &Kinds, builtin: &Builtin) {
+    let era = kinds.code(AgentKind::Eraser);
+    let dup = kinds.code(AgentKind::Duplicator);
+    let ctr = kinds.code(AgentKind::Constructor);
+    let num = kinds.code(AgentKind::Number);
+
+    let body = match builtin {
+        Builtin::EraEra | Builtin::NumEra => String::new(),
+        Builtin::CtrCtr | Builtin::DupDup => {
+            "net_link(ap[0], bp[0]);\nnet_link(ap[1], bp[1]);\n".to_string()
+        }
+        // `a` is the duplicator and `b` is the constructor
+        Builtin::CtrDup => format!(
+            "Term aa = new_port(), ab = new_port(), ba = new_port(), bb = new_port();\n\
+             net_link(new_agent({ctr}, 0, 2, (Term[]){{aa, ab}}), ap[0]);\n\
+             net_link(new_agent({ctr}, 0, 2, (Term[]){{ba, bb}}), ap[1]);\n\
+             net_link(new_agent({dup}, 0, 2, (Term[]){{aa, ba}}), bp[0]);\n\
+             net_link(new_agent({dup}, 0, 2, (Term[]){{ab, bb}}), bp[1]);\n"
+        ),
+        Builtin::DupEra | Builtin::CtrEra => format!(
+            "net_link(new_agent({era}, 0, 0, NULL), bp[0]);\n\
+             net_link(new_agent({era}, 0, 0, NULL), bp[1]);\n"
+        ),
+        Builtin::NumDup => format!(
+            "net_link(new_agent({num}, bd, 0, NULL), ap[0]);\n\
+             net_link(new_agent({num}, bd, 0, NULL), ap[1]);\n"
+        ),
+        Builtin::OpNum(op) => format!(
+            "net_link(new_agent({}, ad, 1, (Term[]){{bp[1]}}), bp[0]);\n",
+            kinds.code(AgentKind::PartialOp(*op))
+        ),
+        Builtin::PartialNum(op) => format!(
+            "net_link(new_agent({num}, {}, 0, NULL), bp[0]);\n",
+            c_apply(*op, "bd", "ad")
+        ),
+        Builtin::SwitchNum => format!(
+            "Term era = new_agent({era}, 0, 0, NULL);\n\
+             if (ad == 0) {{\n\
+             \x20   net_link(new_agent({ctr}, 0, 2, (Term[]){{bp[1], era}}), bp[0]);\n\
+             }} else {{\n\
+             \x20   Term pred = new_agent({num}, ad - 1, 0, NULL);\n\
+             \x20   Term applied = new_agent({ctr}, 0, 2, (Term[]){{pred, bp[1]}});\n\
+             \x20   net_link(new_agent({ctr}, 0, 2, (Term[]){{era, applied}}), bp[0]);\n\
+             }}\n"
+        ),
+        Builtin::OpEra(_)
+        | Builtin::PartialEra(_)
+        | Builtin::SwitchEra
+        | Builtin::DynEra { .. } => format!(
+            "for (uint32_t i = 0; i < bn; i++) {{\n\
+             \x20   net_link(new_agent({era}, 0, 0, NULL), bp[i]);\n\
+             }}\n"
+        ),
+        Builtin::OpDup(_)
+        | Builtin::PartialDup(_)
+        | Builtin::SwitchDup
+        | Builtin::DynDup { .. } => format!(
+            "Term *xs = alloc_or_exit((bn + 1) * sizeof(Term));\n\
+             Term *ys = alloc_or_exit((bn + 1) * sizeof(Term));\n\
+             for (uint32_t i = 0; i < bn; i++) {{\n\
+             \x20   xs[i] = new_port();\n\
+             \x20   ys[i] = new_port();\n\
+             \x20   net_link(new_agent({dup}, 0, 2, (Term[]){{xs[i], ys[i]}}), bp[i]);\n\
+             }}\n\
+             net_link(new_agent(bk, bd, bn, xs), ap[0]);\n\
+             net_link(new_agent(bk, bd, bn, ys), ap[1]);\n\
+             free(xs);\n\
+             free(ys);\n"
+        ),
+    };
+
+    for line in body.lines() {
+        writeln!(out, "        {line}").unwrap();
+    }
+}
+
+/// Writes the body of a case of `rewrite()` for a compiled template, like
+/// [`CompiledRule::execute`] runs it.
+fn write_template(out: &mut String, kinds: &Kinds, rule: &CompiledRule) {
+    let template = rule.template();
+    let left_arity = template.left().ports.len();
+    let right_arity = template.right().ports.len();
+
+    writeln!(
+        out,
+        "        if (an != {left_arity} || bn != {right_arity}) {{\n            return 0;\n        }}"
+    )
+    .unwrap();
+    writeln!(out, "        Term s[{}];", rule.slot_count().max(1)).unwrap();
+    for index in 0..left_arity {
+        writeln!(out, "        s[{index}] = ap[{index}];").unwrap();
+    }
+    for index in 0..right_arity {
+        writeln!(out, "        s[{}] = bp[{index}];", left_arity + index).unwrap();
+    }
+
+    for instruction in rule.instructions() {
+        match *instruction {
+            Instruction::Wire { a, b } => {
+                writeln!(out, "        s[{a}] = s[{b}] = new_port();").unwrap();
+            }
+            Instruction::Node {
+                slot,
+                kind,
+                data,
+                first,
+                arity,
+            } => {
+                let ports = if arity == 0 {
+                    "NULL".to_string()
+                } else {
+                    let operands = &rule.operands()[first as usize..(first + arity) as usize];
+                    let ports: Vec<_> = operands.iter().map(|slot| format!("s[{slot}]")).collect();
+                    format!("(Term[]){{{}}}", ports.join(", "))
+                };
+
+                writeln!(
+                    out,
+                    "        s[{slot}] = new_agent({}, {data}u, {arity}, {ports});",
+                    kinds.code(kind)
+                )
+                .unwrap();
+            }
+            Instruction::Link { a, b } => {
+                writeln!(out, "        net_link(s[{a}], s[{b}]);").unwrap();
+            }
+        }
+    }
+}
+
+/// Writes a C program that reduces the net with the rules in the rulebook and
+/// prints its normal form.
+pub fn write_program(
+    mut writer: impl Write,
+    connections: impl IntoIterator<Item = Connection>,
+    rulebook: &Rulebook,
+) -> Result<(), ExportError> {
+    let (connections, allocator) = id::compact(connections);
+    let id_count = allocator.state().next_index;
+
+    let mut kinds = Kinds::default();
+    for kind in [
+        AgentKind::Eraser,
+        AgentKind::Duplicator,
+        AgentKind::Constructor,
+        AgentKind::Number,
+    ] {
+        kinds.add(kind)?;
+    }
+
+    for Connection(left, right) in &connections {
+        kinds.add_term(left)?;
+        kinds.add_term(right)?;
+    }
+
+    for (pattern, rule) in rulebook.rules() {
+        let (a, b) = pattern.pattern();
+        kinds.add(*a)?;
+        kinds.add(*b)?;
+
+        match rule {
+            Rule::Builtin(Builtin::OpNum(op)) => kinds.add(AgentKind::PartialOp(*op))?,
+            Rule::Template(rule) => {
+                for connection in rule.template().connections() {
+                    kinds.add_term(connection.left())?;
+                    kinds.add_term(connection.right())?;
+                }
+            }
+            Rule::Builtin(_) => {}
+            Rule::Dynamic(_) => {
+                return Err(ExportError::Unsupported(format!(
+                    "the rule for {a:?} = {b:?} is a closure, which can't be exported"
+                )))
+            }
+            Rule::Native(_) => {
+                return Err(ExportError::Unsupported(format!(
+                    "the rule for {a:?} = {b:?} is a Rust function, which can't be exported"
+                )))
+            }
+        }
+    }
+    kinds.number();
+
+    let mut out = String::new();
+    let o = &mut out;
+
+    writeln!(o, "/* Generated by inet-rs. */").unwrap();
+    writeln!(o).unwrap();
+    writeln!(o, "#define KIND_COUNT {}", kinds.0.len()).unwrap();
+    writeln!(o, "static const char *const KIND_NAMES[] = {{").unwrap();
+    for kind in kinds.0.keys() {
+        writeln!(o, "    {},", c_string(&format!("{kind:?}"))).unwrap();
+    }
+    writeln!(o, "}};").unwrap();
+    writeln!(o, "static const int KIND_HAS_DATA[] = {{").unwrap();
+    for kind in kinds.0.keys() {
+        writeln!(o, "    {},", kind.has_data() as u8).unwrap();
+    }
+    writeln!(o, "}};").unwrap();
+    writeln!(o).unwrap();
+
+    o.push_str(RUNTIME);
+    writeln!(o).unwrap();
+
+    writeln!(o, "static int rewrite(uint64_t a, uint64_t b) {{").unwrap();
+    writeln!(
+        o,
+        "    Term *ap = nodes.items[a].ports, *bp = nodes.items[b].ports;"
+    )
+    .unwrap();
+    writeln!(
+        o,
+        "    uint64_t ad = nodes.items[a].data, bd = nodes.items[b].data;"
+    )
+    .unwrap();
+    writeln!(
+        o,
+        "    uint32_t an = nodes.items[a].arity, bn = nodes.items[b].arity;"
+    )
+    .unwrap();
+    writeln!(o, "    uint32_t bk = nodes.items[b].kind;").unwrap();
+    writeln!(
+        o,
+        "    (void)ap, (void)bp, (void)ad, (void)bd, (void)an, (void)bn, (void)bk;"
+    )
+    .unwrap();
+    writeln!(o).unwrap();
+    writeln!(
+        o,
+        "    switch (nodes.items[a].kind * KIND_COUNT + nodes.items[b].kind) {{"
+    )
+    .unwrap();
+    for (pattern, rule) in rulebook.rules() {
+        let (a, b) = pattern.pattern();
+        writeln!(
+            o,
+            "    case {} * KIND_COUNT + {}: {{",
+            kinds.code(*a),
+            kinds.code(*b)
+        )
+        .unwrap();
+
+        match rule {
+            Rule::Builtin(builtin) => write_builtin(o, &kinds, builtin),
+            Rule::Template(rule) => write_template(o, &kinds, rule),
+            Rule::Dynamic(_) | Rule::Native(_) => {
+                unreachable!("dynamic and native rules are rejected above")
+            }
+        }
+
+        writeln!(o, "        return 1;").unwrap();
+        writeln!(o, "    }}").unwrap();
+    }
+    writeln!(o, "    }}").unwrap();
+    writeln!(o, "    return 0;").unwrap();
+    writeln!(o, "}}").unwrap();
+    writeln!(o).unwrap();
+
+    write_net(o, &kinds, &connections, id_count);
+
+    writeln!(o).unwrap();
+    writeln!(o, "int main(void) {{").unwrap();
+    writeln!(o, "    load();").unwrap();
+    writeln!(o, "    reduce();").unwrap();
+    writeln!(o, "    print_net();").unwrap();
+    writeln!(
+        o,
+        "    fprintf(stderr, \"%\" PRIu64 \" interactions\\n\", interactions);"
+    )
+    .unwrap();
+    writeln!(o, "    return 0;").unwrap();
+    writeln!(o, "}}").unwrap();
+
+    writer.write_all(out.as_bytes())?;
+    Ok(())
+}
+
+/// The net, flattened into the tables that `load()` reads.
+#[derive(Default)]
+struct Tables {
+    /// Each agent as a `Node` initializer, with its ports left out.
+    nodes: Vec<String>,
+    /// The ports of each agent.
+    ports: Vec<Vec<String>>,
+    port_names: BTreeMap<usize, String>,
+}
+
+impl Tables {
+    /// Adds a term (and any agents in it), returning the C expression for it.
+    fn term(&mut self, kinds: &Kinds, term: &Term) -> String {
+        match term {
+            Term::Port(port) => {
+                if let Some(name) = &port.name {
+                    self.port_names.insert(*term.id(), name.clone());
+                }
+
+                format!("PORT({})", term.id())
+            }
+            Term::Agent(agent) => {
+                let name = agent.name().map_or("NULL".to_string(), c_string);
+                self.nodes.push(format!(
+                    "{{{}, {}, {}u, {}u, {name}, NULL}}",
+                    kinds.code(agent.kind),
+                    agent.ports.len(),
+                    agent.data,
+                    agent.id
+                ));
+                self.ports.push(Vec::new());
+
+                let index = self.nodes.len() - 1;
+                self.ports[index] = agent
+                    .ports
+                    .iter()
+                    .map(|port| self.term(kinds, port))
+                    .collect();
+
+                format!("AGENT({index})")
+            }
+        }
+    }
+}
+
+/// Writes the tables for the net and a `load()` function that reads them.
+fn write_net(o: &mut String, kinds: &Kinds, connections: &[Connection], id_count: usize) {
+    let mut tables = Tables::default();
+    let connections: Vec<_> = connections
+        .iter()
+        .map(|connection| {
+            let left = tables.term(kinds, connection.left());
+            let right = tables.term(kinds, connection.right());
+            format!("{{{left}, {right}}}")
+        })
+        .collect();
+
+    writeln!(o, "#define NET_ID_COUNT {id_count}").unwrap();
+    writeln!(o, "#define NET_NODE_COUNT {}", tables.nodes.len()).unwrap();
+    writeln!(o, "#define NET_CONNECTION_COUNT {}", connections.len()).unwrap();
+    writeln!(o, "#define NET_PORT_NAME_COUNT {}", tables.port_names.len()).unwrap();
+    writeln!(o).unwrap();
+
+    // C doesn't allow empty arrays, so each one ends with a placeholder
+    writeln!(o, "static const Node NET_NODES[] = {{").unwrap();
+    for node in &tables.nodes {
+        writeln!(o, "    {node},").unwrap();
+    }
+    writeln!(o, "    {{0}},").unwrap();
+    writeln!(o, "}};").unwrap();
+
+    writeln!(o, "static const Term NET_PORTS[] = {{").unwrap();
+    for (index, ports) in tables.ports.iter().enumerate() {
+        if !ports.is_empty() {
+            writeln!(o, "    /* {index} */ {},", ports.join(", ")).unwrap();
+        }
+    }
+    writeln!(o, "    0,").unwrap();
+    writeln!(o, "}};").unwrap();
+
+    writeln!(o, "static const Term NET_CONNECTIONS[][2] = {{").unwrap();
+    for connection in &connections {
+        writeln!(o, "    {connection},").unwrap();
+    }
+    writeln!(o, "    {{0, 0}},").unwrap();
+    writeln!(o, "}};").unwrap();
+
+    writeln!(o, "static const struct {{").unwrap();
+    writeln!(o, "    uint64_t id;").unwrap();
+    writeln!(o, "    const char *name;").unwrap();
+    writeln!(o, "}} NET_PORT_NAMES[] = {{").unwrap();
+    for (id, name) in &tables.port_names {
+        writeln!(o, "    {{{id}, {}}},", c_string(name)).unwrap();
+    }
+    writeln!(o, "    {{0, NULL}},").unwrap();
+    writeln!(o, "}};").unwrap();
+    writeln!(o).unwrap();
+
+    o.push_str(
+        "static void load(void) {
+    for (uint64_t id = 0; id < NET_ID_COUNT; id++) {
+        PUSH(wires, EMPTY);
+        PUSH(port_names, NULL);
+    }
+    for (size_t i = 0; i < NET_PORT_NAME_COUNT; i++) {
+        port_names.items[NET_PORT_NAMES[i].id] = NET_PORT_NAMES[i].name;
+    }
+
+    const Term *ports = NET_PORTS;
+    for (size_t i = 0; i < NET_NODE_COUNT; i++) {
+        Node node = NET_NODES[i];
+        if (node.arity) {
+            node.ports = alloc_or_exit(node.arity * sizeof(Term));
+            memcpy(node.ports, ports, node.arity * sizeof(Term));
+            ports += node.arity;
+        }
+        PUSH(nodes, node);
+    }
+
+    for (size_t i = 0; i < NET_CONNECTION_COUNT; i++) {
+        net_link(NET_CONNECTIONS[i][0], NET_CONNECTIONS[i][1]);
+    }
+}
+",
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process::Command;
+
+    use super::*;
+    use crate::{
+        net::{canonical::CanonicalNet, id::IdAllocator, text::parse_connection},
+        rule::context::RewriteContext,
+        runtime::Runtime,
+    };
+
+    fn net() -> Vec<Connection> {
+        [
+            // duplicating a tree with a number, an eraser and a dynamic agent
+            r#"Duplicator#1($2:"a", $3:"b") = Constructor#4(Constructor#5(Number{1}#6(), Eraser#7()), Dynamic[0]#8($9:"c"))"#,
+            // 2 + 3
+            r#"BinaryOp[Add]#10($11, $12:"sum") = Number{2}#13()"#,
+            "$11 = Number{3}#14()",
+            // switching on 2
+            r#"Switch#15($16, $17:"switch") = Number{2}#18()"#,
+            r#"$16 = Constructor#19($20:"zero", $21:"succ")"#,
+            // duplicating a partially applied operator
+            r#"Duplicator#22($23:"d", $24:"e") = PartialOp[Mul]{4}#25($26:"f")"#,
+            // a pair without a rule, which is left as it is
+            r#"Dynamic[1]#27($28:"g") = Constructor#29($30:"h", $31:"i")"#,
+        ]
+        .into_iter()
+        .map(|src| parse_connection(src).unwrap())
+        .collect()
+    }
+
+    fn rulebook() -> Rulebook {
+        let mut rulebook = Rulebook::default();
+        rulebook.declare_agent(0, 1);
+        rulebook.declare_agent(1, 1);
+        rulebook
+    }
+
+    #[test]
+    fn exported_program_reaches_the_same_normal_form() {
+        if Command::new("cc").arg("--version").output().is_err() {
+            eprintln!("skipping: there's no C compiler");
+            return;
+        }
+
+        let dir = std::env::temp_dir().join(format!("inet-rs-c-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let source = dir.join("net.c");
+        let binary = dir.join("net");
+
+        let mut program = Vec::new();
+        write_program(&mut program, net(), &rulebook()).unwrap();
+        std::fs::write(&source, program).unwrap();
+
+        let status = Command::new("cc")
+            .args(["-std=c99", "-Wall", "-Werror", "-o"])
+            .arg(&binary)
+            .arg(&source)
+            .status()
+            .unwrap();
+        assert!(status.success(), "the exported program doesn't compile");
+
+        let output = Command::new(&binary).output().unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(output.status.success());
+
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        let connections: Vec<_> = stdout
+            .lines()
+            .map(|line| parse_connection(line).unwrap())
+            .collect();
+
+        let mut runtime = Runtime::new(
+            net(),
+            rulebook(),
+            RewriteContext::new(IdAllocator::new_at(100)),
+        );
+        runtime.reduce();
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        assert_eq!(stderr, format!("{} interactions\n", runtime.interactions()));
+
+        let expected = runtime
+            .normalize()
+            .into_iter()
+            .map(|(left, right)| Connection(left, right));
+        assert!(
+            CanonicalNet::new(connections) == CanonicalNet::new(expected),
+            "the exported program reached a different normal form:\n{stdout}"
+        );
+    }
+}
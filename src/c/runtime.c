@@ -0,0 +1,273 @@
+/*
+ * The part of an exported program that doesn't depend on the net or the
+ * rulebook. See `src/c.rs` for how it fits together.
+ *
+ * It mirrors `Runtime` in `src/runtime/mod.rs`: terms are trees of agents
+ * whose leaves are ports, and every port shows up twice. `wires` holds, for
+ * each port that's waiting for its other occurrence, the term on the far side
+ * of it.
+ */
+
+#include <inttypes.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+/* A port ID or a node index, shifted left, with the low bit set for nodes. */
+typedef uint64_t Term;
+
+#define EMPTY UINT64_MAX
+#define IS_AGENT(term) ((term) & 1)
+#define INDEX(term) ((term) >> 1)
+#define PORT(id) ((Term)(id) << 1)
+#define AGENT(node) (((Term)(node) << 1) | 1)
+
+typedef struct {
+    uint32_t kind;
+    uint32_t arity;
+    uint64_t data;
+    uint64_t id;
+    const char *name;
+    Term *ports;
+} Node;
+
+typedef struct {
+    uint64_t a;
+    uint64_t b;
+} Pair;
+
+#define VEC(type)     \
+    struct {          \
+        type *items;  \
+        size_t len;   \
+        size_t cap;   \
+    }
+
+#define PUSH(vec, item)                                                         \
+    do {                                                                        \
+        if ((vec).len == (vec).cap) {                                           \
+            (vec).cap = (vec).cap ? (vec).cap * 2 : 64;                         \
+            (vec).items = realloc((vec).items, (vec).cap * sizeof *(vec).items); \
+            if (!(vec).items) {                                                 \
+                fputs("out of memory\n", stderr);                               \
+                exit(1);                                                        \
+            }                                                                   \
+        }                                                                       \
+        (vec).items[(vec).len++] = (item);                                      \
+    } while (0)
+
+/* Like `malloc`, but exits instead of returning NULL. */
+static void *alloc_or_exit(size_t size) {
+    void *ptr = malloc(size);
+    if (!ptr) {
+        fputs("out of memory\n", stderr);
+        exit(1);
+    }
+    return ptr;
+}
+
+static VEC(Node) nodes;
+static VEC(uint64_t) free_nodes;
+/* Indexed by ID. Only ports use their entry. */
+static VEC(Term) wires;
+static VEC(const char *) port_names;
+static VEC(uint64_t) free_ids;
+static VEC(Pair) redexes;
+/* Active pairs that have no rule. */
+static VEC(Pair) stuck;
+static uint64_t interactions;
+
+static uint64_t new_id(void) {
+    if (free_ids.len) {
+        return free_ids.items[--free_ids.len];
+    }
+
+    PUSH(wires, EMPTY);
+    PUSH(port_names, NULL);
+    return wires.len - 1;
+}
+
+static void retire_id(uint64_t id) {
+    wires.items[id] = EMPTY;
+    port_names.items[id] = NULL;
+    PUSH(free_ids, id);
+}
+
+static Term new_port(void) {
+    return PORT(new_id());
+}
+
+/* Creates an agent, copying its ports from `ports`. */
+static Term new_agent(uint32_t kind, uint64_t data, uint32_t arity, const Term *ports) {
+    Node node = {kind, arity, data, new_id(), NULL, NULL};
+    if (arity) {
+        node.ports = alloc_or_exit(arity * sizeof(Term));
+        memcpy(node.ports, ports, arity * sizeof(Term));
+    }
+
+    uint64_t index;
+    if (free_nodes.len) {
+        index = free_nodes.items[--free_nodes.len];
+        nodes.items[index] = node;
+    } else {
+        PUSH(nodes, node);
+        index = nodes.len - 1;
+    }
+
+    return AGENT(index);
+}
+
+static void free_node(uint64_t index) {
+    retire_id(nodes.items[index].id);
+    free(nodes.items[index].ports);
+    PUSH(free_nodes, index);
+}
+
+/* Removes the connection that a port is part of, returning the term on the
+ * other side of it. */
+static Term take_opposite(uint64_t port) {
+    Term opposite = wires.items[port];
+    if (!IS_AGENT(opposite) && INDEX(opposite) != port) {
+        wires.items[INDEX(opposite)] = EMPTY;
+    }
+
+    retire_id(port);
+    return opposite;
+}
+
+static void net_link(Term left, Term right) {
+    for (;;) {
+        if (IS_AGENT(left) && IS_AGENT(right)) {
+            Pair pair = {INDEX(left), INDEX(right)};
+            PUSH(redexes, pair);
+            return;
+        }
+
+        if (IS_AGENT(left)) {
+            Term swap = left;
+            left = right;
+            right = swap;
+            continue;
+        }
+
+        uint64_t port = INDEX(left);
+        if (left == right) {
+            /* a closed loop, which is kept around as `p = p` */
+            wires.items[port] = left;
+            return;
+        }
+
+        if (wires.items[port] != EMPTY) {
+            left = take_opposite(port);
+            continue;
+        }
+
+        if (!IS_AGENT(right) && wires.items[INDEX(right)] != EMPTY) {
+            right = take_opposite(INDEX(right));
+            continue;
+        }
+
+        wires.items[port] = right;
+        if (!IS_AGENT(right)) {
+            wires.items[INDEX(right)] = left;
+        }
+        return;
+    }
+}
+
+static int rewrite(uint64_t a, uint64_t b);
+
+static void reduce(void) {
+    while (redexes.len) {
+        Pair pair = redexes.items[--redexes.len];
+        uint64_t a = pair.a;
+        uint64_t b = pair.b;
+
+        /* rules expect their agents in the same order as their pattern */
+        if (nodes.items[a].kind > nodes.items[b].kind) {
+            a = pair.b;
+            b = pair.a;
+        }
+
+        if (!rewrite(a, b)) {
+            PUSH(stuck, pair);
+            continue;
+        }
+
+        interactions++;
+        free_node(a);
+        free_node(b);
+    }
+}
+
+static void print_name(const char *name) {
+    if (!name) {
+        return;
+    }
+
+    fputs(":\"", stdout);
+    for (; *name; name++) {
+        switch (*name) {
+        case '"':
+            fputs("\\\"", stdout);
+            break;
+        case '\\':
+            fputs("\\\\", stdout);
+            break;
+        case '\n':
+            fputs("\\n", stdout);
+            break;
+        default:
+            putchar(*name);
+        }
+    }
+    putchar('"');
+}
+
+static void print_term(Term term) {
+    if (!IS_AGENT(term)) {
+        printf("$%" PRIu64, INDEX(term));
+        print_name(port_names.items[INDEX(term)]);
+        return;
+    }
+
+    Node *node = &nodes.items[INDEX(term)];
+    fputs(KIND_NAMES[node->kind], stdout);
+    if (KIND_HAS_DATA[node->kind] || node->data) {
+        printf("{%" PRIu64 "}", node->data);
+    }
+    printf("#%" PRIu64, node->id);
+    print_name(node->name);
+
+    putchar('(');
+    for (uint32_t i = 0; i < node->arity; i++) {
+        if (i) {
+            fputs(", ", stdout);
+        }
+        print_term(node->ports[i]);
+    }
+    putchar(')');
+}
+
+/* Prints every connection left in the net, one per line. */
+static void print_net(void) {
+    for (uint64_t port = 0; port < wires.len; port++) {
+        Term opposite = wires.items[port];
+        if (opposite == EMPTY || (!IS_AGENT(opposite) && INDEX(opposite) < port)) {
+            continue;
+        }
+
+        print_term(PORT(port));
+        fputs(" = ", stdout);
+        print_term(opposite);
+        putchar('\n');
+    }
+
+    for (size_t i = 0; i < stuck.len; i++) {
+        print_term(AGENT(stuck.items[i].a));
+        fputs(" = ", stdout);
+        print_term(AGENT(stuck.items[i].b));
+        putchar('\n');
+    }
+}
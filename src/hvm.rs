@@ -0,0 +1,688 @@
+//! Importing and exporting nets in the textual notation of
+//! [HVM2](https://github.com/HigherOrderCO/HVM), so normal forms can be checked
+//! against other interaction combinator runtimes.
+//!
+//! A book is a list of definitions, each a root tree followed by any redexes:
+//!
+//! ```text
+//! @main = a
+//!   & @id ~ (b a)
+//!   & @id ~ b
+//! @id = (x x)
+//! ```
+//!
+//! Trees are written as:
+//!
+//! | HVM2      | Here                                                   |
+//! |-----------|--------------------------------------------------------|
+//! | `*`       | [`Eraser`](AgentKind::Eraser)                          |
+//! | `(a b)`   | [`Constructor`](AgentKind::Constructor)                |
+//! | `{a b}`   | [`Duplicator`](AgentKind::Duplicator)                  |
+//! | `?(a b)`  | [`Switch`](AgentKind::Switch)                          |
+//! | `#n`      | [`Number`](AgentKind::Number)                          |
+//! | `@name`   | a [`Reference`](AgentKind::Reference) to a definition  |
+//! | `x`       | a port, which shows up twice in a definition           |
+//!
+//! Every definition is added to the rulebook, and `@main` is also the net
+//! itself, with its root connected to a free port called `root`. Numeric
+//! operators, signed and floating point numbers, and labelled duplicators
+//! aren't supported.
+
+use std::{
+    fmt::Write as _,
+    io::{self, Write},
+};
+
+use rustc_hash::{FxHashMap as HashMap, FxHashSet as HashSet};
+
+use crate::{
+    net::{
+        connection::Connection,
+        id,
+        term::{Agent, AgentKind, Port, Term},
+        text::ParseError,
+    },
+    rule::{context::RewriteContext, definition::Definition, rulebook::Rulebook},
+};
+
+pub struct HvmBook {
+    /// The builtin rules, along with every definition in the book.
+    pub rulebook: Rulebook,
+    /// The net of `@main`, with IDs from [`HvmBook::ctx`]. It's empty if the
+    /// book doesn't have one.
+    pub connections: Vec<Connection>,
+    pub ctx: RewriteContext,
+}
+
+#[derive(Debug)]
+pub enum ExportError {
+    Io(io::Error),
+    /// The net or rulebook uses something that HVM2 doesn't have.
+    Unsupported(String),
+}
+
+impl From<io::Error> for ExportError {
+    fn from(value: io::Error) -> Self {
+        Self::Io(value)
+    }
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '-' | '/')
+}
+
+fn is_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(is_name_char)
+}
+
+struct Parser<'a> {
+    src: &'a str,
+    position: usize,
+    rulebook: Rulebook,
+    /// Ports in the definition being parsed by name, along with where each
+    /// one first showed up and how many times it has.
+    ports: HashMap<&'a str, (usize, usize, usize)>,
+    /// Where each definition was first referred to, along with its ID, so
+    /// that references to definitions that never show up can be reported.
+    references: HashMap<&'a str, (usize, usize)>,
+    next_id: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn error(&self, message: impl Into<String>) -> ParseError {
+        ParseError {
+            position: self.position,
+            message: message.into(),
+        }
+    }
+
+    fn rest(&self) -> &'a str {
+        &self.src[self.position..]
+    }
+
+    fn skip_whitespace(&mut self) {
+        loop {
+            let rest = self.rest();
+            self.position += rest.len() - rest.trim_start().len();
+
+            if !self.rest().starts_with("//") {
+                return;
+            }
+
+            let comment = self.rest().find('\n').unwrap_or(self.rest().len());
+            self.position += comment;
+        }
+    }
+
+    fn eat(&mut self, token: &str) -> bool {
+        self.skip_whitespace();
+
+        if self.rest().starts_with(token) {
+            self.position += token.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, token: &str) -> Result<(), ParseError> {
+        if self.eat(token) {
+            Ok(())
+        } else {
+            Err(self.error(format!("expected `{token}`")))
+        }
+    }
+
+    fn name(&mut self) -> Result<&'a str, ParseError> {
+        self.skip_whitespace();
+
+        let rest = self.rest();
+        let len = rest.find(|c| !is_name_char(c)).unwrap_or(rest.len());
+        if len == 0 {
+            return Err(self.error("expected a tree"));
+        }
+
+        self.position += len;
+        Ok(&rest[..len])
+    }
+
+    fn create_id(&mut self) -> usize {
+        self.next_id += 1;
+        self.next_id - 1
+    }
+
+    fn number(&mut self) -> Result<u64, ParseError> {
+        let rest = self.rest();
+        let len = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(rest.len());
+
+        if len == 0 || rest[len..].starts_with('.') {
+            return Err(self.error("only natural numbers are supported"));
+        }
+
+        let number = rest[..len]
+            .parse()
+            .map_err(|_| self.error("number is too big"))?;
+        self.position += len;
+
+        Ok(number)
+    }
+
+    fn pair(&mut self, kind: AgentKind, close: &str) -> Result<Term, ParseError> {
+        let a = self.tree()?;
+        let b = self.tree()?;
+        self.expect(close)?;
+
+        Ok(Term::Agent(Agent::new(self.create_id(), kind, [a, b])))
+    }
+
+    fn tree(&mut self) -> Result<Term, ParseError> {
+        self.skip_whitespace();
+
+        if self.eat("*") {
+            Ok(Term::Agent(Agent::new_eraser(self.create_id())))
+        } else if self.eat("(") {
+            self.pair(AgentKind::Constructor, ")")
+        } else if self.eat("{") {
+            self.pair(AgentKind::Duplicator, "}")
+        } else if self.eat("?") {
+            self.expect("(")?;
+            self.pair(AgentKind::Switch, ")")
+        } else if self.rest().starts_with('$') {
+            Err(self.error("numeric operators aren't supported"))
+        } else if self.eat("#") {
+            let number = self.number()?;
+            Ok(Term::Agent(Agent::new_number(self.create_id(), number)))
+        } else if self.eat("@") {
+            let position = self.position;
+            let name = self.name()?;
+            let kind @ AgentKind::Reference(id) = self.rulebook.reference(name) else {
+                unreachable!();
+            };
+            self.references.entry(name).or_insert((position, id));
+            Ok(Term::Agent(Agent::new(self.create_id(), kind, [])))
+        } else {
+            let position = self.position;
+            let name = self.name()?;
+
+            let id = match self.ports.get_mut(name) {
+                Some((id, _, count)) => {
+                    *count += 1;
+                    *id
+                }
+                None => {
+                    let id = self.create_id();
+                    self.ports.insert(name, (id, position, 1));
+                    id
+                }
+            };
+
+            Ok(Term::Port(Port::new(id).with_name(name)))
+        }
+    }
+
+    fn definition(&mut self) -> Result<(&'a str, Definition), ParseError> {
+        self.expect("@")?;
+        let position = self.position;
+        let name = self.name()?;
+
+        let AgentKind::Reference(id) = self.rulebook.reference(name) else {
+            unreachable!();
+        };
+        if let Some((_, Some(_))) = self.rulebook.definition(id) {
+            return Err(ParseError {
+                position,
+                message: format!("`@{name}` is defined twice"),
+            });
+        }
+
+        self.expect("=")?;
+        self.ports.clear();
+        let root = self.tree()?;
+
+        let mut connections = Vec::new();
+        while self.eat("&") {
+            let left = self.tree()?;
+            self.expect("~")?;
+            let right = self.tree()?;
+            connections.push(Connection(left, right));
+        }
+
+        if let Some((port, &(_, position, count))) = self
+            .ports
+            .iter()
+            .filter(|(_, (_, _, count))| *count != 2)
+            .min_by_key(|(_, (_, position, _))| *position)
+        {
+            return Err(ParseError {
+                position,
+                message: match count {
+                    1 => format!("`{port}` shows up once instead of twice"),
+                    _ => format!("`{port}` shows up {count} times instead of twice"),
+                },
+            });
+        }
+
+        Ok((name, Definition::new(root, connections)))
+    }
+}
+
+/// Parses an HVM2 book.
+pub fn parse_book(src: &str) -> Result<HvmBook, ParseError> {
+    let mut parser = Parser {
+        src,
+        position: 0,
+        rulebook: Rulebook::default(),
+        ports: HashMap::default(),
+        references: HashMap::default(),
+        next_id: 0,
+    };
+
+    let mut connections = Vec::new();
+    loop {
+        parser.skip_whitespace();
+        if parser.rest().is_empty() {
+            break;
+        }
+
+        let (name, definition) = parser.definition()?;
+        if name == "main" {
+            let root = Port::new(parser.create_id()).with_name("root");
+            connections.push(Connection(Term::Port(root), definition.root.clone()));
+            connections.extend(
+                definition
+                    .connections
+                    .iter()
+                    .map(|Connection(left, right)| Connection(left.clone(), right.clone())),
+            );
+        }
+
+        parser.rulebook.define(name, definition);
+    }
+
+    if let Some((name, &(position, _))) = parser
+        .references
+        .iter()
+        .filter(|(_, (_, id))| !matches!(parser.rulebook.definition(*id), Some((_, Some(_)))))
+        .min_by_key(|(_, (position, _))| *position)
+    {
+        return Err(ParseError {
+            position,
+            message: format!("`@{name}` is never defined"),
+        });
+    }
+
+    let (connections, id_alloc) = id::compact(connections);
+
+    Ok(HvmBook {
+        rulebook: parser.rulebook,
+        connections,
+        ctx: RewriteContext::new(id_alloc),
+    })
+}
+
+fn contains_port(term: &Term, port_id: usize) -> bool {
+    match term {
+        Term::Port(_) => *term.id() == port_id,
+        Term::Agent(agent) => agent.ports.iter().any(|port| contains_port(port, port_id)),
+    }
+}
+
+/// Writes one net as an HVM2 definition body. Wherever it can, a connection
+/// from a port to a term is written by putting the term in place of the
+/// port's other occurrence, since HVM2 nets are trees joined by variables.
+struct NetWriter<'a> {
+    rulebook: &'a Rulebook,
+    connections: &'a [Connection],
+    written: Vec<bool>,
+    /// Connections that each port is one side of.
+    sides: HashMap<usize, Vec<usize>>,
+    names: HashMap<usize, String>,
+    used_names: HashSet<String>,
+}
+
+impl<'a> NetWriter<'a> {
+    fn new(rulebook: &'a Rulebook, connections: &'a [Connection]) -> Self {
+        let mut sides = HashMap::<_, Vec<_>>::default();
+        for (index, Connection(left, right)) in connections.iter().enumerate() {
+            for side in [left, right] {
+                if let Term::Port(_) = side {
+                    sides.entry(*side.id()).or_default().push(index);
+                }
+            }
+        }
+
+        Self {
+            rulebook,
+            connections,
+            written: vec![false; connections.len()],
+            sides,
+            names: HashMap::default(),
+            used_names: HashSet::default(),
+        }
+    }
+
+    /// Takes the term on the other side of a connection to this port, if
+    /// there's one left that can be written in its place.
+    fn take_opposite(&mut self, port_id: usize) -> Option<&'a Term> {
+        for &index in self.sides.get(&port_id)? {
+            if self.written[index] {
+                continue;
+            }
+
+            let Connection(left, right) = &self.connections[index];
+            let opposite = match left {
+                Term::Port(_) if *left.id() == port_id => right,
+                _ => left,
+            };
+
+            // a port connected to a term it's inside of has nowhere to go
+            if contains_port(opposite, port_id) {
+                continue;
+            }
+
+            self.written[index] = true;
+            return Some(opposite);
+        }
+
+        None
+    }
+
+    fn name(&mut self, port: &Port, port_id: usize) -> &str {
+        if !self.names.contains_key(&port_id) {
+            let base = port.name.as_deref().filter(|name| is_name(name));
+
+            let mut name = base.map(str::to_string);
+            let mut suffix = 0;
+            while name
+                .as_ref()
+                .is_none_or(|name| self.used_names.contains(name))
+            {
+                name = Some(format!("{}{suffix}", base.unwrap_or("x")));
+                suffix += 1;
+            }
+            let name = name.unwrap();
+
+            self.used_names.insert(name.clone());
+            self.names.insert(port_id, name);
+        }
+
+        &self.names[&port_id]
+    }
+
+    fn tree(&mut self, out: &mut String, term: &Term) -> Result<(), ExportError> {
+        let agent = match term {
+            Term::Port(port) => {
+                match self.take_opposite(*term.id()) {
+                    Some(opposite) => self.tree(out, opposite)?,
+                    None => out.push_str(self.name(port, *term.id())),
+                }
+                return Ok(());
+            }
+            Term::Agent(agent) => agent,
+        };
+
+        let (open, close) = match agent.kind {
+            AgentKind::Eraser => {
+                out.push('*');
+                return Ok(());
+            }
+            AgentKind::Number => {
+                write!(out, "#{}", agent.data).unwrap();
+                return Ok(());
+            }
+            AgentKind::Reference(id) => {
+                let Some((name, _)) = self.rulebook.definition(id) else {
+                    return Err(ExportError::Unsupported(format!(
+                        "Reference[{id}] isn't in the rulebook"
+                    )));
+                };
+                if !is_name(name) {
+                    return Err(ExportError::Unsupported(format!(
+                        "`{name}` isn't a valid name for a definition"
+                    )));
+                }
+
+                write!(out, "@{name}").unwrap();
+                return Ok(());
+            }
+            AgentKind::Constructor => ("(", ")"),
+            AgentKind::Duplicator => ("{", "}"),
+            AgentKind::Switch => ("?(", ")"),
+            kind => {
+                return Err(ExportError::Unsupported(format!(
+                    "{kind:?} agents have no equivalent in HVM2"
+                )))
+            }
+        };
+
+        out.push_str(open);
+        for (index, port) in agent.ports.iter().enumerate() {
+            if index > 0 {
+                out.push(' ');
+            }
+            self.tree(out, port)?;
+        }
+        out.push_str(close);
+
+        Ok(())
+    }
+
+    fn net(mut self, out: &mut String, root: &Term) -> Result<(), ExportError> {
+        self.tree(out, root)?;
+
+        // active pairs go first, so that as many connections as possible are
+        // written inside of them
+        for active_pairs in [true, false] {
+            for (index, connection) in self.connections.iter().enumerate() {
+                if self.written[index] || connection.is_active_pair() != active_pairs {
+                    continue;
+                }
+                self.written[index] = true;
+
+                out.push_str("\n  & ");
+                self.tree(out, connection.left())?;
+                out.push_str(" ~ ");
+                self.tree(out, connection.right())?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Writes a net and the definitions in its rulebook as an HVM2 book. The net
+/// becomes `@main`, in place of any definition by that name, and its one free
+/// port is the root. A net without any free ports gets an eraser as its root.
+///
+/// Only references and the agents that HVM2 has can be exported. Rules that
+/// aren't builtin are left out, since HVM2 has no way to write them.
+pub fn write_book(
+    mut writer: impl Write,
+    connections: &[Connection],
+    rulebook: &Rulebook,
+) -> Result<(), ExportError> {
+    fn count_ports<'a>(term: &'a Term, counts: &mut HashMap<usize, (&'a Port, usize)>) {
+        match term {
+            Term::Port(port) => counts.entry(*term.id()).or_insert((port, 0)).1 += 1,
+            Term::Agent(agent) => agent
+                .ports
+                .iter()
+                .for_each(|port| count_ports(port, counts)),
+        }
+    }
+
+    let mut counts = HashMap::default();
+    for Connection(left, right) in connections {
+        count_ports(left, &mut counts);
+        count_ports(right, &mut counts);
+    }
+
+    let mut free_ports: Vec<_> = counts
+        .into_values()
+        .filter_map(|(port, count)| (count == 1).then_some(port))
+        .collect();
+    let root = match free_ports.pop() {
+        None => Term::Agent(Agent::new_eraser(0)),
+        Some(port) if free_ports.is_empty() => Term::Port(port.clone()),
+        Some(_) => {
+            return Err(ExportError::Unsupported(format!(
+                "HVM2 nets have one free port, but this one has {}",
+                free_ports.len() + 1
+            )))
+        }
+    };
+
+    let mut out = String::from("@main = ");
+    NetWriter::new(rulebook, connections).net(&mut out, &root)?;
+    out.push('\n');
+
+    let mut id = 0;
+    while let Some((name, definition)) = rulebook.definition(id) {
+        id += 1;
+
+        let Some(definition) = definition.filter(|_| name != "main") else {
+            continue;
+        };
+        if !is_name(name) {
+            return Err(ExportError::Unsupported(format!(
+                "`{name}` isn't a valid name for a definition"
+            )));
+        }
+
+        write!(out, "@{name} = ").unwrap();
+        NetWriter::new(rulebook, &definition.connections).net(&mut out, &definition.root)?;
+        out.push('\n');
+    }
+
+    writer.write_all(out.as_bytes())?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{net::canonical::CanonicalNet, runtime::Runtime};
+
+    const IDENTITY: &str = "@main = a\n  & @id ~ (b a)\n  & @id ~ b\n@id = (x x)\n";
+
+    fn parse_error(src: &str) -> ParseError {
+        match parse_book(src) {
+            Ok(_) => panic!("expected an error"),
+            Err(error) => error,
+        }
+    }
+
+    fn normal_form(book: HvmBook) -> (CanonicalNet, usize) {
+        let mut runtime = Runtime::new(book.connections, book.rulebook, book.ctx);
+        runtime.reduce();
+        let interactions = runtime.interactions();
+        let connections = runtime
+            .normalize()
+            .into_iter()
+            .map(|(left, right)| Connection(left, right));
+
+        (CanonicalNet::new(connections), interactions)
+    }
+
+    #[test]
+    fn reduces_the_documented_book() {
+        let (net, interactions) = normal_form(parse_book(IDENTITY).unwrap());
+        let (expected, _) = normal_form(parse_book("@main = @id\n@id = (x x)").unwrap());
+
+        assert!(net == expected);
+        assert_eq!(interactions, 2);
+    }
+
+    #[test]
+    fn round_trips_books() {
+        let src =
+            "@main = r & @c2 ~ ((@c2 r) *)\n@c2 = ({(a b) (b c)} (a c))\n@tree = ?(#0 ({a a} *))\n";
+        let book = parse_book(src).unwrap();
+
+        let mut written = Vec::new();
+        write_book(&mut written, &book.connections, &book.rulebook).unwrap();
+        let written = String::from_utf8(written).unwrap();
+        let reparsed = parse_book(&written).unwrap();
+
+        let original = CanonicalNet::new(
+            book.connections
+                .iter()
+                .map(|Connection(l, r)| Connection(l.clone(), r.clone())),
+        );
+        let copy = CanonicalNet::new(
+            reparsed
+                .connections
+                .iter()
+                .map(|Connection(l, r)| Connection(l.clone(), r.clone())),
+        );
+        assert!(original == copy, "{written}");
+
+        let (original, original_interactions) = normal_form(book);
+        let (copy, copy_interactions) = normal_form(reparsed);
+        assert!(original == copy);
+        assert_eq!(original_interactions, copy_interactions);
+    }
+
+    #[test]
+    fn rejects_malformed_definitions() {
+        assert_eq!(
+            parse_error("@main = (a b)").message,
+            "`a` shows up once instead of twice"
+        );
+        assert_eq!(
+            parse_error("@main = (a a)\n@main = *").message,
+            "`@main` is defined twice"
+        );
+        assert_eq!(
+            parse_error("@main = $(a a)").message,
+            "numeric operators aren't supported"
+        );
+    }
+
+    #[test]
+    fn refuses_to_export_dynamic_agents() {
+        let mut rulebook = Rulebook::default();
+        let kind = rulebook.declare_agent(0, 0);
+        let connections = [Connection(
+            Term::Port(Port::new(0)),
+            Term::Agent(Agent::new(1, kind, [])),
+        )];
+
+        let result = write_book(Vec::new(), &connections, &rulebook);
+        assert!(matches!(result, Err(ExportError::Unsupported(_))));
+    }
+
+    #[test]
+    fn rejects_undefined_references() {
+        let Err(error) = parse_book("@main = (a b) & @f ~ (a b)\n@g = @h") else {
+            panic!("expected an error");
+        };
+
+        assert_eq!(error.message, "`@f` is never defined");
+        assert_eq!(error.position, 17);
+    }
+
+    #[test]
+    fn duplicating_a_recursive_reference_copies_it() {
+        // expanding `@inf` before copying it would never finish
+        let book = parse_book("@main = (a b) & {a b} ~ @inf\n@inf = (* @inf)").unwrap();
+        let runtime = Runtime::new(book.connections, book.rulebook, book.ctx);
+
+        let mut kinds: Vec<_> = runtime
+            .normalize()
+            .into_iter()
+            .flat_map(|(left, right)| [left, right])
+            .filter_map(|term| match term {
+                Term::Agent(agent) => Some(agent.kind),
+                Term::Port(_) => None,
+            })
+            .collect();
+        kinds.sort();
+
+        let inf = AgentKind::Reference(1);
+        assert_eq!(kinds, [AgentKind::Constructor, inf, inf]);
+    }
+}
@@ -0,0 +1,423 @@
+//! Importing programs written for [Inpla](https://github.com/inpla/inpla), so
+//! its benchmarks can be run here and their interaction counts compared.
+//!
+//! A program is a list of rules and nets, each ending with `;`:
+//!
+//! ```text
+//! // Ackermann on unary numbers
+//! Ack(r, y) >< Z => r ~ S(y);
+//! Ack(r, y) >< S(x) => Ackm(r, x) ~ y;
+//! Ackm(r, x) >< Z => Ack(r, S(Z)) ~ x;
+//! Ackm(r, x) >< S(y) => Dup(x1, x2) ~ x, Ack(r, w) ~ x1, Ack(w, y) ~ S(x2);
+//!
+//! Ack(r, S(S(S(Z)))) ~ S(S(Z));
+//! r;
+//! ```
+//!
+//! Agents start with an uppercase letter, and each one is given a declared
+//! [`Dynamic`](AgentKind::Dynamic) kind, so they can be erased and duplicated
+//! without rules of their own. `Eraser` and `Dup` are Inpla's built-in agents,
+//! and map onto [`Eraser`](AgentKind::Eraser) and
+//! [`Duplicator`](AgentKind::Duplicator). Names start with a lowercase letter,
+//! and a name that only shows up once in the nets is part of the interface.
+//! A statement that's just names, like `r;` above, asks Inpla to print them,
+//! and they're kept as [`InplaProgram::interface`].
+//!
+//! Only the pure interaction net part of Inpla is supported: integers,
+//! attributes, conditional rules, and the list and tuple notation aren't.
+
+use rustc_hash::FxHashMap as HashMap;
+
+use crate::{
+    net::{
+        connection::Connection,
+        id,
+        term::{Agent, AgentKind, Port, Term},
+        text::ParseError,
+    },
+    rule::{
+        context::RewriteContext, parse::describe_template_error, rulebook::Rulebook,
+        template::RuleTemplate,
+    },
+};
+
+pub struct InplaProgram {
+    pub rulebook: Rulebook,
+    /// The nets, with IDs from [`InplaProgram::ctx`].
+    pub connections: Vec<Connection>,
+    pub ctx: RewriteContext,
+    /// The name of each agent, by the ID of its `Dynamic` kind.
+    pub agents: Vec<String>,
+    /// Names that the program asks to print, in order.
+    pub interface: Vec<String>,
+}
+
+impl InplaProgram {
+    /// The name of a kind of agent, as it was in the program.
+    pub fn agent_name(&self, kind: AgentKind) -> Option<&str> {
+        match kind {
+            AgentKind::Dynamic(id) => self.agents.get(id).map(String::as_str),
+            AgentKind::Eraser => Some("Eraser"),
+            AgentKind::Duplicator => Some("Dup"),
+            _ => None,
+        }
+    }
+}
+
+struct Parser<'a> {
+    src: &'a str,
+    position: usize,
+    rulebook: Rulebook,
+    /// Kind and arity of each agent, by name.
+    agents: HashMap<&'a str, (AgentKind, usize)>,
+    agent_names: Vec<String>,
+    /// Names in the rule or net being parsed, and the ports they stand for.
+    names: HashMap<&'a str, usize>,
+    next_id: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn error(&self, message: impl Into<String>) -> ParseError {
+        ParseError {
+            position: self.position,
+            message: message.into(),
+        }
+    }
+
+    fn rest(&self) -> &'a str {
+        &self.src[self.position..]
+    }
+
+    fn skip_whitespace(&mut self) -> Result<(), ParseError> {
+        loop {
+            let rest = self.rest();
+            self.position += rest.len() - rest.trim_start().len();
+
+            if self.rest().starts_with("//") {
+                let comment = self.rest().find('\n').unwrap_or(self.rest().len());
+                self.position += comment;
+            } else if self.rest().starts_with("/*") {
+                let Some(comment) = self.rest().find("*/") else {
+                    return Err(self.error("unterminated comment"));
+                };
+                self.position += comment + 2;
+            } else {
+                return Ok(());
+            }
+        }
+    }
+
+    fn eat(&mut self, token: &str) -> Result<bool, ParseError> {
+        self.skip_whitespace()?;
+
+        if self.rest().starts_with(token) {
+            self.position += token.len();
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    fn expect(&mut self, token: &str) -> Result<(), ParseError> {
+        if self.eat(token)? {
+            Ok(())
+        } else {
+            Err(self.error(format!("expected `{token}`")))
+        }
+    }
+
+    fn ident(&mut self) -> Result<&'a str, ParseError> {
+        self.skip_whitespace()?;
+
+        let rest = self.rest();
+        if rest.starts_with(|c: char| c.is_ascii_digit()) {
+            return Err(self.error("integers aren't supported"));
+        }
+        if rest.starts_with(['[', '(']) {
+            return Err(self.error("lists and tuples aren't supported"));
+        }
+
+        let len = rest
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+            .unwrap_or(rest.len());
+        if len == 0 {
+            return Err(self.error("expected an agent or a name"));
+        }
+
+        let ident = &rest[..len];
+        if ident == "int" {
+            return Err(self.error("attributes aren't supported"));
+        }
+
+        self.position += len;
+        Ok(ident)
+    }
+
+    fn create_id(&mut self) -> usize {
+        self.next_id += 1;
+        self.next_id - 1
+    }
+
+    /// Gets the kind of an agent, declaring it if this is the first time it's
+    /// been used.
+    fn kind(
+        &mut self,
+        name: &'a str,
+        arity: usize,
+        position: usize,
+    ) -> Result<AgentKind, ParseError> {
+        let (kind, expected) = match (name, self.agents.get(name)) {
+            ("Eraser", _) => (AgentKind::Eraser, 0),
+            ("Dup", _) => (AgentKind::Duplicator, 2),
+            (_, Some(&known)) => known,
+            (_, None) => {
+                let kind = self.rulebook.declare_agent(self.agent_names.len(), arity);
+                self.agent_names.push(name.to_string());
+                self.agents.insert(name, (kind, arity));
+                (kind, arity)
+            }
+        };
+
+        if arity != expected {
+            let ports = if expected == 1 { "port" } else { "ports" };
+            return Err(ParseError {
+                position,
+                message: format!("`{name}` takes {expected} {ports}, not {arity}"),
+            });
+        }
+
+        Ok(kind)
+    }
+
+    fn term(&mut self) -> Result<Term, ParseError> {
+        self.skip_whitespace()?;
+        let position = self.position;
+        let name = self.ident()?;
+
+        if !name.starts_with(|c: char| c.is_ascii_uppercase()) {
+            let id = match self.names.get(name) {
+                Some(&id) => id,
+                None => {
+                    let id = self.create_id();
+                    self.names.insert(name, id);
+                    id
+                }
+            };
+
+            return Ok(Term::Port(Port::new(id).with_name(name)));
+        }
+
+        let mut ports = Vec::new();
+        if self.eat("(")? && !self.eat(")")? {
+            loop {
+                ports.push(self.term()?);
+                if self.eat(")")? {
+                    break;
+                }
+                self.expect(",")?;
+            }
+        }
+
+        let kind = self.kind(name, ports.len(), position)?;
+        Ok(Term::Agent(Agent::new(self.create_id(), kind, ports)))
+    }
+
+    fn connection(&mut self) -> Result<Connection, ParseError> {
+        let left = self.term()?;
+        self.expect("~")?;
+        let right = self.term()?;
+
+        Ok(Connection(left, right))
+    }
+
+    /// Parses connections separated by commas, up to the `;` at the end of
+    /// the statement.
+    fn connections(&mut self) -> Result<Vec<Connection>, ParseError> {
+        let mut connections = Vec::new();
+        if self.eat(";")? {
+            return Ok(connections);
+        }
+
+        loop {
+            connections.push(self.connection()?);
+            if self.eat(";")? {
+                return Ok(connections);
+            }
+            self.expect(",")?;
+        }
+    }
+
+    /// Parses the rest of a rule, after the `><`. The rule started at
+    /// `position`.
+    fn rule(&mut self, left: Term, position: usize) -> Result<(), ParseError> {
+        let right = self.term()?;
+        self.expect("=>")?;
+        let connections = self.connections()?;
+
+        let (Term::Agent(left), Term::Agent(right)) = (left, right) else {
+            return Err(ParseError {
+                position,
+                message: "both sides of `><` should be agents".to_string(),
+            });
+        };
+
+        self.rulebook
+            .add_template(RuleTemplate::new(left, right, connections))
+            .map_err(|error| ParseError {
+                position,
+                message: describe_template_error(error, &self.names),
+            })?;
+
+        Ok(())
+    }
+
+    /// Parses a statement that's just names, after the first one.
+    fn interface(&mut self, first: &'a str, interface: &mut Vec<String>) -> Result<(), ParseError> {
+        interface.push(first.to_string());
+
+        while !self.eat(";")? {
+            self.eat(",")?;
+            interface.push(self.ident()?.to_string());
+        }
+
+        Ok(())
+    }
+}
+
+/// Parses an Inpla program.
+pub fn parse_program(src: &str) -> Result<InplaProgram, ParseError> {
+    let mut parser = Parser {
+        src,
+        position: 0,
+        rulebook: Rulebook::default(),
+        agents: HashMap::default(),
+        agent_names: Vec::new(),
+        names: HashMap::default(),
+        next_id: 0,
+    };
+
+    let mut connections = Vec::new();
+    let mut interface = Vec::new();
+    // names in nets are shared between statements, but not with rules
+    let mut net_names = HashMap::default();
+
+    loop {
+        parser.skip_whitespace()?;
+        if parser.rest().is_empty() || parser.eat("exit")? {
+            break;
+        }
+
+        let start = parser.position;
+        let first = parser.ident()?;
+        parser.position = start;
+
+        if first.starts_with(|c: char| c.is_ascii_uppercase()) {
+            parser.names.clear();
+            let left = parser.term()?;
+
+            if parser.eat("><")? {
+                parser.rule(left, start)?;
+                continue;
+            }
+
+            parser.position = start;
+        } else if !parser.rest()[first.len()..].trim_start().starts_with('~') {
+            parser.position += first.len();
+            parser.interface(first, &mut interface)?;
+            continue;
+        }
+
+        std::mem::swap(&mut parser.names, &mut net_names);
+        let statement = parser.connections();
+        std::mem::swap(&mut parser.names, &mut net_names);
+        connections.extend(statement?);
+    }
+
+    let (connections, id_alloc) = id::compact(connections);
+
+    Ok(InplaProgram {
+        rulebook: parser.rulebook,
+        connections,
+        ctx: RewriteContext::new(id_alloc),
+        agents: parser.agent_names,
+        interface,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::{readback::Node, Runtime};
+
+    const ACKERMANN: &str = "
+        // Ackermann on unary numbers
+        Ack(r, y) >< Z => r ~ S(y);
+        Ack(r, y) >< S(x) => Ackm(r, x) ~ y;
+        Ackm(r, x) >< Z => Ack(r, S(Z)) ~ x;
+        Ackm(r, x) >< S(y) => Dup(x1, x2) ~ x, Ack(r, w) ~ x1, Ack(w, y) ~ S(x2);
+
+        Ack(r, S(S(S(Z)))) ~ S(S(Z));
+        r;
+    ";
+
+    fn parse_error(src: &str) -> ParseError {
+        match parse_program(src) {
+            Ok(_) => panic!("expected an error"),
+            Err(error) => error,
+        }
+    }
+
+    #[test]
+    fn runs_ackermann() {
+        let program = parse_program(ACKERMANN).unwrap();
+        assert_eq!(program.interface, ["r"]);
+
+        let kind = |name: &str| {
+            let id = program.agents.iter().position(|agent| agent == name);
+            AgentKind::Dynamic(id.unwrap())
+        };
+        let (s, z) = (kind("S"), kind("Z"));
+        assert_eq!(program.agent_name(s), Some("S"));
+
+        let mut runtime = Runtime::new(program.connections, program.rulebook, program.ctx);
+        let result: Vec<_> = runtime
+            .read_back("r")
+            .unwrap()
+            .map(|node| match node {
+                Node::Agent { kind, .. } => kind,
+                node => panic!("expected an agent, not {node:?}"),
+            })
+            .collect();
+
+        // the principal port is the first argument, so this is A(2, 3) = 9
+        let mut expected = vec![s; 9];
+        expected.push(z);
+        assert!(result == expected);
+    }
+
+    #[test]
+    fn maps_builtin_agents() {
+        let program = parse_program("Dup(a, b) ~ Eraser; a ~ b;").unwrap();
+
+        let Connection(Term::Agent(left), Term::Agent(right)) = &program.connections[0] else {
+            panic!("expected an active pair");
+        };
+        assert!(left.kind == AgentKind::Duplicator && right.kind == AgentKind::Eraser);
+        assert!(program.agents.is_empty());
+    }
+
+    #[test]
+    fn rejects_rules_with_unused_ports() {
+        let error = parse_error("A(r) >< B(x) => r ~ C;");
+
+        assert_eq!(error.message, "`x` shows up once instead of twice");
+        assert_eq!(error.position, 0);
+    }
+
+    #[test]
+    fn rejects_agents_used_with_different_arities() {
+        let error = parse_error("A(x) ~ B; A(x, y) ~ y;");
+
+        assert_eq!(error.line_column("A(x) ~ B; A(x, y) ~ y;"), (1, 11));
+    }
+}
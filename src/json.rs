@@ -0,0 +1,225 @@
+//! JSON import and export of nets, for tools that aren't written in Rust.
+//!
+//! A net is an object with a list of connections, each of which is a pair of
+//! terms:
+//!
+//! ```json
+//! {
+//!   "connections": [
+//!     [
+//!       { "type": "Port", "name": "ed_port", "id": 4 },
+//!       {
+//!         "type": "Agent",
+//!         "name": "e",
+//!         "id": 6,
+//!         "kind": "Constructor",
+//!         "ports": [
+//!           { "type": "Port", "name": "ed_port", "id": 4 },
+//!           { "type": "Port", "name": "out_port", "id": 5 }
+//!         ]
+//!       }
+//!     ]
+//!   ]
+//! }
+//! ```
+//!
+//! - `type` is either `"Agent"` or `"Port"`.
+//! - `id` is unique to each agent. The two occurrences of a port share theirs,
+//!   which is how wires are represented.
+//! - `name` is optional.
+//! - `kind` is `"Eraser"`, `"Duplicator"`, `"Constructor"`, `"Number"`,
+//!   `"Switch"`, `{ "Dynamic": id }`, `{ "Reference": id }`,
+//!   `{ "BinaryOp": op }` or `{ "PartialOp": op }`, where `op` is one of
+//!   `"Add"`, `"Sub"`, `"Mul"`, `"Div"`, `"Eq"`, `"Ne"`, `"Lt"`, `"Le"`,
+//!   `"Gt"` or `"Ge"`.
+//! - `data` is the agent's payload, like the value of a number. It's left out
+//!   when it's zero.
+//! - `ports` are an agent's auxiliary ports in order. Its principal port is the
+//!   one that it's connected by.
+//!
+//! A rulebook is exported as the list of patterns it has rules for:
+//!
+//! ```json
+//! {
+//!   "rules": [
+//!     { "pattern": ["Constructor", "Constructor"], "rule": { "Builtin": "CtrCtr" } },
+//!     { "pattern": ["Eraser", { "Dynamic": 0 }], "rule": "Dynamic" }
+//!   ]
+//! }
+//! ```
+//!
+//! Template rules are exported as `{ "Template": { "left": agent, "right":
+//! agent, "connections": [...] } }`, with the agents and connections in the
+//! same format as nets. Dynamic and native rules are code, so there's nothing
+//! to export but the fact that they exist (`"Dynamic"` or `"Native"`), and
+//! rulebooks can't be imported.
+
+use std::io::{Read, Write};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    net::connection::Connection,
+    rule::{
+        builtin::Builtin,
+        rulebook::{ActivePairPattern, Rulebook},
+        template::RuleTemplate,
+        Rule,
+    },
+};
+
+#[derive(Serialize)]
+struct NetRef<'a> {
+    connections: &'a [Connection],
+}
+
+#[derive(Deserialize)]
+struct Net {
+    connections: Vec<Connection>,
+}
+
+#[derive(Serialize)]
+enum RuleRef<'a> {
+    Builtin(&'a Builtin),
+    Dynamic,
+    Native,
+    Template(&'a RuleTemplate),
+}
+
+#[derive(Serialize)]
+struct RuleEntry<'a> {
+    pattern: &'a ActivePairPattern,
+    rule: RuleRef<'a>,
+}
+
+#[derive(Serialize)]
+struct RulebookRef<'a> {
+    rules: Vec<RuleEntry<'a>>,
+}
+
+pub fn write_net(writer: impl Write, connections: &[Connection]) -> serde_json::Result<()> {
+    serde_json::to_writer_pretty(writer, &NetRef { connections })
+}
+
+pub fn read_net(reader: impl Read) -> serde_json::Result<Vec<Connection>> {
+    let net: Net = serde_json::from_reader(reader)?;
+    Ok(net.connections)
+}
+
+pub fn write_rulebook(writer: impl Write, rulebook: &Rulebook) -> serde_json::Result<()> {
+    let rules = rulebook
+        .rules()
+        .map(|(pattern, rule)| RuleEntry {
+            pattern,
+            rule: match rule {
+                Rule::Builtin(builtin) => RuleRef::Builtin(builtin),
+                Rule::Dynamic(_) => RuleRef::Dynamic,
+                Rule::Native(_) => RuleRef::Native,
+                Rule::Template(rule) => RuleRef::Template(rule.template()),
+            },
+        })
+        .collect();
+
+    serde_json::to_writer_pretty(writer, &RulebookRef { rules })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::net::{
+        term::Operator,
+        text::{parse_connection, write_connection},
+    };
+
+    fn text(connections: &[Connection]) -> Vec<String> {
+        connections
+            .iter()
+            .map(|connection| {
+                let mut text = String::new();
+                write_connection(&mut text, connection.left(), connection.right()).unwrap();
+                text
+            })
+            .collect()
+    }
+
+    #[test]
+    fn round_trips_every_kind() {
+        let mut sources = vec![
+            r#"$0:"out" = Constructor#4:"b"(Constructor#2(Eraser#3(), $1), $0:"out")"#.to_string(),
+            "Constructor#5($6, $7) = Duplicator#8($6, $7)".to_string(),
+            "Dynamic[3]#9($10) = Number{42}#11()".to_string(),
+            "Switch#12($13, $14) = Reference[300]#15()".to_string(),
+            "$1 = $10".to_string(),
+        ];
+        for (index, op) in Operator::ALL.into_iter().enumerate() {
+            sources.push(format!(
+                "BinaryOp[{op:?}]#{}($0, $0) = PartialOp[{op:?}]{{7}}#{}($0)",
+                100 + index,
+                200 + index
+            ));
+        }
+        let connections: Vec<_> = sources
+            .iter()
+            .map(|src| parse_connection(src).unwrap())
+            .collect();
+
+        let mut json = Vec::new();
+        write_net(&mut json, &connections).unwrap();
+
+        assert_eq!(text(&read_net(json.as_slice()).unwrap()), sources);
+    }
+
+    #[test]
+    fn reads_the_documented_format() {
+        let json = r#"{
+          "connections": [
+            [
+              { "type": "Port", "name": "ed_port", "id": 4 },
+              {
+                "type": "Agent",
+                "name": "e",
+                "id": 6,
+                "kind": "Constructor",
+                "ports": [
+                  { "type": "Port", "name": "ed_port", "id": 4 },
+                  { "type": "Port", "name": "out_port", "id": 5 }
+                ]
+              }
+            ],
+            [
+              { "type": "Agent", "id": 7, "kind": { "PartialOp": "Sub" }, "data": 3, "ports": [] },
+              { "type": "Agent", "id": 8, "kind": { "Reference": 2 }, "ports": [] }
+            ]
+          ]
+        }"#;
+
+        assert_eq!(
+            text(&read_net(json.as_bytes()).unwrap()),
+            [
+                r#"$4:"ed_port" = Constructor#6:"e"($4:"ed_port", $5:"out_port")"#,
+                "PartialOp[Sub]{3}#7() = Reference[2]#8()",
+            ]
+        );
+    }
+
+    #[test]
+    fn writes_every_kind_of_rule() {
+        let mut rulebook = Rulebook::default();
+        rulebook.declare_agent(0, 1);
+
+        let mut json = Vec::new();
+        write_rulebook(&mut json, &rulebook).unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&json).unwrap();
+
+        let rules = json["rules"].as_array().unwrap();
+        assert_eq!(rules.len(), rulebook.rules().count());
+        assert!(rules.contains(&serde_json::json!({
+            "pattern": ["Constructor", "Constructor"],
+            "rule": { "Builtin": "CtrCtr" },
+        })));
+        assert!(rules.contains(&serde_json::json!({
+            "pattern": ["Eraser", { "Dynamic": 0 }],
+            "rule": { "Builtin": { "DynEra": { "id": 0, "arity": 1 } } },
+        })));
+    }
+}
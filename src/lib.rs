@@ -0,0 +1,10 @@
+pub mod ast;
+pub mod c;
+pub mod hvm;
+pub mod inpla;
+#[cfg(feature = "serde")]
+pub mod json;
+pub mod map;
+pub mod net;
+pub mod rule;
+pub mod runtime;
@@ -61,6 +61,10 @@ where
         self.do_assertions();
         self.left_to_pair.len()
     }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
 }
 
 impl<L, R> ConnectionMap<L, R>
@@ -74,18 +78,23 @@ where
         let left_key = left.as_key();
         let right_key = right.as_key();
 
-        if let Some(old) = self
+        if self
             .left_to_right
             .insert(left_key.clone(), right_key.clone())
+            .is_some()
         {
             return Err(InsertAlreadyExistsError::Left);
         }
 
-        if let Some(old) = self.right_to_left.insert(right_key, left_key.clone()) {
+        if self
+            .right_to_left
+            .insert(right_key, left_key.clone())
+            .is_some()
+        {
             return Err(InsertAlreadyExistsError::Right);
         }
 
-        if let Some(old) = self.left_to_pair.insert(left_key, (left, right)) {
+        if self.left_to_pair.insert(left_key, (left, right)).is_some() {
             panic!("Map invariant broken!");
         }
 
@@ -165,7 +174,7 @@ where
     pub fn dump(&self) {
         println!("====DUMP====");
 
-        if self.len() == 0 {
+        if self.is_empty() {
             println!("(empty)");
             println!("============");
         } else {
@@ -0,0 +1,561 @@
+//! A compact binary format for nets, for loading nets that are too big to go
+//! through the text format.
+//!
+//! A stream starts with the magic bytes `INET` and a version byte, and is then
+//! a sequence of records, each starting with a tag byte:
+//!
+//! - `KIND`: defines the next entry in the kind table, as a kind tag byte, a
+//!   varint for the dynamic or definition ID (dynamic and reference kinds
+//!   only) or an operator byte (operator kinds only), and a varint arity.
+//! - `CONNECTION`: two terms.
+//! - `END`: the end of the net.
+//!
+//! A term starts with a varint that is its slot shifted left twice, with the
+//! lowest bit set if the term has a name and the next one set if it has data.
+//! Slot zero is a port, and any other slot is an agent with the kind table
+//! entry one below it. Then comes the varint ID, the name as a varint length
+//! and UTF-8 bytes if there is one, the varint data if there is any, and for
+//! agents, each port in turn. Ports are linked by sharing an ID, like
+//! everywhere else.
+//!
+//! Integers are LEB128 varints, so small IDs take a single byte.
+//!
+//! Terms are read and written without recursion, so deeply nested nets don't
+//! overflow the stack.
+
+use std::io::{self, BufReader, BufWriter, Read, Write};
+
+use rustc_hash::FxHashMap as HashMap;
+
+use super::{
+    connection::Connection,
+    term::{Agent, AgentKind, Operator, Port, Term},
+};
+
+const MAGIC: &[u8; 4] = b"INET";
+const VERSION: u8 = 2;
+
+const END: u8 = 0;
+const KIND: u8 = 1;
+const CONNECTION: u8 = 2;
+
+const ERASER: u8 = 0;
+const DUPLICATOR: u8 = 1;
+const CONSTRUCTOR: u8 = 2;
+const DYNAMIC: u8 = 3;
+const NUMBER: u8 = 4;
+const BINARY_OP: u8 = 5;
+const PARTIAL_OP: u8 = 6;
+const SWITCH: u8 = 7;
+const REFERENCE: u8 = 8;
+
+const HAS_NAME: usize = 0b01;
+const HAS_DATA: usize = 0b10;
+
+fn invalid_data(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+fn write_varint(writer: &mut impl Write, mut value: u64) -> io::Result<()> {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+
+        if value == 0 {
+            return writer.write_all(&[byte]);
+        }
+
+        writer.write_all(&[byte | 0x80])?;
+    }
+}
+
+fn read_byte(reader: &mut impl Read) -> io::Result<u8> {
+    let mut byte = [0];
+    reader.read_exact(&mut byte)?;
+    Ok(byte[0])
+}
+
+fn read_varint64(reader: &mut impl Read) -> io::Result<u64> {
+    let mut value = 0u64;
+    let mut shift = 0;
+
+    loop {
+        let byte = read_byte(reader)?;
+
+        if shift >= u64::BITS {
+            return Err(invalid_data("varint is too long"));
+        }
+        // only the lowest bit of the tenth byte still fits
+        if shift == 63 && byte & 0x7f > 1 {
+            return Err(invalid_data("varint is too large"));
+        }
+        value |= ((byte & 0x7f) as u64) << shift;
+        shift += 7;
+
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+}
+
+fn read_varint(reader: &mut impl Read) -> io::Result<usize> {
+    read_varint64(reader)?
+        .try_into()
+        .map_err(|_| invalid_data("varint is too large"))
+}
+
+fn operator_tag(operator: Operator) -> u8 {
+    Operator::ALL.iter().position(|op| *op == operator).unwrap() as u8
+}
+
+fn read_operator(reader: &mut impl Read) -> io::Result<Operator> {
+    let tag = read_byte(reader)?;
+    Operator::ALL
+        .get(tag as usize)
+        .copied()
+        .ok_or_else(|| invalid_data(format!("unknown operator tag {tag}")))
+}
+
+/// Writes connections to a stream, one at a time. Writes are buffered, so
+/// nothing is guaranteed to reach the stream until [`NetWriter::finish`].
+pub struct NetWriter<W: Write> {
+    writer: BufWriter<W>,
+    /// Slots of the kinds that have been written so far.
+    kinds: HashMap<(AgentKind, usize), usize>,
+}
+
+impl<W: Write> NetWriter<W> {
+    pub fn new(writer: W) -> io::Result<Self> {
+        let mut writer = BufWriter::new(writer);
+        writer.write_all(MAGIC)?;
+        writer.write_all(&[VERSION])?;
+
+        Ok(Self {
+            writer,
+            kinds: HashMap::default(),
+        })
+    }
+
+    /// Writes a kind table entry for every kind in the term that doesn't
+    /// have one yet.
+    fn define_kinds(&mut self, term: &Term) -> io::Result<()> {
+        let mut stack = vec![term];
+
+        while let Some(term) = stack.pop() {
+            let Term::Agent(agent) = term else {
+                continue;
+            };
+            stack.extend(agent.ports.iter().rev());
+
+            let key = (agent.kind, agent.ports.len());
+            if self.kinds.contains_key(&key) {
+                continue;
+            }
+
+            self.writer.write_all(&[KIND])?;
+            match agent.kind {
+                AgentKind::Eraser => self.writer.write_all(&[ERASER])?,
+                AgentKind::Duplicator => self.writer.write_all(&[DUPLICATOR])?,
+                AgentKind::Constructor => self.writer.write_all(&[CONSTRUCTOR])?,
+                AgentKind::Dynamic(id) => {
+                    self.writer.write_all(&[DYNAMIC])?;
+                    write_varint(&mut self.writer, id as u64)?;
+                }
+                AgentKind::Number => self.writer.write_all(&[NUMBER])?,
+                AgentKind::BinaryOp(op) => self.writer.write_all(&[BINARY_OP, operator_tag(op)])?,
+                AgentKind::PartialOp(op) => {
+                    self.writer.write_all(&[PARTIAL_OP, operator_tag(op)])?
+                }
+                AgentKind::Switch => self.writer.write_all(&[SWITCH])?,
+                AgentKind::Reference(id) => {
+                    self.writer.write_all(&[REFERENCE])?;
+                    write_varint(&mut self.writer, id as u64)?;
+                }
+            }
+            write_varint(&mut self.writer, agent.ports.len() as u64)?;
+
+            let slot = self.kinds.len() + 1;
+            self.kinds.insert(key, slot);
+        }
+
+        Ok(())
+    }
+
+    /// Writes a term and everything below it, in prefix order.
+    fn write_term(&mut self, term: &Term) -> io::Result<()> {
+        let mut stack = vec![term];
+
+        while let Some(term) = stack.pop() {
+            let (slot, name, data) = match term {
+                Term::Port(port) => (0, port.name.as_deref(), 0),
+                Term::Agent(agent) => (
+                    self.kinds[&(agent.kind, agent.ports.len())],
+                    agent.name(),
+                    agent.data,
+                ),
+            };
+
+            let mut header = slot << 2;
+            if name.is_some() {
+                header |= HAS_NAME;
+            }
+            if data != 0 {
+                header |= HAS_DATA;
+            }
+
+            write_varint(&mut self.writer, header as u64)?;
+            write_varint(&mut self.writer, *term.id() as u64)?;
+
+            if let Some(name) = name {
+                write_varint(&mut self.writer, name.len() as u64)?;
+                self.writer.write_all(name.as_bytes())?;
+            }
+            if data != 0 {
+                write_varint(&mut self.writer, data)?;
+            }
+
+            if let Term::Agent(agent) = term {
+                stack.extend(agent.ports.iter().rev());
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn write_connection(&mut self, connection: &Connection) -> io::Result<()> {
+        self.define_kinds(connection.left())?;
+        self.define_kinds(connection.right())?;
+
+        self.writer.write_all(&[CONNECTION])?;
+        self.write_term(connection.left())?;
+        self.write_term(connection.right())
+    }
+
+    /// Marks the end of the net and returns the underlying writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.writer.write_all(&[END])?;
+        self.writer.flush()?;
+        self.writer.into_inner().map_err(|err| err.into_error())
+    }
+}
+
+/// Reads connections from a stream, one at a time. Reads are buffered, so
+/// the stream may be read past the end of the net.
+pub struct NetReader<R: Read> {
+    reader: BufReader<R>,
+    /// The kind and arity of each kind table entry.
+    kinds: Vec<(AgentKind, usize)>,
+    done: bool,
+}
+
+impl<R: Read> NetReader<R> {
+    pub fn new(reader: R) -> io::Result<Self> {
+        let mut reader = BufReader::new(reader);
+        let mut header = [0; 5];
+        reader.read_exact(&mut header)?;
+
+        if &header[..4] != MAGIC {
+            return Err(invalid_data("not a binary net"));
+        }
+        if header[4] != VERSION {
+            return Err(invalid_data(format!(
+                "unsupported binary net version {}",
+                header[4]
+            )));
+        }
+
+        Ok(Self {
+            reader,
+            kinds: Vec::new(),
+            done: false,
+        })
+    }
+
+    fn read_kind(&mut self) -> io::Result<()> {
+        let kind = match read_byte(&mut self.reader)? {
+            ERASER => AgentKind::Eraser,
+            DUPLICATOR => AgentKind::Duplicator,
+            CONSTRUCTOR => AgentKind::Constructor,
+            DYNAMIC => AgentKind::Dynamic(read_varint(&mut self.reader)?),
+            NUMBER => AgentKind::Number,
+            BINARY_OP => AgentKind::BinaryOp(read_operator(&mut self.reader)?),
+            PARTIAL_OP => AgentKind::PartialOp(read_operator(&mut self.reader)?),
+            SWITCH => AgentKind::Switch,
+            REFERENCE => AgentKind::Reference(read_varint(&mut self.reader)?),
+            tag => return Err(invalid_data(format!("unknown kind tag {tag}"))),
+        };
+        let arity = read_varint(&mut self.reader)?;
+        if kind.arity().is_some_and(|expected| expected != arity) {
+            return Err(invalid_data(format!("{kind:?} can't have {arity} ports")));
+        }
+
+        self.kinds.push((kind, arity));
+        Ok(())
+    }
+
+    /// Reads a term's header, ID, name and data. Agents come back without
+    /// their ports, along with how many there should be.
+    fn read_node(&mut self) -> io::Result<(Term, usize)> {
+        let header = read_varint(&mut self.reader)?;
+        let slot = header >> 2;
+        let id = read_varint(&mut self.reader)?;
+
+        let name = if header & HAS_NAME != 0 {
+            // read through `take` rather than into a buffer of the given
+            // length, so a bogus length can't make us allocate it all up front
+            let len = read_varint(&mut self.reader)?;
+            let mut name = Vec::new();
+            (&mut self.reader).take(len as u64).read_to_end(&mut name)?;
+            if name.len() != len {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+
+            Some(String::from_utf8(name).map_err(|_| invalid_data("name is not UTF-8"))?)
+        } else {
+            None
+        };
+
+        let data = if header & HAS_DATA != 0 {
+            read_varint64(&mut self.reader)?
+        } else {
+            0
+        };
+
+        if slot == 0 {
+            let mut port = Port::new(id);
+            port.name = name;
+            return Ok((Term::Port(port), 0));
+        }
+
+        let &(kind, arity) = self
+            .kinds
+            .get(slot - 1)
+            .ok_or_else(|| invalid_data(format!("undefined kind slot {slot}")))?;
+
+        let agent = Agent::new(id, kind, []).with_data(data);
+        let agent = match name {
+            Some(name) => agent.with_name(name),
+            None => agent,
+        };
+
+        Ok((Term::Agent(agent), arity))
+    }
+
+    /// Reads a term and everything below it.
+    fn read_term(&mut self) -> io::Result<Term> {
+        // agents whose ports are still being read, innermost last
+        let mut stack: Vec<(Term, usize, Vec<Term>)> = Vec::new();
+
+        loop {
+            let (mut term, arity) = self.read_node()?;
+            if arity > 0 {
+                stack.push((term, arity, Vec::new()));
+                continue;
+            }
+
+            // hand the term up to its parent, and keep going up while that
+            // completes the parent
+            loop {
+                let Some((_, arity, ports)) = stack.last_mut() else {
+                    return Ok(term);
+                };
+
+                ports.push(term);
+                if ports.len() < *arity {
+                    break;
+                }
+
+                let (parent, _, ports) = stack.pop().unwrap();
+                let Term::Agent(mut agent) = parent else {
+                    unreachable!("only agents have ports");
+                };
+                agent.ports = ports.into();
+                term = Term::Agent(agent);
+            }
+        }
+    }
+
+    fn read_connection(&mut self) -> io::Result<Option<Connection>> {
+        loop {
+            match read_byte(&mut self.reader)? {
+                END => return Ok(None),
+                KIND => self.read_kind()?,
+                CONNECTION => {
+                    let left = self.read_term()?;
+                    let right = self.read_term()?;
+                    return Ok(Some(Connection(left, right)));
+                }
+                tag => return Err(invalid_data(format!("unknown record tag {tag}"))),
+            }
+        }
+    }
+}
+
+impl<R: Read> Iterator for NetReader<R> {
+    type Item = io::Result<Connection>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        let result = self.read_connection().transpose();
+        if !matches!(result, Some(Ok(_))) {
+            self.done = true;
+        }
+
+        result
+    }
+}
+
+/// Writes a whole net in the binary format.
+pub fn write_net<'a, W: Write>(
+    writer: W,
+    connections: impl IntoIterator<Item = &'a Connection>,
+) -> io::Result<W> {
+    let mut writer = NetWriter::new(writer)?;
+    for connection in connections {
+        writer.write_connection(connection)?;
+    }
+    writer.finish()
+}
+
+/// Reads a whole net in the binary format.
+pub fn read_net(reader: impl Read) -> io::Result<Vec<Connection>> {
+    NetReader::new(reader)?.collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::net::text::{parse_connection, write_connection};
+
+    fn text(connections: &[Connection]) -> Vec<String> {
+        connections
+            .iter()
+            .map(|connection| {
+                let mut text = String::new();
+                write_connection(&mut text, connection.left(), connection.right()).unwrap();
+                text
+            })
+            .collect()
+    }
+
+    fn round_trip(connections: &[Connection]) -> Vec<Connection> {
+        let bytes = write_net(Vec::new(), connections).unwrap();
+        read_net(bytes.as_slice()).unwrap()
+    }
+
+    #[test]
+    fn round_trips_every_kind() {
+        let mut sources = vec![
+            r#"$0:"out" = Constructor#4:"b"(Constructor#2(Eraser#3(), $1), $0:"out")"#.to_string(),
+            "Constructor#5($6, $7) = Duplicator#8($6, $7)".to_string(),
+            "Dynamic[3]#9($10) = Number{42}#11()".to_string(),
+            "Switch#12($13, $14) = Reference[300]#15()".to_string(),
+            "$1 = $10".to_string(),
+        ];
+        for (index, op) in Operator::ALL.into_iter().enumerate() {
+            sources.push(format!(
+                "BinaryOp[{op:?}]#{}($0, $0) = PartialOp[{op:?}]{{7}}#{}($0)",
+                100 + index,
+                200 + index
+            ));
+        }
+
+        let connections: Vec<_> = sources
+            .iter()
+            .map(|src| parse_connection(src).unwrap())
+            .collect();
+
+        assert_eq!(text(&round_trip(&connections)), sources);
+    }
+
+    /// Drops a term without recursing, which dropping it normally would.
+    fn dismantle(term: Term) {
+        let mut stack = vec![term];
+        while let Some(term) = stack.pop() {
+            if let Term::Agent(mut agent) = term {
+                stack.extend(std::mem::take(&mut agent.ports).into_vec());
+            }
+        }
+    }
+
+    #[test]
+    fn round_trips_deep_nesting() {
+        const DEPTH: usize = 20_000;
+
+        let mut term = Term::Port(Port::new(0));
+        for id in 1..=DEPTH {
+            term = Term::Agent(Agent::new(
+                id,
+                AgentKind::Constructor,
+                [term, Term::Port(Port::new(DEPTH + id))],
+            ));
+        }
+        let connections = [Connection(Term::Port(Port::new(0)), term)];
+
+        let read = round_trip(&connections);
+        assert_eq!(read.len(), 1);
+
+        let mut term = read[0].right();
+        let mut depth = 0;
+        while let Term::Agent(agent) = term {
+            depth += 1;
+            term = &agent.ports[0];
+        }
+        assert_eq!(depth, DEPTH);
+
+        for Connection(left, right) in connections.into_iter().chain(read) {
+            dismantle(left);
+            dismantle(right);
+        }
+    }
+
+    #[test]
+    fn rejects_a_name_longer_than_the_input() {
+        let connections = [parse_connection(r#"$0:"name" = Eraser#1()"#).unwrap()];
+        let mut bytes = write_net(Vec::new(), &connections).unwrap();
+
+        // claim the name is as long as it can be
+        let at = bytes
+            .windows(5)
+            .position(|window| window == b"\x04name")
+            .unwrap();
+        bytes.splice(at..at + 1, [0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x7f]);
+
+        let error = read_net(bytes.as_slice()).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn rejects_an_undefined_kind() {
+        let mut bytes = write_net(Vec::new(), []).unwrap();
+        bytes.pop();
+        bytes.extend([CONNECTION, 1 << 2, 1, 0, 2]);
+
+        let error = read_net(bytes.as_slice()).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn rejects_varints_that_overflow() {
+        let mut bytes = [0xff; 10];
+        bytes[9] = 0x01;
+        assert_eq!(read_varint64(&mut bytes.as_slice()).unwrap(), u64::MAX);
+
+        bytes[9] = 0x02;
+        let error = read_varint64(&mut bytes.as_slice()).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn rejects_kinds_with_the_wrong_arity() {
+        let mut bytes = write_net(Vec::new(), []).unwrap();
+        bytes.pop();
+        bytes.extend([KIND, CONSTRUCTOR, 3, END]);
+
+        let error = read_net(bytes.as_slice()).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,679 @@
+//! Comparing nets by their shape rather than their IDs.
+//!
+//! [`Connection`]'s `PartialEq` compares IDs, and the same graph can be split
+//! into trees in more than one way, so two runs that reach the same normal
+//! form usually don't produce equal connections. A [`CanonicalNet`] rebuilds
+//! the graph and writes it back out in a fixed way:
+//!
+//! - Agents are numbered by walking the graph from the interface ports, in
+//!   order of their names, visiting each agent's ports in order. Unnamed
+//!   interface ports come after named ones, ordered by ID, so they only match
+//!   up between nets that share them.
+//! - Parts that can't be reached from the interface are ordered by their
+//!   shape. Finding that order is quadratic in the size of each part, but such
+//!   parts are rare in normal forms.
+//! - Each agent is nested in the port its principal port is connected to,
+//!   unless it's in an active pair or on the interface.
+//! - IDs are renumbered in the order they're written, and every name except
+//!   those of interface ports is dropped.
+//!
+//! Two nets are isomorphic when their canonical forms are equal.
+
+use std::hash::{Hash, Hasher};
+
+use rustc_hash::{FxHashMap as HashMap, FxHasher};
+
+use super::{
+    connection::Connection,
+    id,
+    term::{Agent, AgentKind, Port, Term},
+};
+
+/// The shape of a part of a graph: each agent in the order it's walked in,
+/// with the numbers and ports of its neighbors.
+type Signature = Vec<(AgentKind, u64, Vec<(usize, usize)>)>;
+
+/// One end of a wire.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+enum End {
+    /// A port of an agent, where zero is the principal port and `i + 1` is
+    /// auxiliary port `i`.
+    Agent(usize, usize),
+    /// An interface port, by ID.
+    Free(usize),
+}
+
+/// Something that a term is linked to while the graph is being built.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Node {
+    End(End),
+    /// A port that links two things, by ID.
+    Wire(usize),
+}
+
+struct Vertex {
+    kind: AgentKind,
+    data: u64,
+    /// What each of the agent's ports is connected to.
+    neighbors: Vec<End>,
+}
+
+/// A net as a graph, with the wires between ports followed all the way
+/// through.
+struct Graph {
+    vertices: Vec<Vertex>,
+    /// Interface ports by ID, in the order they're walked from, along with
+    /// their names and what they're connected to.
+    interface: Vec<(usize, Option<String>, End)>,
+    /// Closed loops that no agents are on.
+    loops: usize,
+}
+
+impl Graph {
+    fn new(connections: impl IntoIterator<Item = Connection>) -> Self {
+        let mut builder = GraphBuilder::default();
+        for Connection(left, right) in connections {
+            let left = builder.add_term(left);
+            let right = builder.add_term(right);
+            builder.link(left, right);
+        }
+
+        builder.build()
+    }
+
+    /// Walks from each of the given agents in turn, numbering every agent it
+    /// reaches that hasn't been numbered yet, and returns the new ones.
+    fn walk(
+        &self,
+        starts: impl IntoIterator<Item = usize>,
+        numbers: &mut [Option<usize>],
+        next: &mut usize,
+    ) -> Vec<usize> {
+        let mut order = Vec::new();
+        let mut stack = Vec::new();
+
+        for start in starts {
+            stack.push(start);
+
+            while let Some(vertex) = stack.pop() {
+                if numbers[vertex].is_some() {
+                    continue;
+                }
+
+                numbers[vertex] = Some(*next);
+                *next += 1;
+                order.push(vertex);
+
+                for neighbor in self.vertices[vertex].neighbors.iter().rev() {
+                    if let End::Agent(neighbor, _) = neighbor {
+                        stack.push(*neighbor);
+                    }
+                }
+            }
+        }
+
+        order
+    }
+
+    /// Describes the part of the graph reachable from `start`, in terms of the
+    /// order it's walked in, so that parts can be compared by their shape.
+    fn signature(&self, start: usize) -> Signature {
+        let mut numbers = vec![None; self.vertices.len()];
+        let order = self.walk([start], &mut numbers, &mut 0);
+
+        order
+            .into_iter()
+            .map(|vertex| {
+                let vertex = &self.vertices[vertex];
+                let neighbors = vertex
+                    .neighbors
+                    .iter()
+                    .map(|neighbor| match neighbor {
+                        End::Agent(other, slot) => (numbers[*other].unwrap(), *slot),
+                        End::Free(_) => unreachable!(),
+                    })
+                    .collect();
+
+                (vertex.kind, vertex.data, neighbors)
+            })
+            .collect()
+    }
+
+    /// Gives each agent its number in the canonical order.
+    fn number(&self) -> Vec<usize> {
+        let mut numbers = vec![None; self.vertices.len()];
+        let mut next = 0;
+
+        let starts = self.interface.iter().filter_map(|(_, _, end)| match end {
+            End::Agent(vertex, _) => Some(*vertex),
+            End::Free(_) => None,
+        });
+        self.walk(starts, &mut numbers, &mut next);
+
+        // what's left is split into parts that aren't connected to anything
+        let mut parts = Vec::new();
+        for vertex in 0..self.vertices.len() {
+            if numbers[vertex].is_some() {
+                continue;
+            }
+
+            let part = self.walk([vertex], &mut numbers, &mut 0);
+            let smallest = part
+                .iter()
+                .map(|&vertex| (self.vertices[vertex].kind, self.vertices[vertex].data))
+                .min()
+                .unwrap();
+
+            let (signature, start) = part
+                .iter()
+                .filter(|&&vertex| {
+                    (self.vertices[vertex].kind, self.vertices[vertex].data) == smallest
+                })
+                .map(|&vertex| (self.signature(vertex), vertex))
+                .min()
+                .unwrap();
+            parts.push((signature, start, part));
+        }
+        parts.sort();
+
+        for (_, _, part) in &parts {
+            for &vertex in part {
+                numbers[vertex] = None;
+            }
+        }
+        for (_, start, _) in parts {
+            self.walk([start], &mut numbers, &mut next);
+        }
+
+        numbers.into_iter().map(Option::unwrap).collect()
+    }
+}
+
+#[derive(Default)]
+struct GraphBuilder {
+    vertices: Vec<Vertex>,
+    links: Vec<[Node; 2]>,
+    /// The links that each wire is part of, and which side of them it's on.
+    wires: HashMap<usize, Vec<(usize, usize)>>,
+    names: HashMap<usize, Option<String>>,
+}
+
+impl GraphBuilder {
+    fn link(&mut self, left: Node, right: Node) {
+        let index = self.links.len();
+        for (side, node) in [left, right].into_iter().enumerate() {
+            if let Node::Wire(id) = node {
+                self.wires.entry(id).or_default().push((index, side));
+            }
+        }
+
+        self.links.push([left, right]);
+    }
+
+    fn add_term(&mut self, term: Term) -> Node {
+        let id = *term.id();
+
+        match term {
+            Term::Port(port) => {
+                self.names.entry(id).or_insert(port.name);
+                Node::Wire(id)
+            }
+            Term::Agent(agent) => {
+                let vertex = self.vertices.len();
+                self.vertices.push(Vertex {
+                    kind: agent.kind,
+                    data: agent.data,
+                    // filled in once every wire has been seen
+                    neighbors: vec![End::Free(0); agent.ports.len() + 1],
+                });
+
+                for (index, port) in Vec::from(agent.ports).into_iter().enumerate() {
+                    let port = self.add_term(port);
+                    self.link(Node::End(End::Agent(vertex, index + 1)), port);
+                }
+
+                Node::End(End::Agent(vertex, 0))
+            }
+        }
+    }
+
+    /// Follows a link from one side to the end of the wire it's part of,
+    /// marking the wires it goes through as seen.
+    fn follow(&self, mut link: usize, mut side: usize, seen: &mut HashMap<usize, bool>) -> End {
+        loop {
+            let other = 1 - side;
+            let id = match self.links[link][other] {
+                Node::End(end) => return end,
+                Node::Wire(id) => id,
+            };
+            seen.insert(id, true);
+
+            let uses = &self.wires[&id];
+            let position = uses.iter().position(|&used| used == (link, other)).unwrap();
+            match uses
+                .iter()
+                .enumerate()
+                .find(|&(index, _)| index != position)
+            {
+                Some((_, &(next_link, next_side))) => {
+                    link = next_link;
+                    side = next_side;
+                }
+                None => return End::Free(id),
+            }
+        }
+    }
+
+    fn build(mut self) -> Graph {
+        let mut seen = HashMap::default();
+
+        for (index, link) in self.links.iter().enumerate() {
+            for (side, node) in link.iter().enumerate() {
+                if let Node::End(End::Agent(vertex, slot)) = *node {
+                    let end = self.follow(index, side, &mut seen);
+
+                    self.vertices[vertex].neighbors[slot] = end;
+                }
+            }
+        }
+
+        let mut interface = Vec::new();
+        for (&id, uses) in &self.wires {
+            if let [(link, side)] = uses[..] {
+                let name = self.names[&id].clone();
+                interface.push((id, name, self.follow(link, side, &mut seen)));
+            }
+        }
+        interface.sort_by(|(a, a_name, _), (b, b_name, _)| {
+            (a_name.is_none(), a_name, a).cmp(&(b_name.is_none(), b_name, b))
+        });
+
+        let loops = self.count_loops(&seen);
+
+        Graph {
+            vertices: self.vertices,
+            interface,
+            loops,
+        }
+    }
+
+    /// Counts the wires that are only linked to each other, in closed loops.
+    fn count_loops(&self, seen: &HashMap<usize, bool>) -> usize {
+        let mut visited = HashMap::<usize, bool>::default();
+        let mut loops = 0;
+
+        for &id in self.wires.keys() {
+            if seen.contains_key(&id) || visited.contains_key(&id) {
+                continue;
+            }
+
+            loops += 1;
+            let mut stack = vec![id];
+            while let Some(id) = stack.pop() {
+                if visited.insert(id, true).is_some() {
+                    continue;
+                }
+
+                for &(link, _) in &self.wires[&id] {
+                    for node in self.links[link] {
+                        if let Node::Wire(other) = node {
+                            stack.push(other);
+                        }
+                    }
+                }
+            }
+        }
+
+        loops
+    }
+}
+
+/// Writes a graph back out as connections, in canonical order.
+struct Writer<'a> {
+    graph: &'a Graph,
+    numbers: Vec<usize>,
+    /// Agents whose principal port is connected to an auxiliary port, but
+    /// that can't be nested there because they're part of a cycle.
+    broken: Vec<bool>,
+    /// IDs for wires, by their ends in order.
+    wires: HashMap<(End, End), usize>,
+    interface: HashMap<usize, Option<String>>,
+}
+
+impl Writer<'_> {
+    fn is_nested(&self, vertex: usize) -> bool {
+        matches!(self.graph.vertices[vertex].neighbors[0], End::Agent(_, slot) if slot > 0)
+            && !self.broken[vertex]
+    }
+
+    /// Finds the agents that would end up inside of themselves if they were
+    /// nested, and picks the first one of each cycle to leave out.
+    fn break_cycles(&mut self, by_number: &[usize]) {
+        let mut done = vec![false; by_number.len()];
+
+        for &start in by_number {
+            let mut path = Vec::new();
+            let mut vertex = start;
+
+            while !done[vertex] {
+                if let Some(position) = path.iter().position(|&other| other == vertex) {
+                    let cycle: &[usize] = &path[position..];
+                    let first = *cycle
+                        .iter()
+                        .min_by_key(|&&other| self.numbers[other])
+                        .unwrap();
+                    self.broken[first] = true;
+                    break;
+                }
+                path.push(vertex);
+
+                match self.graph.vertices[vertex].neighbors[0] {
+                    End::Agent(parent, slot) if slot > 0 => vertex = parent,
+                    _ => break,
+                }
+            }
+
+            for vertex in path {
+                done[vertex] = true;
+            }
+        }
+    }
+
+    fn wire(&mut self, a: End, b: End) -> Term {
+        let key = (a.min(b), a.max(b));
+        let next = self.numbers.len() + self.wires.len();
+        Term::Port(Port::new(*self.wires.entry(key).or_insert(next)))
+    }
+
+    fn free_port(&mut self, id: usize) -> Term {
+        let Term::Port(mut port) = self.wire(End::Free(id), End::Free(id)) else {
+            unreachable!();
+        };
+        port.name = self.interface[&id].clone();
+        Term::Port(port)
+    }
+
+    fn tree(&mut self, vertex: usize) -> Term {
+        let graph = self.graph;
+        let Vertex {
+            kind,
+            data,
+            neighbors,
+        } = &graph.vertices[vertex];
+
+        let ports: Vec<_> = neighbors[1..]
+            .iter()
+            .enumerate()
+            .map(|(index, &neighbor)| match neighbor {
+                End::Agent(other, 0) if self.is_nested(other) => self.tree(other),
+                End::Free(id) => self.free_port(id),
+                neighbor => self.wire(End::Agent(vertex, index + 1), neighbor),
+            })
+            .collect();
+
+        Term::Agent(Agent::new(self.numbers[vertex], *kind, ports).with_data(*data))
+    }
+}
+
+/// A net in canonical form. Nets with the same shape have equal canonical
+/// forms, whatever their IDs were and however they were split into trees.
+pub struct CanonicalNet {
+    connections: Vec<Connection>,
+}
+
+impl CanonicalNet {
+    pub fn new(connections: impl IntoIterator<Item = Connection>) -> Self {
+        let graph = Graph::new(connections);
+        let numbers = graph.number();
+
+        let mut by_number = vec![0; numbers.len()];
+        for (vertex, &number) in numbers.iter().enumerate() {
+            by_number[number] = vertex;
+        }
+
+        let mut writer = Writer {
+            graph: &graph,
+            numbers,
+            broken: vec![false; by_number.len()],
+            wires: HashMap::default(),
+            interface: graph
+                .interface
+                .iter()
+                .map(|(id, name, _)| (*id, name.clone()))
+                .collect(),
+        };
+        writer.break_cycles(&by_number);
+
+        let mut connections = Vec::new();
+        for &vertex in &by_number {
+            let principal = graph.vertices[vertex].neighbors[0];
+            let connection = match principal {
+                _ if writer.is_nested(vertex) => continue,
+                End::Agent(other, 0) if writer.numbers[other] < writer.numbers[vertex] => continue,
+                End::Agent(other, 0) => Connection(writer.tree(vertex), writer.tree(other)),
+                End::Agent(..) => Connection(
+                    writer.wire(End::Agent(vertex, 0), principal),
+                    writer.tree(vertex),
+                ),
+                End::Free(id) => Connection(writer.free_port(id), writer.tree(vertex)),
+            };
+
+            connections.push(connection);
+        }
+
+        // interface ports that are connected straight to each other
+        for (index, (id, _, end)) in graph.interface.iter().enumerate() {
+            let End::Free(other) = *end else {
+                continue;
+            };
+            if graph.interface[..index]
+                .iter()
+                .any(|(id, _, _)| *id == other)
+            {
+                continue;
+            }
+
+            let left = writer.free_port(*id);
+            let right = writer.free_port(other);
+            connections.push(Connection(left, right));
+        }
+
+        let next_id = writer.numbers.len() + writer.wires.len();
+        for id in next_id..next_id + graph.loops {
+            connections.push(Connection(
+                Term::Port(Port::new(id)),
+                Term::Port(Port::new(id)),
+            ));
+        }
+
+        let (connections, _) = id::compact(connections);
+        Self { connections }
+    }
+
+    pub fn connections(&self) -> &[Connection] {
+        &self.connections
+    }
+
+    pub fn into_connections(self) -> Vec<Connection> {
+        self.connections
+    }
+
+    /// A hash of the shape of the net, which is the same for isomorphic nets.
+    pub fn structural_hash(&self) -> u64 {
+        let mut hasher = FxHasher::default();
+        self.hash(&mut hasher);
+        hasher.finish()
+    }
+}
+
+fn same_term(a: &Term, b: &Term) -> bool {
+    match (a, b) {
+        (Term::Port(port_a), Term::Port(port_b)) => a.id() == b.id() && port_a.name == port_b.name,
+        (Term::Agent(agent_a), Term::Agent(agent_b)) => {
+            agent_a.id == agent_b.id
+                && agent_a.kind == agent_b.kind
+                && agent_a.data == agent_b.data
+                && agent_a.ports.len() == agent_b.ports.len()
+                && agent_a
+                    .ports
+                    .iter()
+                    .zip(agent_b.ports.iter())
+                    .all(|(a, b)| same_term(a, b))
+        }
+        _ => false,
+    }
+}
+
+fn hash_term(term: &Term, state: &mut impl Hasher) {
+    term.id().hash(state);
+    match term {
+        Term::Port(port) => port.name.hash(state),
+        Term::Agent(agent) => {
+            agent.kind.hash(state);
+            agent.data.hash(state);
+            agent.ports.len().hash(state);
+            for port in agent.ports.iter() {
+                hash_term(port, state);
+            }
+        }
+    }
+}
+
+impl Eq for CanonicalNet {}
+impl PartialEq for CanonicalNet {
+    fn eq(&self, other: &Self) -> bool {
+        self.connections.len() == other.connections.len()
+            && self
+                .connections
+                .iter()
+                .zip(&other.connections)
+                .all(|(a, b)| same_term(a.left(), b.left()) && same_term(a.right(), b.right()))
+    }
+}
+
+impl Hash for CanonicalNet {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        for Connection(left, right) in &self.connections {
+            hash_term(left, state);
+            hash_term(right, state);
+        }
+    }
+}
+
+impl std::fmt::Debug for CanonicalNet {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_list().entries(&self.connections).finish()
+    }
+}
+
+/// Whether two nets have the same shape, with the same interface.
+pub fn is_isomorphic(
+    a: impl IntoIterator<Item = Connection>,
+    b: impl IntoIterator<Item = Connection>,
+) -> bool {
+    CanonicalNet::new(a) == CanonicalNet::new(b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::net::text::parse_connection;
+
+    fn canonical(sources: &[&str]) -> CanonicalNet {
+        CanonicalNet::new(sources.iter().map(|src| parse_connection(src).unwrap()))
+    }
+
+    fn assert_isomorphic(a: &[&str], b: &[&str]) {
+        let (a, b) = (canonical(a), canonical(b));
+        assert_eq!(a, b);
+        assert_eq!(a.structural_hash(), b.structural_hash());
+    }
+
+    fn assert_not_isomorphic(a: &[&str], b: &[&str]) {
+        assert_ne!(canonical(a), canonical(b));
+    }
+
+    #[test]
+    fn ignores_ids_and_how_the_net_is_split() {
+        assert_isomorphic(
+            &[
+                r#"$0:"out" = Constructor#1($2, $3)"#,
+                "$2 = Eraser#4()",
+                "$3 = Number{5}#6()",
+            ],
+            &[r#"$10:"out" = Constructor#11(Eraser#12(), Number{5}#13())"#],
+        );
+    }
+
+    #[test]
+    fn ignores_the_order_of_connections() {
+        assert_isomorphic(
+            &[
+                r#"$0:"a" = Duplicator#1($2, $3:"b")"#,
+                r#"$2 = $4:"c""#,
+                "Eraser#5() = Constructor#6($7, $7)",
+            ],
+            &[
+                "Eraser#15() = Constructor#16($17, $17)",
+                r#"$0:"a" = Duplicator#1($4:"c", $3:"b")"#,
+            ],
+        );
+    }
+
+    #[test]
+    fn orders_unreachable_parts_by_shape() {
+        assert_isomorphic(
+            &[
+                "Eraser#1() = Eraser#2()",
+                "Constructor#3($4, $5) = Duplicator#6($4, $5)",
+                "$7 = $7",
+            ],
+            &[
+                "$17 = $17",
+                "Duplicator#16($14, $15) = Constructor#13($14, $15)",
+                "Eraser#11() = Eraser#12()",
+            ],
+        );
+    }
+
+    #[test]
+    fn tells_different_nets_apart() {
+        let net = [r#"$0:"out" = Constructor#1(Number{1}#2(), Eraser#3())"#];
+
+        // different data
+        assert_not_isomorphic(
+            &net,
+            &[r#"$0:"out" = Constructor#1(Number{2}#2(), Eraser#3())"#],
+        );
+        // swapped ports
+        assert_not_isomorphic(
+            &net,
+            &[r#"$0:"out" = Constructor#1(Eraser#3(), Number{1}#2())"#],
+        );
+        // a different interface
+        assert_not_isomorphic(
+            &net,
+            &[r#"$0:"result" = Constructor#1(Number{1}#2(), Eraser#3())"#],
+        );
+        // a different kind
+        assert_not_isomorphic(
+            &net,
+            &[r#"$0:"out" = Duplicator#1(Number{1}#2(), Eraser#3())"#],
+        );
+    }
+
+    #[test]
+    fn tells_wirings_apart() {
+        // the same agents, with their ports crossed over differently
+        assert_not_isomorphic(
+            &[
+                r#"$0:"a" = Constructor#1($2, $3)"#,
+                r#"$4:"b" = Constructor#5($2, $3)"#,
+            ],
+            &[
+                r#"$0:"a" = Constructor#1($2, $3)"#,
+                r#"$4:"b" = Constructor#5($3, $2)"#,
+            ],
+        );
+    }
+}
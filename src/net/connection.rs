@@ -2,6 +2,7 @@ use std::fmt::Debug;
 
 use super::term::{Agent, Term};
 
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Connection(pub Term, pub Term);
 
 impl Connection {
@@ -0,0 +1,389 @@
+//! Finding the leftover structure in a normal form that can never reduce.
+//!
+//! A normal form with a mistake in it looks much like a correct one: it's
+//! just a net with no active pairs left to reduce. The usual mistakes leave
+//! behind one of three things, which [`find_deadlocks`] looks for:
+//!
+//! - A vicious circle, where each agent's principal port faces an auxiliary
+//!   port of the next one, all the way round. None of them can ever meet
+//!   another principal port.
+//! - A closed loop of wire with no agents on it.
+//! - An active pair that the rulebook has no rule for, so it's stuck.
+
+use std::fmt::{Display, Formatter};
+
+use rustc_hash::FxHashMap as HashMap;
+
+use super::term::{Agent, Term};
+use crate::rule::rulebook::Rulebook;
+
+#[derive(Debug)]
+pub enum Deadlock<'a> {
+    /// Agents that each have their principal port connected to an auxiliary
+    /// port of the next one, and the last one's to the first one's. It starts
+    /// from the agent with the lowest ID.
+    ViciousCircle(Vec<&'a Agent>),
+    /// A wire that's only connected to itself, by the IDs of the ports along
+    /// it, sorted.
+    WireLoop(Vec<usize>),
+    /// An active pair that the rulebook has no rule for.
+    StuckPair(&'a Agent, &'a Agent),
+}
+
+/// Writes an agent without what's connected to it.
+fn write_agent(f: &mut Formatter<'_>, agent: &Agent) -> std::fmt::Result {
+    write!(f, "{:?}", agent.kind)?;
+    if agent.kind.has_data() || agent.data != 0 {
+        write!(f, "{{{}}}", agent.data)?;
+    }
+
+    match agent.name() {
+        Some(name) => write!(f, "<{},{}>", agent.id, name),
+        None => write!(f, "<{}>", agent.id),
+    }
+}
+
+impl Display for Deadlock<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::ViciousCircle(agents) => {
+                write!(f, "vicious circle: ")?;
+                for agent in agents {
+                    write_agent(f, agent)?;
+                    write!(f, " -> ")?;
+                }
+                write_agent(f, agents[0])
+            }
+            Self::WireLoop(ports) => write!(f, "closed loop of wire through ports {ports:?}"),
+            Self::StuckPair(left, right) => {
+                write!(f, "no rule for active pair ")?;
+                write_agent(f, left)?;
+                write!(f, " ~ ")?;
+                write_agent(f, right)
+            }
+        }
+    }
+}
+
+/// Where a port shows up in the net.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Site {
+    /// In an auxiliary port of an agent, by the agent's ID.
+    Aux(usize),
+    /// On the other side of a connection from an agent's principal port, by
+    /// the agent's ID.
+    Principal(usize),
+    /// On the other side of a connection from another port, by its ID, and
+    /// which side of the connection this one is on.
+    Wire(usize, usize),
+}
+
+struct Index<'a> {
+    /// Every agent, along with the agent its principal port is connected to
+    /// an auxiliary port of, when that's known without following a wire.
+    agents: HashMap<usize, (&'a Agent, Option<usize>)>,
+    ports: HashMap<usize, Vec<Site>>,
+}
+
+impl<'a> Index<'a> {
+    fn add_agent(&mut self, agent: &'a Agent, parent: Option<usize>) {
+        self.agents.insert(agent.id, (agent, parent));
+
+        for port in agent.ports.iter() {
+            match port {
+                Term::Agent(child) => self.add_agent(child, Some(agent.id)),
+                Term::Port(_) => self.add_site(*port.id(), Site::Aux(agent.id)),
+            }
+        }
+    }
+
+    fn add_site(&mut self, port_id: usize, site: Site) {
+        self.ports.entry(port_id).or_default().push(site);
+    }
+
+    /// Follows a wire from the agent at one end of it to the agent whose
+    /// auxiliary port is at the other end, if that's what's there.
+    fn follow(&self, agent_id: usize, port_id: usize) -> Option<usize> {
+        let mut from = Site::Principal(agent_id);
+        let mut port_id = port_id;
+
+        // a wire can't be longer than the number of ports, so this also
+        // stops on wires that are already loops
+        for _ in 0..=self.ports.len() {
+            let sites = &self.ports[&port_id];
+            let position = sites.iter().position(|&site| site == from)?;
+            let (_, &site) = sites
+                .iter()
+                .enumerate()
+                .find(|&(index, _)| index != position)?;
+
+            match site {
+                Site::Aux(other) => return Some(other),
+                Site::Principal(_) => return None,
+                Site::Wire(other, side) => {
+                    from = Site::Wire(port_id, 1 - side);
+                    port_id = other;
+                }
+            }
+        }
+
+        None
+    }
+
+    /// The agent that each agent's principal port faces an auxiliary port of.
+    fn successors(&self, principals: &HashMap<usize, usize>) -> HashMap<usize, usize> {
+        let mut successors = HashMap::default();
+
+        for (&agent_id, &(_, parent)) in &self.agents {
+            let successor = parent.or_else(|| {
+                let &port_id = principals.get(&agent_id)?;
+                self.follow(agent_id, port_id)
+            });
+
+            if let Some(successor) = successor {
+                successors.insert(agent_id, successor);
+            }
+        }
+
+        successors
+    }
+
+    fn vicious_circles(&self, successors: &HashMap<usize, usize>) -> Vec<Vec<&'a Agent>> {
+        let mut agent_ids: Vec<_> = self.agents.keys().copied().collect();
+        agent_ids.sort_unstable();
+
+        // which walk each agent was first reached by
+        let mut walks = HashMap::<usize, usize>::default();
+        let mut circles = Vec::new();
+
+        for (walk, &start) in agent_ids.iter().enumerate() {
+            let mut path = Vec::new();
+            let mut agent_id = start;
+
+            loop {
+                if let Some(&seen_by) = walks.get(&agent_id) {
+                    // only a walk that runs into itself has found a new circle
+                    if seen_by == walk {
+                        let start = path.iter().position(|&id| id == agent_id).unwrap();
+                        circles.push(path.split_off(start));
+                    }
+                    break;
+                }
+
+                walks.insert(agent_id, walk);
+                path.push(agent_id);
+
+                match successors.get(&agent_id) {
+                    Some(&successor) => agent_id = successor,
+                    None => break,
+                }
+            }
+        }
+
+        for circle in &mut circles {
+            let lowest = (0..circle.len()).min_by_key(|&i| circle[i]).unwrap();
+            circle.rotate_left(lowest);
+        }
+        circles.sort_unstable_by_key(|circle| circle[0]);
+
+        circles
+            .into_iter()
+            .map(|circle| circle.into_iter().map(|id| self.agents[&id].0).collect())
+            .collect()
+    }
+
+    /// Ports that are only ever connected to other ports, grouped by the wire
+    /// they're on, when that wire doesn't have a free end.
+    fn wire_loops(&self) -> Vec<Vec<usize>> {
+        let mut visited = HashMap::<usize, bool>::default();
+        let mut loops = Vec::new();
+
+        let mut port_ids: Vec<_> = self.ports.keys().copied().collect();
+        port_ids.sort_unstable();
+
+        for port_id in port_ids {
+            if visited.contains_key(&port_id) {
+                continue;
+            }
+
+            let mut closed = true;
+            let mut wire = Vec::new();
+            let mut stack = vec![port_id];
+            while let Some(port_id) = stack.pop() {
+                if visited.insert(port_id, true).is_some() {
+                    continue;
+                }
+                wire.push(port_id);
+
+                let sites = &self.ports[&port_id];
+                closed &= sites.len() == 2;
+                for site in sites {
+                    match *site {
+                        Site::Wire(other, _) => stack.push(other),
+                        _ => closed = false,
+                    }
+                }
+            }
+
+            if closed {
+                wire.sort_unstable();
+                loops.push(wire);
+            }
+        }
+
+        loops
+    }
+}
+
+/// Finds vicious circles, closed loops of wire and active pairs without a
+/// rule in a net. It's meant for nets in normal form, but active pairs that do
+/// have a rule are just skipped.
+///
+/// Circles come first, then loops, then pairs, each ordered by their lowest
+/// ID.
+pub fn find_deadlocks<'a>(
+    connections: impl IntoIterator<Item = (&'a Term, &'a Term)>,
+    rulebook: &Rulebook,
+) -> Vec<Deadlock<'a>> {
+    let mut index = Index {
+        agents: HashMap::default(),
+        ports: HashMap::default(),
+    };
+    // the port that each top-level agent's principal port is connected to
+    let mut principals = HashMap::default();
+    let mut pairs = Vec::new();
+
+    for (left, right) in connections {
+        match (left, right) {
+            (Term::Agent(left), Term::Agent(right)) => {
+                index.add_agent(left, None);
+                index.add_agent(right, None);
+                if !rulebook.has_rule(left, right) {
+                    pairs.push((left, right));
+                }
+            }
+            (port @ Term::Port(_), Term::Agent(agent))
+            | (Term::Agent(agent), port @ Term::Port(_)) => {
+                index.add_agent(agent, None);
+                index.add_site(*port.id(), Site::Principal(agent.id));
+                principals.insert(agent.id, *port.id());
+            }
+            (Term::Port(_), Term::Port(_)) => {
+                index.add_site(*left.id(), Site::Wire(*right.id(), 0));
+                index.add_site(*right.id(), Site::Wire(*left.id(), 1));
+            }
+        }
+    }
+
+    let successors = index.successors(&principals);
+
+    let mut deadlocks: Vec<_> = index
+        .vicious_circles(&successors)
+        .into_iter()
+        .map(Deadlock::ViciousCircle)
+        .collect();
+    deadlocks.extend(index.wire_loops().into_iter().map(Deadlock::WireLoop));
+
+    pairs.sort_by_key(|(left, right)| left.id.min(right.id));
+    deadlocks.extend(
+        pairs
+            .into_iter()
+            .map(|(left, right)| Deadlock::StuckPair(left, right)),
+    );
+
+    deadlocks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        net::{connection::Connection, id::IdAllocator, text::parse_connection},
+        rule::context::RewriteContext,
+        runtime::{readback::Node, Runtime},
+    };
+
+    fn connections(sources: &[&str]) -> Vec<(Term, Term)> {
+        sources
+            .iter()
+            .map(|src| {
+                let Connection(left, right) = parse_connection(src).unwrap();
+                (left, right)
+            })
+            .collect()
+    }
+
+    fn ids(agents: &[&Agent]) -> Vec<usize> {
+        agents.iter().map(|agent| agent.id).collect()
+    }
+
+    #[test]
+    fn finds_vicious_circles() {
+        let net = connections(&[
+            // through a nested agent
+            "Constructor#5(Constructor#2($3, $4), $6) = $3",
+            // through a wire, and starting from the lowest ID
+            "Duplicator#9($10, $11) = $12",
+            "$12 = $13",
+            "Eraser#14() = $10",
+            "Constructor#7($13, $15) = $11",
+        ]);
+
+        let deadlocks = find_deadlocks(net.iter().map(|(l, r)| (l, r)), &Rulebook::default());
+        let circles: Vec<_> = deadlocks
+            .iter()
+            .map(|deadlock| match deadlock {
+                Deadlock::ViciousCircle(agents) => ids(agents),
+                deadlock => panic!("expected a vicious circle, not {deadlock}"),
+            })
+            .collect();
+        assert_eq!(circles, [vec![2, 5], vec![7, 9]]);
+    }
+
+    #[test]
+    fn finds_closed_loops_of_wire() {
+        let net = connections(&["$3 = $1", "$1 = $2", "$2 = $3", "$4 = $5"]);
+
+        let deadlocks = find_deadlocks(net.iter().map(|(l, r)| (l, r)), &Rulebook::default());
+        let loops: Vec<_> = deadlocks
+            .iter()
+            .map(|deadlock| match deadlock {
+                Deadlock::WireLoop(ports) => ports.clone(),
+                deadlock => panic!("expected a loop of wire, not {deadlock}"),
+            })
+            .collect();
+        assert_eq!(loops, [vec![1, 2, 3]]);
+    }
+
+    #[test]
+    fn reducing_leaves_pairs_without_a_rule() {
+        let mut rulebook = Rulebook::default();
+        rulebook.declare_agent(0, 1);
+        let net = connections(&[
+            r#"Dynamic[0]#1($2:"a") = Constructor#3($4:"b", $5:"c")"#,
+            "Eraser#6() = Eraser#7()",
+        ]);
+        let mut runtime = Runtime::new(
+            net.into_iter().map(|(left, right)| Connection(left, right)),
+            rulebook,
+            RewriteContext::new(IdAllocator::new_at(100)),
+        );
+
+        runtime.reduce();
+        assert_eq!(runtime.interactions(), 1);
+
+        let deadlocks = runtime.deadlocks();
+        let [Deadlock::StuckPair(left, right)] = deadlocks.as_slice() else {
+            panic!("expected a single stuck pair, not {deadlocks:?}");
+        };
+        assert_eq!([left.id.min(right.id), left.id.max(right.id)], [1, 3]);
+        assert!(deadlocks[0]
+            .to_string()
+            .starts_with("no rule for active pair"));
+
+        // nor do lazy reduction and readback try to get past it
+        assert_eq!(runtime.reduce_lazily(2), 0);
+        let nodes: Vec<_> = runtime.read_back("a").unwrap().collect();
+        assert_eq!(nodes, [Node::Blocked]);
+    }
+}
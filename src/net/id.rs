@@ -1,7 +1,202 @@
-use std::sync::atomic::AtomicUsize;
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc, OnceLock,
+};
 
+use rustc_hash::FxHashMap as HashMap;
+
+use super::connection::Connection;
+
+/// Number of low bits of an ID that hold its index. The remaining high bits
+/// hold the generation, which is bumped every time the index is recycled.
+const INDEX_BITS: u32 = usize::BITS * 5 / 8;
+const INDEX_MASK: usize = (1 << INDEX_BITS) - 1;
+const GENERATION_MASK: usize = usize::MAX >> INDEX_BITS;
+
+/// The head of the free list holds a link, which is an index plus one so that
+/// zero can mean the list is empty, and a tag in the bits above it.
+const LINK_BITS: u32 = INDEX_BITS + 1;
+const LINK_MASK: usize = (1 << LINK_BITS) - 1;
+
+/// Number of slots in the first bucket of the slot table. Each bucket after it
+/// is twice as big as the one before.
+const FIRST_BUCKET: usize = 64;
+
+#[inline]
+fn compose(index: usize, generation: usize) -> usize {
+    assert!(index <= INDEX_MASK, "ran out of ID indices");
+    (generation << INDEX_BITS) | index
+}
+
+/// Splits an ID into its index and generation.
+#[inline]
+pub fn split(id: usize) -> (usize, usize) {
+    (id & INDEX_MASK, id >> INDEX_BITS)
+}
+
+/// What the allocator knows about an index.
+#[derive(Default)]
+struct Slot {
+    generation: AtomicUsize,
+    /// The link to the next index in the free list, while this one is in it.
+    next: AtomicUsize,
+}
+
+/// A slot for every index that has been retired, in buckets that are only
+/// ever added, so a slot never moves once it exists and can be used without
+/// a lock.
+struct Slots {
+    buckets: [OnceLock<Box<[Slot]>>; usize::BITS as usize],
+}
+
+impl Slots {
+    fn new() -> Self {
+        Self {
+            buckets: [const { OnceLock::new() }; usize::BITS as usize],
+        }
+    }
+
+    /// The bucket an index is in, and where in the bucket.
+    #[inline]
+    fn locate(index: usize) -> (usize, usize) {
+        let n = index / FIRST_BUCKET + 1;
+        let bucket = (usize::BITS - 1 - n.leading_zeros()) as usize;
+
+        (bucket, index - FIRST_BUCKET * ((1 << bucket) - 1))
+    }
+
+    fn get(&self, index: usize) -> Option<&Slot> {
+        let (bucket, offset) = Self::locate(index);
+        Some(&self.buckets[bucket].get()?[offset])
+    }
+
+    /// Gets the slot for an index, adding its bucket if this is the first
+    /// slot in it that's needed. That's the only time this can block.
+    fn get_or_add(&self, index: usize) -> &Slot {
+        let (bucket, offset) = Self::locate(index);
+        let slots = self.buckets[bucket].get_or_init(|| {
+            (0..FIRST_BUCKET << bucket)
+                .map(|_| Slot::default())
+                .collect()
+        });
+
+        &slots[offset]
+    }
+
+    fn generation(&self, index: usize) -> usize {
+        self.get(index)
+            .map_or(0, |slot| slot.generation.load(Ordering::Acquire))
+    }
+}
+
+/// A stack of retired indices, linked through their slots.
+///
+/// The head is tagged with a count of the changes made to it, so a thread
+/// that read the head before another thread popped it and pushed it back
+/// can't swap in a link that's out of date.
+struct FreeList {
+    head: AtomicUsize,
+}
+
+impl FreeList {
+    fn new() -> Self {
+        Self {
+            head: AtomicUsize::new(0),
+        }
+    }
+
+    /// The head after this one, with its tag bumped.
+    #[inline]
+    fn next_head(head: usize, link: usize) -> usize {
+        let tag = (head >> LINK_BITS).wrapping_add(1) & (usize::MAX >> LINK_BITS);
+        (tag << LINK_BITS) | link
+    }
+
+    fn push(&self, slots: &Slots, index: usize) {
+        let slot = slots.get_or_add(index);
+        let mut head = self.head.load(Ordering::Relaxed);
+
+        loop {
+            slot.next.store(head & LINK_MASK, Ordering::Relaxed);
+            match self.head.compare_exchange_weak(
+                head,
+                Self::next_head(head, index + 1),
+                Ordering::Release,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => return,
+                Err(current) => head = current,
+            }
+        }
+    }
+
+    fn pop(&self, slots: &Slots) -> Option<usize> {
+        let mut head = self.head.load(Ordering::Acquire);
+
+        loop {
+            let index = (head & LINK_MASK).checked_sub(1)?;
+            let next = slots.get(index)?.next.load(Ordering::Relaxed);
+
+            match self.head.compare_exchange_weak(
+                head,
+                Self::next_head(head, next),
+                Ordering::Acquire,
+                Ordering::Acquire,
+            ) {
+                Ok(_) => return Some(index),
+                Err(current) => head = current,
+            }
+        }
+    }
+
+    /// The indices in the list, from the one that would be popped last to the
+    /// one that would be popped first.
+    fn indices(&self, slots: &Slots) -> Vec<usize> {
+        let mut indices = Vec::new();
+        let mut link = self.head.load(Ordering::Acquire) & LINK_MASK;
+
+        while let Some(index) = link.checked_sub(1) {
+            indices.push(index);
+            link = slots
+                .get(index)
+                .map_or(0, |slot| slot.next.load(Ordering::Relaxed));
+        }
+        indices.reverse();
+
+        indices
+    }
+}
+
+/// Hands out IDs and takes them back, from any number of threads at once.
+///
+/// Nothing in it takes a lock: creating and retiring IDs are a few atomic
+/// operations each, apart from the rare time that retiring an ID needs a new
+/// bucket of slots for its generation. Threads that do a lot of both can
+/// each have a [worker](IdAllocator::worker) with a free list of its own, so
+/// they don't all contend on one.
 pub struct IdAllocator {
-    next_id: AtomicUsize,
+    shared: Arc<Shared>,
+    /// Indices retired through this allocator.
+    free: FreeList,
+}
+
+/// The part of an allocator that its workers share with it.
+struct Shared {
+    next_index: AtomicUsize,
+    slots: Slots,
+    /// One past the highest index that has ever been retired.
+    retired_len: AtomicUsize,
+}
+
+/// Everything needed to recreate an [`IdAllocator`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct AllocatorState {
+    /// The next index that has never been handed out.
+    pub next_index: usize,
+    /// Retired indices, in the order they'll be handed out again (last first).
+    pub free: Vec<usize>,
+    /// Current generation of each index, as far as any have been retired.
+    pub generations: Vec<usize>,
 }
 
 impl IdAllocator {
@@ -11,24 +206,315 @@ impl IdAllocator {
 
     pub fn new_at(next_id: usize) -> Self {
         Self {
-            next_id: AtomicUsize::new(next_id),
+            shared: Arc::new(Shared {
+                next_index: AtomicUsize::new(next_id),
+                slots: Slots::new(),
+                retired_len: AtomicUsize::new(0),
+            }),
+            free: FreeList::new(),
+        }
+    }
+
+    /// Makes an allocator that hands out IDs from the same range as this one,
+    /// but keeps the IDs it retires to itself. A parallel runtime gives one
+    /// to each worker, so workers recycling IDs never touch the same free
+    /// list. Give it back with [`IdAllocator::join`] once it's done.
+    pub fn worker(&self) -> Self {
+        Self {
+            shared: Arc::clone(&self.shared),
+            free: FreeList::new(),
         }
     }
 
-    pub unsafe fn set_next_id(&self, value: usize) {
-        self.next_id
-            .store(value, std::sync::atomic::Ordering::Relaxed);
+    /// Takes back the IDs that a [worker](IdAllocator::worker) retired and
+    /// didn't reuse, so this allocator can hand them out again.
+    pub fn join(&self, worker: Self) {
+        assert!(
+            Arc::ptr_eq(&self.shared, &worker.shared),
+            "can only join a worker of this allocator"
+        );
+
+        let slots = &self.shared.slots;
+        while let Some(index) = worker.free.pop(slots) {
+            self.free.push(slots, index);
+        }
     }
 
-    /// Gets the next available ID.
+    /// Recreates an allocator from a saved [`AllocatorState`].
+    pub fn from_state(state: AllocatorState) -> Self {
+        let alloc = Self::new_at(state.next_index);
+
+        let shared = &alloc.shared;
+
+        for (index, &generation) in state.generations.iter().enumerate() {
+            if generation != 0 {
+                let slot = shared.slots.get_or_add(index);
+                slot.generation.store(generation, Ordering::Relaxed);
+            }
+        }
+        shared
+            .retired_len
+            .store(state.generations.len(), Ordering::Relaxed);
+        for index in state.free {
+            alloc.free.push(&shared.slots, index);
+        }
+
+        alloc
+    }
+
+    /// Saves where the allocator is up to, so that it can be recreated later.
+    /// It should only be called while no other thread is using the allocator,
+    /// and after any workers have been joined.
+    pub fn state(&self) -> AllocatorState {
+        let shared = &self.shared;
+        let retired_len = shared.retired_len.load(Ordering::Acquire);
+
+        AllocatorState {
+            next_index: shared.next_index.load(Ordering::Relaxed),
+            free: self.free.indices(&shared.slots),
+            generations: (0..retired_len)
+                .map(|index| shared.slots.generation(index))
+                .collect(),
+        }
+    }
+
+    /// Gets the next available ID, reusing a retired one if there is one.
     pub fn create_id(&self) -> usize {
-        self.next_id
-            .fetch_add(1, std::sync::atomic::Ordering::Relaxed)
+        let shared = &self.shared;
+
+        match self.free.pop(&shared.slots) {
+            Some(index) => compose(index, shared.slots.generation(index)),
+            None => compose(shared.next_index.fetch_add(1, Ordering::Relaxed), 0),
+        }
     }
 
-    /// Retires an ID. Calling this is technically necessary.
+    /// Retires an ID so that its index can be reused. The reused ID will have a
+    /// newer generation, so any leftover references to this one are stale.
+    ///
+    /// Retiring an ID that is already stale does nothing.
     pub fn retire_id(&self, id: usize) {
-        // will need a lock while this function is executing
-        _ = id;
+        let (index, generation) = split(id);
+        let shared = &self.shared;
+        let slot = shared.slots.get_or_add(index);
+
+        // only one of several threads retiring the same ID gets to bump it
+        let bumped = (generation + 1) & GENERATION_MASK;
+        if slot
+            .generation
+            .compare_exchange(generation, bumped, Ordering::AcqRel, Ordering::Relaxed)
+            .is_err()
+        {
+            return;
+        }
+
+        shared.retired_len.fetch_max(index + 1, Ordering::Relaxed);
+        self.free.push(&shared.slots, index);
+    }
+
+    /// Returns whether the ID was handed out by this allocator and hasn't been
+    /// retired since.
+    pub fn is_live(&self, id: usize) -> bool {
+        let (index, generation) = split(id);
+
+        index < self.shared.next_index.load(Ordering::Relaxed)
+            && self.shared.slots.generation(index) == generation
+    }
+}
+
+impl Default for IdAllocator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Maps the IDs of a net onto a dense range starting at zero.
+#[derive(Default)]
+pub struct Renumbering {
+    map: HashMap<usize, usize>,
+}
+
+impl Renumbering {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Gets the new ID for `id`, assigning the next one if it hasn't been seen.
+    pub fn get(&mut self, id: usize) -> usize {
+        let next = self.map.len();
+        *self.map.entry(id).or_insert(next)
+    }
+
+    /// Creates an allocator that continues after the last assigned ID.
+    pub fn into_allocator(self) -> IdAllocator {
+        IdAllocator::new_at(self.map.len())
+    }
+}
+
+/// Renumbers a whole net so that its IDs are dense and all on generation zero,
+/// returning it along with an allocator that continues after it.
+pub fn compact(
+    connections: impl IntoIterator<Item = Connection>,
+) -> (Vec<Connection>, IdAllocator) {
+    let mut renumbering = Renumbering::new();
+
+    let connections = connections
+        .into_iter()
+        .map(|Connection(left, right)| {
+            Connection(
+                left.map_ids(&mut |id| renumbering.get(id)),
+                right.map_ids(&mut |id| renumbering.get(id)),
+            )
+        })
+        .collect();
+
+    (connections, renumbering.into_allocator())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+    use crate::net::term::{Agent, Port, Term};
+
+    #[test]
+    fn retired_ids_come_back_with_a_new_generation() {
+        let alloc = IdAllocator::new();
+        let a = alloc.create_id();
+        let b = alloc.create_id();
+
+        alloc.retire_id(a);
+        assert!(!alloc.is_live(a));
+        assert!(alloc.is_live(b));
+
+        let c = alloc.create_id();
+        assert_ne!(c, a);
+        assert_eq!(split(c), (split(a).0, 1));
+        assert!(alloc.is_live(c));
+
+        // retiring a stale ID doesn't free the index again
+        alloc.retire_id(a);
+        assert_eq!(split(alloc.create_id()).0, 2);
+    }
+
+    #[test]
+    fn state_round_trip() {
+        let alloc = IdAllocator::new();
+        let ids: Vec<_> = (0..5).map(|_| alloc.create_id()).collect();
+        alloc.retire_id(ids[1]);
+        alloc.retire_id(ids[3]);
+
+        let state = alloc.state();
+        let restored = IdAllocator::from_state(state.clone());
+        assert_eq!(restored.state(), state);
+        assert_eq!(restored.create_id(), alloc.create_id());
+        assert_eq!(restored.create_id(), alloc.create_id());
+        assert_eq!(restored.create_id(), alloc.create_id());
+    }
+
+    #[test]
+    fn recycles_ids_on_many_threads_at_once() {
+        let alloc = IdAllocator::new();
+
+        let created: Vec<_> = thread::scope(|scope| {
+            let handles: Vec<_> = (0..8)
+                .map(|_| {
+                    scope.spawn(|| {
+                        let mut created = Vec::new();
+                        for _ in 0..10_000 {
+                            let ids = [alloc.create_id(), alloc.create_id()];
+                            created.extend(ids);
+                            for id in ids {
+                                alloc.retire_id(id);
+                            }
+                        }
+                        created
+                    })
+                })
+                .collect();
+
+            handles
+                .into_iter()
+                .flat_map(|handle| handle.join().unwrap())
+                .collect()
+        });
+
+        // no ID was handed out twice, and no thread held more than two at once
+        let mut distinct = created.clone();
+        distinct.sort_unstable();
+        distinct.dedup();
+        assert_eq!(distinct.len(), created.len());
+        let state = alloc.state();
+        assert!(state.next_index <= 16);
+        assert_eq!(state.free.len(), state.next_index);
+    }
+
+    #[test]
+    fn workers_keep_the_ids_they_retire() {
+        let alloc = IdAllocator::new();
+        let (a, b) = (alloc.worker(), alloc.worker());
+
+        let id = a.create_id();
+        a.retire_id(id);
+        assert!(!b.is_live(id));
+        // only the worker that retired the index hands it out again
+        assert_eq!(split(b.create_id()).0, 1);
+        assert_eq!(split(alloc.create_id()).0, 2);
+        assert_eq!(split(a.create_id()), (0, 1));
+
+        let id = b.create_id();
+        b.retire_id(id);
+        assert!(alloc.state().free.is_empty());
+        alloc.join(b);
+        assert_eq!(alloc.state().free, [split(id).0]);
+        assert_eq!(alloc.create_id(), compose(split(id).0, 1));
+    }
+
+    #[test]
+    fn keeps_generations_across_buckets() {
+        let alloc = IdAllocator::new_at(100_000);
+        let indices = [0, 63, 64, 191, 192, 99_999];
+        for index in indices {
+            alloc.retire_id(index);
+        }
+
+        let state = alloc.state();
+        assert_eq!(state.generations.len(), 100_000);
+        for (index, &generation) in state.generations.iter().enumerate() {
+            assert_eq!(generation, indices.contains(&index) as usize);
+        }
+        assert_eq!(state.free, indices);
+        assert_eq!(IdAllocator::from_state(state.clone()).state(), state);
+    }
+
+    #[test]
+    #[should_panic(expected = "ran out of ID indices")]
+    fn running_out_of_indices_panics() {
+        let alloc = IdAllocator::new_at(INDEX_MASK);
+        alloc.create_id();
+        alloc.create_id();
+    }
+
+    #[test]
+    fn compact_makes_ids_dense() {
+        let alloc = IdAllocator::new();
+        for _ in 0..10 {
+            let id = alloc.create_id();
+            alloc.retire_id(id);
+        }
+        let port = Term::Port(Port::new(alloc.create_id()));
+        let agent = Agent::new_constructor(alloc.create_id(), port.clone(), port);
+        let root = Term::Port(Port::new(alloc.create_id()));
+
+        let (connections, alloc) = compact([Connection(root, Term::Agent(agent))]);
+
+        let Connection(root, Term::Agent(agent)) = &connections[0] else {
+            panic!("expected a port connected to an agent");
+        };
+        assert_eq!(*root.id(), 0);
+        assert_eq!(agent.id, 1);
+        assert_eq!(*agent.ports[0].id(), 2);
+        assert_eq!(*agent.ports[1].id(), 2);
+        assert_eq!(alloc.create_id(), 3);
     }
 }
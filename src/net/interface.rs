@@ -0,0 +1,215 @@
+//! The interface of a net: the ports that only show up once, because their
+//! other end is outside of the net. They keep their names through reduction,
+//! so they're how results are found once a net is in normal form.
+
+use rustc_hash::FxHashMap as HashMap;
+
+use super::{
+    connection::Connection,
+    term::{Port, Term},
+};
+
+/// Calls `f` with every port in a term, along with its ID.
+fn for_each_port<'a>(term: &'a Term, f: &mut impl FnMut(usize, &'a Port)) {
+    match term {
+        Term::Port(port) => f(*term.id(), port),
+        Term::Agent(agent) => agent.ports.iter().for_each(|port| for_each_port(port, f)),
+    }
+}
+
+/// Where each port shows up in a net, by the index of its connection.
+fn occurrences<'a>(connections: &[(&'a Term, &'a Term)]) -> HashMap<usize, (&'a Port, Vec<usize>)> {
+    let mut occurrences = HashMap::<_, (_, Vec<_>)>::default();
+    for (index, (left, right)) in connections.iter().enumerate() {
+        for term in [left, right] {
+            for_each_port(term, &mut |id, port| {
+                occurrences
+                    .entry(id)
+                    .or_insert((port, Vec::new()))
+                    .1
+                    .push(index)
+            });
+        }
+    }
+
+    occurrences
+}
+
+/// Finds every connection that can be reached from the given ones by
+/// following ports, in the order they're reached.
+fn reach(
+    connections: &[(&Term, &Term)],
+    occurrences: &HashMap<usize, (&Port, Vec<usize>)>,
+    starts: impl IntoIterator<Item = usize>,
+) -> Vec<usize> {
+    let mut reached = vec![false; connections.len()];
+    let mut queue = Vec::new();
+    for start in starts {
+        if !reached[start] {
+            reached[start] = true;
+            queue.push(start);
+        }
+    }
+
+    let mut index = 0;
+    while let Some(&connection) = queue.get(index) {
+        index += 1;
+
+        let (left, right) = connections[connection];
+        for term in [left, right] {
+            for_each_port(term, &mut |id, _| {
+                for &other in &occurrences[&id].1 {
+                    if !reached[other] {
+                        reached[other] = true;
+                        queue.push(other);
+                    }
+                }
+            });
+        }
+    }
+
+    queue
+}
+
+/// Which connections of a net can be reached from any of its free ports.
+pub fn reachable_from_interface<'a>(
+    connections: impl IntoIterator<Item = (&'a Term, &'a Term)>,
+) -> Vec<bool> {
+    let connections: Vec<_> = connections.into_iter().collect();
+    let occurrences = occurrences(&connections);
+
+    let starts = occurrences
+        .values()
+        .filter(|(_, uses)| uses.len() == 1)
+        .map(|(_, uses)| uses[0]);
+
+    let mut reachable = vec![false; connections.len()];
+    for index in reach(&connections, &occurrences, starts) {
+        reachable[index] = true;
+    }
+
+    reachable
+}
+
+/// The free ports of a net, sorted by ID.
+pub fn interface<'a>(connections: impl IntoIterator<Item = (&'a Term, &'a Term)>) -> Vec<&'a Port> {
+    let connections: Vec<_> = connections.into_iter().collect();
+
+    let mut free_ports: Vec<_> = occurrences(&connections)
+        .into_iter()
+        .filter(|(_, (_, uses))| uses.len() == 1)
+        .map(|(id, (port, _))| (id, port))
+        .collect();
+    free_ports.sort_by_key(|(id, _)| *id);
+
+    free_ports.into_iter().map(|(_, port)| port).collect()
+}
+
+/// Copies out the part of a net that can be reached from the free port called
+/// `name`, starting with the connection it's in. If more than one free port
+/// has that name, the one with the lowest ID is used.
+pub fn subnet<'a>(
+    connections: impl IntoIterator<Item = (&'a Term, &'a Term)>,
+    name: &str,
+) -> Option<Vec<Connection>> {
+    let connections: Vec<_> = connections.into_iter().collect();
+    let occurrences = occurrences(&connections);
+
+    let start = occurrences
+        .iter()
+        .filter(|(_, (port, uses))| uses.len() == 1 && port.name.as_deref() == Some(name))
+        .min_by_key(|(id, _)| **id)
+        .map(|(_, (_, uses))| uses[0])?;
+
+    let queue = reach(&connections, &occurrences, [start]);
+
+    Some(
+        queue
+            .into_iter()
+            .map(|index| {
+                let (left, right) = connections[index];
+                Connection(left.clone(), right.clone())
+            })
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        net::{canonical::CanonicalNet, id::IdAllocator, text::parse_connection},
+        rule::{context::RewriteContext, rulebook::Rulebook},
+        runtime::Runtime,
+    };
+
+    fn net(sources: &[&str]) -> Vec<Connection> {
+        sources
+            .iter()
+            .map(|src| parse_connection(src).unwrap())
+            .collect()
+    }
+
+    fn pairs(connections: &[Connection]) -> impl Iterator<Item = (&Term, &Term)> {
+        connections
+            .iter()
+            .map(|Connection(left, right)| (left, right))
+    }
+
+    const NET: [&str; 4] = [
+        r#"$3:"b" = Constructor#1($2, $4:"a")"#,
+        "$2 = Eraser#5()",
+        "Eraser#6() = Duplicator#7($8, $8)",
+        r#"$9 = Switch#10($11:"c", Eraser#12())"#,
+    ];
+
+    #[test]
+    fn finds_free_ports_in_order() {
+        let connections = net(&NET);
+        let names: Vec<_> = interface(pairs(&connections))
+            .into_iter()
+            .map(|port| port.name.as_deref())
+            .collect();
+
+        assert_eq!(names, [Some("b"), Some("a"), None, Some("c")]);
+    }
+
+    #[test]
+    fn finds_what_the_interface_reaches() {
+        let connections = net(&NET);
+
+        assert_eq!(
+            reachable_from_interface(pairs(&connections)),
+            [true, true, false, true]
+        );
+    }
+
+    #[test]
+    fn copies_out_a_subnet_by_name() {
+        let connections = net(&NET);
+
+        let subnet = subnet(pairs(&connections), "a").unwrap();
+        assert!(CanonicalNet::new(subnet) == CanonicalNet::new(net(&NET[..2])));
+        assert!(super::subnet(pairs(&connections), "missing").is_none());
+    }
+
+    #[test]
+    fn queries_a_result_after_reducing() {
+        let connections = net(&[
+            r#"Constructor#1($2:"x", $3:"y") = Constructor#4(Number{7}#5(), $6)"#,
+            "$6 = Eraser#7()",
+        ]);
+        let mut runtime = Runtime::new(
+            connections,
+            Rulebook::default(),
+            RewriteContext::new(IdAllocator::new_at(100)),
+        );
+        runtime.reduce();
+
+        let x = runtime.query("x").unwrap();
+        assert!(CanonicalNet::new(x) == CanonicalNet::new(net(&[r#"$0:"x" = Number{7}#1()"#])));
+        let y = runtime.query("y").unwrap();
+        assert!(CanonicalNet::new(y) == CanonicalNet::new(net(&[r#"$0:"y" = Eraser#1()"#])));
+        assert_eq!(runtime.interface().len(), 2);
+    }
+}
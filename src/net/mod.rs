@@ -1,3 +1,8 @@
+pub mod binary;
+pub mod canonical;
 pub mod connection;
+pub mod deadlock;
 pub mod id;
+pub mod interface;
 pub mod term;
+pub mod text;
@@ -3,14 +3,21 @@ use std::fmt::Debug;
 use crate::map::AsHashKey;
 
 pub use self::agent::{Agent, AgentKind};
+pub use self::operator::Operator;
 pub use self::port::Port;
 
 use super::connection::Connection;
 
 mod agent;
+mod operator;
 mod port;
 
 #[derive(Clone)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(tag = "type")
+)]
 pub enum Term {
     Agent(Agent),
     Port(Port),
@@ -31,7 +38,7 @@ impl std::borrow::Borrow<usize> for Term {
 }
 
 impl Term {
-    pub fn with_name(mut self, name: impl Into<String>) -> Self {
+    pub fn with_name(self, name: impl Into<String>) -> Self {
         match self {
             Term::Port(port) => Self::Port(port.with_name(name)),
             Term::Agent(agent) => Self::Agent(agent.with_name(name)),
@@ -45,6 +52,24 @@ impl Term {
         }
     }
 
+    /// Replaces the ID of this term and every term below it.
+    pub fn map_ids(self, f: &mut impl FnMut(usize) -> usize) -> Self {
+        match self {
+            Self::Port(mut port) => {
+                port.id = f(port.id);
+                Self::Port(port)
+            }
+            Self::Agent(mut agent) => {
+                agent.id = f(agent.id);
+                agent.ports = Vec::from(agent.ports)
+                    .into_iter()
+                    .map(|port| port.map_ids(f))
+                    .collect();
+                Self::Agent(agent)
+            }
+        }
+    }
+
     #[inline]
     pub fn connect(self, other: Term) -> Connection {
         Connection(self, other)
@@ -59,7 +84,7 @@ impl std::hash::Hash for Term {
 
 impl Ord for Term {
     fn cmp(&self, other: &Self) -> std::cmp::Ordering {
-        self.id().cmp(&other.id())
+        self.id().cmp(other.id())
     }
 }
 
@@ -1,13 +1,55 @@
 use std::fmt::Debug;
 
-use super::Term;
+use super::{Operator, Term};
 
-#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum AgentKind {
     Eraser,
     Duplicator,
     Constructor,
     Dynamic(usize),
+    /// A number, stored in the agent's data. It has no auxiliary ports.
+    Number,
+    /// Waits for a number on its principal port to use as the left operand.
+    /// Its ports are the right operand and the result.
+    BinaryOp(Operator),
+    /// A [`BinaryOp`](AgentKind::BinaryOp) that already has its left operand,
+    /// stored in its data. Waits for the right operand on its principal port,
+    /// and its one port is the result.
+    PartialOp(Operator),
+    /// Branches on a number on its principal port. Its ports are a pair of
+    /// branches and the result.
+    ///
+    /// When the number is zero, the branches are connected to a
+    /// `Constructor(result, Eraser)`, so the first branch becomes the result.
+    /// Otherwise, they're connected to a
+    /// `Constructor(Eraser, Constructor(n - 1, result))`, so the second branch
+    /// is applied to the predecessor.
+    Switch,
+    /// Stands for a definition in the rulebook, and expands into a fresh copy
+    /// of it when it meets another agent. Erasers and duplicators erase or
+    /// copy the reference itself instead. It has no auxiliary ports.
+    Reference(usize),
+}
+
+impl AgentKind {
+    /// Whether agents of this kind use their data.
+    pub fn has_data(&self) -> bool {
+        matches!(self, Self::Number | Self::PartialOp(_))
+    }
+
+    /// How many auxiliary ports agents of this kind have, or `None` for
+    /// [`Dynamic`](AgentKind::Dynamic) agents, which have as many as they're
+    /// declared with.
+    pub fn arity(&self) -> Option<usize> {
+        match self {
+            Self::Eraser | Self::Number | Self::Reference(_) => Some(0),
+            Self::PartialOp(_) => Some(1),
+            Self::Duplicator | Self::Constructor | Self::BinaryOp(_) | Self::Switch => Some(2),
+            Self::Dynamic(_) => None,
+        }
+    }
 }
 
 impl Debug for AgentKind {
@@ -17,24 +59,51 @@ impl Debug for AgentKind {
             Self::Duplicator => write!(f, "Duplicator"),
             Self::Constructor => write!(f, "Constructor"),
             Self::Dynamic(id) => write!(f, "Dynamic[{id}]"),
+            Self::Number => write!(f, "Number"),
+            Self::BinaryOp(op) => write!(f, "BinaryOp[{op:?}]"),
+            Self::PartialOp(op) => write!(f, "PartialOp[{op:?}]"),
+            Self::Switch => write!(f, "Switch"),
+            Self::Reference(id) => write!(f, "Reference[{id}]"),
         }
     }
 }
 
 #[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Agent {
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
     name: Option<String>,
     pub id: usize,
     pub kind: AgentKind,
+    /// A payload, like the value of a number. Zero for kinds that don't use it.
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "is_zero"))]
+    pub data: u64,
     pub ports: Box<[Term]>,
 }
 
+#[cfg(feature = "serde")]
+fn is_zero(data: &u64) -> bool {
+    *data == 0
+}
+
 impl Agent {
     pub fn with_name(mut self, name: impl Into<String>) -> Self {
         self.name = Some(name.into());
         self
     }
 
+    pub fn with_data(mut self, data: u64) -> Self {
+        self.data = data;
+        self
+    }
+
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
     pub fn ports_array<const N: usize>(self) -> Result<[Term; N], usize> {
         let array_boxed: Box<[Term; N]> =
             self.ports.try_into().map_err(|b: Box<[Term]>| b.len())?;
@@ -46,6 +115,7 @@ impl Agent {
             name: None,
             id,
             kind,
+            data: 0,
             ports: ports.into(),
         }
     }
@@ -55,10 +125,15 @@ impl Agent {
             name: None,
             id,
             kind: AgentKind::Eraser,
+            data: 0,
             ports: [].into(),
         }
     }
 
+    pub fn new_number(id: usize, value: u64) -> Self {
+        Self::new(id, AgentKind::Number, []).with_data(value)
+    }
+
     pub fn new_duplicator(id: usize, port_a: impl Into<Term>, port_b: impl Into<Term>) -> Self {
         Self::new_2_arity(id, AgentKind::Duplicator, port_a.into(), port_b.into())
     }
@@ -77,6 +152,7 @@ impl Agent {
             name: None,
             id,
             kind,
+            data: 0,
             ports: [port_a.into(), port_b.into()].into(),
         }
     }
@@ -86,6 +162,10 @@ impl Debug for Agent {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{:?}", self.kind)?;
 
+        if self.kind.has_data() || self.data != 0 {
+            write!(f, "{{{}}}", self.data)?;
+        }
+
         if let Some(name) = &self.name {
             write!(f, "<{},{}>(", self.id, name)?;
         } else {
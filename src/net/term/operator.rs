@@ -0,0 +1,70 @@
+use std::fmt::Debug;
+
+/// A binary operation on numbers.
+///
+/// Arithmetic wraps on overflow, and dividing by zero gives zero. Comparisons
+/// give one for true and zero for false.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Operator {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl Operator {
+    pub const ALL: [Operator; 10] = [
+        Self::Add,
+        Self::Sub,
+        Self::Mul,
+        Self::Div,
+        Self::Eq,
+        Self::Ne,
+        Self::Lt,
+        Self::Le,
+        Self::Gt,
+        Self::Ge,
+    ];
+
+    pub fn apply(self, left: u64, right: u64) -> u64 {
+        match self {
+            Self::Add => left.wrapping_add(right),
+            Self::Sub => left.wrapping_sub(right),
+            Self::Mul => left.wrapping_mul(right),
+            Self::Div => left.checked_div(right).unwrap_or(0),
+            Self::Eq => (left == right) as u64,
+            Self::Ne => (left != right) as u64,
+            Self::Lt => (left < right) as u64,
+            Self::Le => (left <= right) as u64,
+            Self::Gt => (left > right) as u64,
+            Self::Ge => (left >= right) as u64,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wraps_divides_by_zero_and_compares_as_documented() {
+        assert_eq!(Operator::Add.apply(u64::MAX, 2), 1);
+        assert_eq!(Operator::Sub.apply(1, 2), u64::MAX);
+        assert_eq!(Operator::Mul.apply(u64::MAX, 2), u64::MAX - 1);
+        assert_eq!(Operator::Div.apply(7, 2), 3);
+        assert_eq!(Operator::Div.apply(7, 0), 0);
+
+        let compared: Vec<_> = Operator::ALL[4..]
+            .iter()
+            .map(|op| (op.apply(1, 2), op.apply(2, 2)))
+            .collect();
+        assert_eq!(compared, [(0, 1), (1, 0), (1, 0), (1, 1), (0, 0), (0, 1)]);
+    }
+}
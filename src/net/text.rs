@@ -0,0 +1,364 @@
+//! A text format for terms that keeps their IDs and names, so that a net can be
+//! written out and read back exactly. It looks like this:
+//!
+//! ```text
+//! Constructor#4:"b"(Constructor#2(Eraser#3(), $1), $0:"out")
+//! ```
+//!
+//! Agents are their kind, `#` and their ID, followed by their ports in
+//! parentheses. Agents with data have it in braces after their kind, like
+//! `Number{42}#7()`. Ports are `$` and their ID. Either can be followed by `:`
+//! and a quoted name.
+
+use std::fmt::{self, Write};
+
+use super::{
+    connection::Connection,
+    term::{Agent, AgentKind, Operator, Port, Term},
+};
+
+pub fn write_term(f: &mut impl Write, term: &Term) -> fmt::Result {
+    match term {
+        Term::Port(port) => {
+            write!(f, "${}", term.id())?;
+            write_name(f, port.name.as_deref())
+        }
+        Term::Agent(agent) => {
+            write_kind(f, agent.kind)?;
+            if agent.kind.has_data() || agent.data != 0 {
+                write!(f, "{{{}}}", agent.data)?;
+            }
+            write!(f, "#{}", agent.id)?;
+            write_name(f, agent.name())?;
+
+            write!(f, "(")?;
+            for (index, port) in agent.ports.iter().enumerate() {
+                if index > 0 {
+                    write!(f, ", ")?;
+                }
+                write_term(f, port)?;
+            }
+            write!(f, ")")
+        }
+    }
+}
+
+pub fn write_connection(f: &mut impl Write, left: &Term, right: &Term) -> fmt::Result {
+    write_term(f, left)?;
+    write!(f, " = ")?;
+    write_term(f, right)
+}
+
+fn write_kind(f: &mut impl Write, kind: AgentKind) -> fmt::Result {
+    match kind {
+        AgentKind::Eraser => write!(f, "Eraser"),
+        AgentKind::Duplicator => write!(f, "Duplicator"),
+        AgentKind::Constructor => write!(f, "Constructor"),
+        AgentKind::Dynamic(id) => write!(f, "Dynamic[{id}]"),
+        AgentKind::Number => write!(f, "Number"),
+        AgentKind::BinaryOp(op) => write!(f, "BinaryOp[{op:?}]"),
+        AgentKind::PartialOp(op) => write!(f, "PartialOp[{op:?}]"),
+        AgentKind::Switch => write!(f, "Switch"),
+        AgentKind::Reference(id) => write!(f, "Reference[{id}]"),
+    }
+}
+
+fn write_name(f: &mut impl Write, name: Option<&str>) -> fmt::Result {
+    let Some(name) = name else {
+        return Ok(());
+    };
+
+    write!(f, ":\"")?;
+    for c in name.chars() {
+        match c {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            c => f.write_char(c)?,
+        }
+    }
+    write!(f, "\"")
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParseError {
+    /// Byte offset into the source where parsing failed.
+    pub position: usize,
+    pub message: String,
+}
+
+impl ParseError {
+    /// The line and column of the error in `src`, both counted from one.
+    pub fn line_column(&self, src: &str) -> (usize, usize) {
+        let before = &src[..self.position];
+        let line_start = before.rfind('\n').map_or(0, |index| index + 1);
+
+        (
+            before.matches('\n').count() + 1,
+            before[line_start..].chars().count() + 1,
+        )
+    }
+}
+
+pub struct Parser<'a> {
+    src: &'a str,
+    position: usize,
+}
+
+impl<'a> Parser<'a> {
+    pub fn new(src: &'a str) -> Self {
+        Self { src, position: 0 }
+    }
+
+    fn error(&self, message: impl Into<String>) -> ParseError {
+        ParseError {
+            position: self.position,
+            message: message.into(),
+        }
+    }
+
+    fn rest(&self) -> &'a str {
+        &self.src[self.position..]
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = self.rest();
+        self.position += rest.len() - rest.trim_start().len();
+    }
+
+    fn eat(&mut self, token: &str) -> bool {
+        self.skip_whitespace();
+
+        if self.rest().starts_with(token) {
+            self.position += token.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, token: &str) -> Result<(), ParseError> {
+        if self.eat(token) {
+            Ok(())
+        } else {
+            Err(self.error(format!("expected `{token}`")))
+        }
+    }
+
+    fn operator(&mut self) -> Result<Operator, ParseError> {
+        self.expect("[")?;
+        let operator = Operator::ALL
+            .into_iter()
+            .find(|op| self.eat(&format!("{op:?}")))
+            .ok_or_else(|| self.error("expected an operator"))?;
+        self.expect("]")?;
+
+        Ok(operator)
+    }
+
+    fn number<T: std::str::FromStr>(&mut self) -> Result<T, ParseError> {
+        self.skip_whitespace();
+
+        let rest = self.rest();
+        let len = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(rest.len());
+
+        let number = rest[..len]
+            .parse()
+            .map_err(|_| self.error("expected a number"))?;
+        self.position += len;
+
+        Ok(number)
+    }
+
+    fn name(&mut self) -> Result<Option<String>, ParseError> {
+        if !self.eat(":") {
+            return Ok(None);
+        }
+        self.expect("\"")?;
+
+        let mut name = String::new();
+        let mut chars = self.rest().char_indices();
+
+        while let Some((offset, c)) = chars.next() {
+            match c {
+                '"' => {
+                    self.position += offset + 1;
+                    return Ok(Some(name));
+                }
+                '\\' => match chars.next() {
+                    Some((_, 'n')) => name.push('\n'),
+                    Some((_, c)) => name.push(c),
+                    None => break,
+                },
+                c => name.push(c),
+            }
+        }
+
+        self.position = self.src.len();
+        Err(self.error("unterminated name"))
+    }
+
+    fn kind(&mut self) -> Result<AgentKind, ParseError> {
+        if self.eat("Eraser") {
+            Ok(AgentKind::Eraser)
+        } else if self.eat("Duplicator") {
+            Ok(AgentKind::Duplicator)
+        } else if self.eat("Constructor") {
+            Ok(AgentKind::Constructor)
+        } else if self.eat("Dynamic") {
+            self.expect("[")?;
+            let id = self.number()?;
+            self.expect("]")?;
+            Ok(AgentKind::Dynamic(id))
+        } else if self.eat("Number") {
+            Ok(AgentKind::Number)
+        } else if self.eat("BinaryOp") {
+            Ok(AgentKind::BinaryOp(self.operator()?))
+        } else if self.eat("PartialOp") {
+            Ok(AgentKind::PartialOp(self.operator()?))
+        } else if self.eat("Switch") {
+            Ok(AgentKind::Switch)
+        } else if self.eat("Reference") {
+            self.expect("[")?;
+            let id = self.number()?;
+            self.expect("]")?;
+            Ok(AgentKind::Reference(id))
+        } else {
+            Err(self.error("expected a port or an agent kind"))
+        }
+    }
+
+    pub fn term(&mut self) -> Result<Term, ParseError> {
+        if self.eat("$") {
+            let mut port = Port::new(self.number()?);
+            port.name = self.name()?;
+            return Ok(Term::Port(port));
+        }
+
+        let kind = self.kind()?;
+        let data = if self.eat("{") {
+            let data = self.number()?;
+            self.expect("}")?;
+            data
+        } else {
+            0
+        };
+        self.expect("#")?;
+        let id = self.number()?;
+        let name = self.name()?;
+
+        self.expect("(")?;
+        let mut ports = Vec::new();
+        if !self.eat(")") {
+            loop {
+                ports.push(self.term()?);
+                if self.eat(")") {
+                    break;
+                }
+                self.expect(",")?;
+            }
+        }
+
+        let agent = Agent::new(id, kind, ports).with_data(data);
+        Ok(Term::Agent(match name {
+            Some(name) => agent.with_name(name),
+            None => agent,
+        }))
+    }
+
+    pub fn connection(&mut self) -> Result<Connection, ParseError> {
+        let left = self.term()?;
+        self.expect("=")?;
+        let right = self.term()?;
+
+        Ok(Connection(left, right))
+    }
+
+    /// Checks that there's nothing but whitespace left.
+    pub fn finish(mut self) -> Result<(), ParseError> {
+        self.skip_whitespace();
+
+        if self.rest().is_empty() {
+            Ok(())
+        } else {
+            Err(self.error("unexpected trailing input"))
+        }
+    }
+}
+
+pub fn parse_connection(src: &str) -> Result<Connection, ParseError> {
+    let mut parser = Parser::new(src);
+    let connection = parser.connection()?;
+    parser.finish()?;
+
+    Ok(connection)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write(connection: &Connection) -> String {
+        let mut text = String::new();
+        write_connection(&mut text, connection.left(), connection.right()).unwrap();
+        text
+    }
+
+    fn round_trip(src: &str) {
+        let connection = parse_connection(src).unwrap();
+        assert_eq!(write(&connection), src);
+    }
+
+    #[test]
+    fn round_trips_every_kind() {
+        let mut kinds = vec![
+            "Eraser#1()".to_string(),
+            "Duplicator#2($3, $4)".to_string(),
+            "Constructor#5($6, $7)".to_string(),
+            "Dynamic[3]#8($9)".to_string(),
+            "Number{42}#10()".to_string(),
+            "Switch#11($12, $13)".to_string(),
+            "Reference[0]#14()".to_string(),
+        ];
+        for (index, op) in Operator::ALL.into_iter().enumerate() {
+            kinds.push(format!("BinaryOp[{op:?}]#{}($0, $0)", 100 + index));
+            kinds.push(format!("PartialOp[{op:?}]{{7}}#{}($0)", 200 + index));
+        }
+
+        for kind in kinds {
+            round_trip(&format!("$0 = {kind}"));
+        }
+    }
+
+    #[test]
+    fn round_trips_names_and_nesting() {
+        round_trip(r#"$0:"out" = Constructor#4:"b"(Constructor#2(Eraser#3(), $1), $0:"out")"#);
+        round_trip(r#"$1:"quote \" and \\ and \n" = Eraser#2:"e"()"#);
+        round_trip("Constructor#1($2, $3) = Duplicator#4($2, $3)");
+    }
+
+    #[test]
+    fn keeps_ids_and_data() {
+        let Connection(left, right) = parse_connection("$9 = Number{5}#12()").unwrap();
+
+        assert_eq!(*left.id(), 9);
+        let Term::Agent(agent) = right else {
+            panic!("expected an agent");
+        };
+        assert_eq!(agent.id, 12);
+        assert_eq!(agent.kind, AgentKind::Number);
+        assert_eq!(agent.data, 5);
+    }
+
+    #[test]
+    fn reports_where_parsing_failed() {
+        let src = "$0 = Constructor#1($2,\n  Blob#3())";
+        let err = parse_connection(src).unwrap_err();
+        assert_eq!(err.line_column(src), (2, 3));
+
+        assert!(parse_connection("$0 = $1 extra").is_err());
+        assert!(parse_connection(r#"$0:"open = $1"#).is_err());
+    }
+}
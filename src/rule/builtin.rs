@@ -1,10 +1,11 @@
 use crate::net::{
     connection::Connection,
-    term::{Agent, AgentKind, Term},
+    term::{Agent, AgentKind, Operator, Term},
 };
 
 use super::{context::RewriteContext, rulebook::ActivePairPattern, RewriteResult};
 
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Builtin {
     EraEra,
     CtrCtr,
@@ -12,13 +13,62 @@ pub enum Builtin {
     DupEra,
     CtrEra,
     CtrDup,
+    NumEra,
+    NumDup,
+    /// A [`BinaryOp`](AgentKind::BinaryOp) receiving its left operand.
+    OpNum(Operator),
+    /// A [`PartialOp`](AgentKind::PartialOp) receiving its right operand.
+    PartialNum(Operator),
+    SwitchNum,
+    /// Erases a [`BinaryOp`](AgentKind::BinaryOp) by erasing both of its
+    /// ports.
+    OpEra(Operator),
+    /// Duplicates a [`BinaryOp`](AgentKind::BinaryOp) like
+    /// [`DynDup`](Builtin::DynDup) does.
+    OpDup(Operator),
+    /// Erases a [`PartialOp`](AgentKind::PartialOp) and its output port.
+    PartialEra(Operator),
+    /// Duplicates a [`PartialOp`](AgentKind::PartialOp) along with the
+    /// operand it holds.
+    PartialDup(Operator),
+    SwitchEra,
+    SwitchDup,
+    /// Erases a declared [`Dynamic`](AgentKind::Dynamic) agent by erasing each
+    /// of its ports. See [`Rulebook::declare_agent`].
+    ///
+    /// [`Rulebook::declare_agent`]: super::rulebook::Rulebook::declare_agent
+    DynEra {
+        id: usize,
+        arity: usize,
+    },
+    /// Duplicates a declared [`Dynamic`](AgentKind::Dynamic) agent by making
+    /// two copies of it and a duplicator for each of its ports.
+    DynDup {
+        id: usize,
+        arity: usize,
+    },
 }
 
 impl Builtin {
     pub fn all() -> Vec<Self> {
         use Builtin::*;
 
-        vec![EraEra, CtrCtr, DupDup, DupEra, CtrEra, CtrDup]
+        let mut all = vec![
+            EraEra, CtrCtr, DupDup, DupEra, CtrEra, CtrDup, NumEra, NumDup, SwitchNum, SwitchEra,
+            SwitchDup,
+        ];
+        for op in Operator::ALL {
+            all.extend([
+                OpNum(op),
+                PartialNum(op),
+                OpEra(op),
+                OpDup(op),
+                PartialEra(op),
+                PartialDup(op),
+            ]);
+        }
+
+        all
     }
 
     pub fn pattern(&self) -> ActivePairPattern {
@@ -32,6 +82,19 @@ impl Builtin {
             DupEra => ActivePairPattern::new(Duplicator, Eraser),
             CtrEra => ActivePairPattern::new(Constructor, Eraser),
             CtrDup => ActivePairPattern::new(Constructor, Duplicator),
+            NumEra => ActivePairPattern::new(Number, Eraser),
+            NumDup => ActivePairPattern::new(Number, Duplicator),
+            OpNum(op) => ActivePairPattern::new(BinaryOp(*op), Number),
+            PartialNum(op) => ActivePairPattern::new(PartialOp(*op), Number),
+            SwitchNum => ActivePairPattern::new(Switch, Number),
+            OpEra(op) => ActivePairPattern::new(BinaryOp(*op), Eraser),
+            OpDup(op) => ActivePairPattern::new(BinaryOp(*op), Duplicator),
+            PartialEra(op) => ActivePairPattern::new(PartialOp(*op), Eraser),
+            PartialDup(op) => ActivePairPattern::new(PartialOp(*op), Duplicator),
+            SwitchEra => ActivePairPattern::new(Switch, Eraser),
+            SwitchDup => ActivePairPattern::new(Switch, Duplicator),
+            DynEra { id, .. } => ActivePairPattern::new(Dynamic(*id), Eraser),
+            DynDup { id, .. } => ActivePairPattern::new(Dynamic(*id), Duplicator),
         }
     }
 
@@ -60,13 +123,16 @@ impl Builtin {
                 }
             }
             Self::CtrDup => {
-                let ctr = a;
-                let dup = b;
+                let dup = a;
+                let ctr = b;
                 assert!(ctr.kind == AgentKind::Constructor);
                 assert!(ctr.ports.len() == 2);
                 assert!(dup.kind == AgentKind::Duplicator);
                 assert!(dup.ports.len() == 2);
 
+                ctx.id_alloc.retire_id(ctr.id);
+                ctx.id_alloc.retire_id(dup.id);
+
                 let [ctr_a_in, ctr_b_in] = ctr.ports_array().unwrap();
                 let [dup_a_in, dup_b_in] = dup.ports_array().unwrap();
 
@@ -83,7 +149,7 @@ impl Builtin {
 
                 let mut new_connections: Vec<_> = [w0, w1, w2, w3]
                     .into_iter()
-                    .map(Clone::clone)
+                    .cloned()
                     .map(Into::into)
                     .collect();
 
@@ -119,26 +185,439 @@ impl Builtin {
 
                 RewriteResult { new_connections }
             }
-            Self::DupEra | Self::CtrEra => handle_dup_or_ctr_to_era(a, b),
+            Self::DupEra | Self::CtrEra => handle_dup_or_ctr_to_era(ctx, b, a),
+            Self::NumEra => {
+                ctx.id_alloc.retire_id(a.id);
+                ctx.id_alloc.retire_id(b.id);
+
+                RewriteResult::empty()
+            }
+            Self::NumDup => {
+                let dup = a;
+                let num = b;
+                assert!(dup.kind == AgentKind::Duplicator);
+                assert!(num.kind == AgentKind::Number);
+
+                ctx.id_alloc.retire_id(dup.id);
+                ctx.id_alloc.retire_id(num.id);
+
+                let [out_a, out_b] = dup.ports_array().unwrap();
+
+                RewriteResult {
+                    new_connections: vec![
+                        ctx.create_number(num.data).connect(out_a),
+                        ctx.create_number(num.data).connect(out_b),
+                    ],
+                }
+            }
+            Self::OpNum(operator) => {
+                let num = a;
+                let op = b;
+                assert!(num.kind == AgentKind::Number);
+                assert!(op.kind == AgentKind::BinaryOp(*operator));
+
+                ctx.id_alloc.retire_id(num.id);
+                ctx.id_alloc.retire_id(op.id);
+
+                let [right, out] = op.ports_array().unwrap();
+                let partial = Agent::new(
+                    ctx.id_alloc.create_id(),
+                    AgentKind::PartialOp(*operator),
+                    [out],
+                )
+                .with_data(num.data);
+
+                RewriteResult {
+                    new_connections: vec![Term::Agent(partial).connect(right)],
+                }
+            }
+            Self::PartialNum(operator) => {
+                let num = a;
+                let partial = b;
+                assert!(num.kind == AgentKind::Number);
+                assert!(partial.kind == AgentKind::PartialOp(*operator));
+
+                ctx.id_alloc.retire_id(partial.id);
+                ctx.id_alloc.retire_id(num.id);
+
+                let result = ctx.create_number(operator.apply(partial.data, num.data));
+                let [out] = partial.ports_array().unwrap();
+
+                RewriteResult {
+                    new_connections: vec![result.connect(out)],
+                }
+            }
+            Self::SwitchNum => {
+                let num = a;
+                let switch = b;
+                assert!(num.kind == AgentKind::Number);
+                assert!(switch.kind == AgentKind::Switch);
+
+                ctx.id_alloc.retire_id(num.id);
+                ctx.id_alloc.retire_id(switch.id);
+
+                let [branches, out] = switch.ports_array().unwrap();
+                let era = Term::Agent(Agent::new_eraser(ctx.id_alloc.create_id()));
+
+                let selector = if num.data == 0 {
+                    Agent::new_constructor(ctx.id_alloc.create_id(), out, era)
+                } else {
+                    let pred = ctx.create_number(num.data - 1);
+                    let applied = Agent::new_constructor(ctx.id_alloc.create_id(), pred, out);
+                    Agent::new_constructor(ctx.id_alloc.create_id(), era, applied)
+                };
+
+                RewriteResult {
+                    new_connections: vec![Term::Agent(selector).connect(branches)],
+                }
+            }
+            Self::OpEra(operator) | Self::OpDup(operator) => {
+                assert!(b.kind == AgentKind::BinaryOp(*operator));
+
+                match self {
+                    Self::OpEra(_) => erase_agent(ctx, b, a),
+                    _ => duplicate_agent(ctx, b, a),
+                }
+            }
+            Self::PartialEra(operator) | Self::PartialDup(operator) => {
+                assert!(b.kind == AgentKind::PartialOp(*operator));
+
+                match self {
+                    Self::PartialEra(_) => erase_agent(ctx, b, a),
+                    _ => duplicate_agent(ctx, b, a),
+                }
+            }
+            Self::SwitchEra => {
+                assert!(b.kind == AgentKind::Switch);
+
+                erase_agent(ctx, b, a)
+            }
+            Self::SwitchDup => {
+                assert!(b.kind == AgentKind::Switch);
+
+                duplicate_agent(ctx, b, a)
+            }
+            Self::DynEra { id, arity } => {
+                assert!(b.kind == AgentKind::Dynamic(*id));
+                assert!(b.ports.len() == *arity);
+
+                erase_agent(ctx, b, a)
+            }
+            Self::DynDup { id, arity } => {
+                assert!(b.kind == AgentKind::Dynamic(*id));
+                assert!(b.ports.len() == *arity);
+
+                duplicate_agent(ctx, b, a)
+            }
         }
     }
 }
 
-fn handle_dup_or_ctr_to_era(dup_or_ctr: Agent, era: Agent) -> RewriteResult {
+fn handle_dup_or_ctr_to_era(ctx: &RewriteContext, dup_or_ctr: Agent, era: Agent) -> RewriteResult {
     assert!(dup_or_ctr.ports.len() == 2);
     assert!(era.kind == AgentKind::Eraser);
-    assert!(era.ports.len() == 0);
+    assert!(era.ports.is_empty());
 
-    // we can reuse these IDs
-    let era_a_id = dup_or_ctr.id;
-    let era_b_id = era.id;
+    ctx.id_alloc.retire_id(dup_or_ctr.id);
+    ctx.id_alloc.retire_id(era.id);
 
     let [a, b] = dup_or_ctr.ports_array().unwrap();
 
-    let era_a = Agent::new_eraser(era_a_id);
-    let era_b = Agent::new_eraser(era_b_id);
+    let era_a = Agent::new_eraser(ctx.id_alloc.create_id());
+    let era_b = Agent::new_eraser(ctx.id_alloc.create_id());
 
     let new_connections = vec![Term::Agent(era_a).connect(a), Term::Agent(era_b).connect(b)];
 
     RewriteResult { new_connections }
 }
+
+/// Connects an eraser to each port of `agent`.
+fn erase_agent(ctx: &RewriteContext, agent: Agent, era: Agent) -> RewriteResult {
+    assert!(era.kind == AgentKind::Eraser);
+
+    ctx.id_alloc.retire_id(agent.id);
+    ctx.id_alloc.retire_id(era.id);
+
+    let new_connections = Vec::from(agent.ports)
+        .into_iter()
+        .map(|port| Term::Agent(Agent::new_eraser(ctx.id_alloc.create_id())).connect(port))
+        .collect();
+
+    RewriteResult { new_connections }
+}
+
+/// Makes two copies of `agent`, one for each port of the duplicator, and
+/// connects a duplicator to each port of `agent` that feeds into the
+/// corresponding ports of the copies.
+fn duplicate_agent(ctx: &RewriteContext, agent: Agent, dup: Agent) -> RewriteResult {
+    assert!(dup.kind == AgentKind::Duplicator);
+
+    ctx.id_alloc.retire_id(agent.id);
+    ctx.id_alloc.retire_id(dup.id);
+
+    let (kind, data) = (agent.kind, agent.data);
+
+    let [dup_a_out, dup_b_out] = dup.ports_array().unwrap();
+
+    let mut copy_a_ports = Vec::with_capacity(agent.ports.len());
+    let mut copy_b_ports = Vec::with_capacity(agent.ports.len());
+    let mut new_connections = Vec::with_capacity(agent.ports.len() + 2);
+
+    for port in Vec::from(agent.ports) {
+        let a = ctx.create_port();
+        let b = ctx.create_port();
+
+        let port_dup = ctx.create_agent(AgentKind::Duplicator, &[a.clone(), b.clone()]);
+        new_connections.push(port_dup.connect(port));
+
+        copy_a_ports.push(a);
+        copy_b_ports.push(b);
+    }
+
+    let copy_a = Agent::new(ctx.id_alloc.create_id(), kind, copy_a_ports).with_data(data);
+    let copy_b = Agent::new(ctx.id_alloc.create_id(), kind, copy_b_ports).with_data(data);
+
+    new_connections.push(Term::Agent(copy_a).connect(dup_a_out));
+    new_connections.push(Term::Agent(copy_b).connect(dup_b_out));
+
+    RewriteResult { new_connections }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        net::{id::IdAllocator, text::parse_connection},
+        rule::rulebook::Rulebook,
+        runtime::Runtime,
+    };
+
+    fn agent_ids(term: &Term, ids: &mut Vec<usize>) {
+        if let Term::Agent(agent) = term {
+            ids.push(agent.id);
+            agent.ports.iter().for_each(|port| agent_ids(port, ids));
+        }
+    }
+
+    /// Rewrites a pair and checks that both of its agents were retired, and
+    /// that every agent in the result has a live ID.
+    fn rewrite(ctx: &RewriteContext, rule: Builtin, a: Term, b: Term) -> Vec<Connection> {
+        let (Term::Agent(a), Term::Agent(b)) = (a, b) else {
+            panic!("expected two agents");
+        };
+        let consumed = [a.id, b.id];
+        let pattern = rule.pattern();
+        let kinds = pattern.pattern();
+
+        let result = rule.rewrite(ctx, a, b);
+
+        for id in consumed {
+            assert!(!ctx.id_alloc.is_live(id), "{kinds:?} didn't retire {id}");
+        }
+        let mut ids = Vec::new();
+        for Connection(left, right) in &result.new_connections {
+            agent_ids(left, &mut ids);
+            agent_ids(right, &mut ids);
+        }
+        for id in ids {
+            assert!(
+                ctx.id_alloc.is_live(id),
+                "{kinds:?} made an agent with a dead ID"
+            );
+        }
+
+        result.new_connections
+    }
+
+    #[test]
+    fn rules_retire_the_agents_they_consume() {
+        use AgentKind::*;
+
+        let ctx = RewriteContext::new(IdAllocator::new());
+        let era = || ctx.create_agent(Eraser, &[]);
+        let pair = |kind| ctx.create_agent(kind, &[ctx.create_port(), ctx.create_port()]);
+
+        rewrite(&ctx, Builtin::CtrEra, era(), pair(Constructor));
+        rewrite(&ctx, Builtin::DupEra, era(), pair(Duplicator));
+        rewrite(&ctx, Builtin::CtrDup, pair(Duplicator), pair(Constructor));
+        rewrite(
+            &ctx,
+            Builtin::NumDup,
+            pair(Duplicator),
+            ctx.create_number(3),
+        );
+        rewrite(&ctx, Builtin::NumEra, ctx.create_number(3), era());
+        rewrite(&ctx, Builtin::SwitchNum, ctx.create_number(0), pair(Switch));
+        rewrite(&ctx, Builtin::SwitchNum, ctx.create_number(2), pair(Switch));
+        rewrite(
+            &ctx,
+            Builtin::OpNum(Operator::Add),
+            ctx.create_number(1),
+            pair(BinaryOp(Operator::Add)),
+        );
+        rewrite(
+            &ctx,
+            Builtin::PartialNum(Operator::Add),
+            ctx.create_number(1),
+            ctx.create_agent(PartialOp(Operator::Add), &[ctx.create_port()]),
+        );
+        for op in Operator::ALL {
+            rewrite(&ctx, Builtin::OpEra(op), era(), pair(BinaryOp(op)));
+            rewrite(
+                &ctx,
+                Builtin::OpDup(op),
+                pair(Duplicator),
+                pair(BinaryOp(op)),
+            );
+
+            let partial = || ctx.create_agent(PartialOp(op), &[ctx.create_port()]);
+            rewrite(&ctx, Builtin::PartialEra(op), era(), partial());
+            rewrite(&ctx, Builtin::PartialDup(op), pair(Duplicator), partial());
+        }
+        rewrite(&ctx, Builtin::SwitchEra, era(), pair(Switch));
+        rewrite(&ctx, Builtin::SwitchDup, pair(Duplicator), pair(Switch));
+        rewrite(
+            &ctx,
+            Builtin::DynEra { id: 0, arity: 3 },
+            era(),
+            ctx.create_agent(
+                Dynamic(0),
+                &[ctx.create_port(), ctx.create_port(), ctx.create_port()],
+            ),
+        );
+        rewrite(
+            &ctx,
+            Builtin::DynDup { id: 0, arity: 1 },
+            pair(Duplicator),
+            ctx.create_agent(Dynamic(0), &[ctx.create_port()]),
+        );
+    }
+
+    /// Reduces a net given in the text format to normal form.
+    fn normalize(sources: &[&str]) -> Vec<(Term, Term)> {
+        let connections: Vec<_> = sources
+            .iter()
+            .map(|src| parse_connection(src).unwrap())
+            .collect();
+        let ctx = RewriteContext::new(IdAllocator::new_at(100));
+
+        Runtime::new(connections, Rulebook::default(), ctx)
+            .normalize()
+            .into_iter()
+            .collect()
+    }
+
+    /// The agent in a connection between an agent and a port.
+    fn agent_on_port<'a>(left: &'a Term, right: &'a Term) -> &'a Agent {
+        match (left, right) {
+            (Term::Port(_), Term::Agent(agent)) | (Term::Agent(agent), Term::Port(_)) => agent,
+            _ => panic!("expected a port connected to an agent"),
+        }
+    }
+
+    #[test]
+    fn erasing_operators_and_switches_erases_their_ports() {
+        let normal_form = normalize(&[
+            r#"Eraser#1() = BinaryOp[Mul]#2($3:"a", $4:"b")"#,
+            r#"Eraser#5() = PartialOp[Sub]{3}#6($7:"c")"#,
+            r#"Eraser#8() = Switch#9($10:"d", $11:"e")"#,
+        ]);
+
+        assert_eq!(normal_form.len(), 5);
+        for (left, right) in &normal_form {
+            assert!(agent_on_port(left, right).kind == AgentKind::Eraser);
+        }
+    }
+
+    #[test]
+    fn duplicated_operators_apply_to_each_copy() {
+        // a partial `5 -`, duplicated and then applied to 1 and to 2
+        let normal_form = normalize(&[
+            "Duplicator#1($2, $3) = PartialOp[Sub]{5}#4($5)",
+            "$2 = Number{1}#6()",
+            "$3 = Number{2}#7()",
+            r#"$5 = $8:"out""#,
+        ]);
+
+        let (_, dup) = normal_form
+            .iter()
+            .find(
+                |(left, _)| matches!(left, Term::Port(port) if port.name.as_deref() == Some("out")),
+            )
+            .expect("expected the output port to be connected");
+        let Term::Agent(dup) = dup else {
+            panic!("expected the output port connected to an agent");
+        };
+        assert!(dup.kind == AgentKind::Duplicator);
+
+        // each copy's result is connected to one of the duplicator's ports
+        let values: Vec<_> = dup
+            .ports
+            .iter()
+            .map(|port| {
+                let (_, number) = normal_form
+                    .iter()
+                    .find(|(left, _)| left == port)
+                    .expect("expected a result");
+                assert_eq!(agent_on_port(port, number).kind, AgentKind::Number);
+                agent_on_port(port, number).data
+            })
+            .collect();
+        assert_eq!(values, [4, 3]);
+    }
+
+    /// The connection that the interface port called `name` is in, with that
+    /// port on the left.
+    fn named<'a>(normal_form: &'a [(Term, Term)], name: &str) -> (&'a Term, &'a Term) {
+        let is_named =
+            |term: &Term| matches!(term, Term::Port(port) if port.name.as_deref() == Some(name));
+
+        normal_form
+            .iter()
+            .find_map(|(left, right)| match () {
+                _ if is_named(left) => Some((left, right)),
+                _ if is_named(right) => Some((right, left)),
+                _ => None,
+            })
+            .unwrap_or_else(|| panic!("expected `{name}` to be connected"))
+    }
+
+    #[test]
+    fn switching_on_zero_picks_the_first_branch() {
+        let normal_form = normalize(&[
+            r#"Switch#1($2, $3:"out") = Number{0}#4()"#,
+            r#"$2 = Constructor#5($6:"zero", $7:"succ")"#,
+        ]);
+
+        let (_, zero) = named(&normal_form, "zero");
+        assert!(matches!(zero, Term::Port(port) if port.name.as_deref() == Some("out")));
+        let (succ, eraser) = named(&normal_form, "succ");
+        assert_eq!(agent_on_port(succ, eraser).kind, AgentKind::Eraser);
+        assert_eq!(normal_form.len(), 2);
+    }
+
+    #[test]
+    fn switching_on_a_successor_applies_the_second_branch_to_its_predecessor() {
+        let normal_form = normalize(&[
+            r#"Switch#1($2, $3:"out") = Number{3}#4()"#,
+            r#"$2 = Constructor#5($6:"zero", $7:"succ")"#,
+        ]);
+
+        let (zero, eraser) = named(&normal_form, "zero");
+        assert_eq!(agent_on_port(zero, eraser).kind, AgentKind::Eraser);
+
+        // the second branch gets a constructor of the predecessor and the output
+        let (succ, applied) = named(&normal_form, "succ");
+        let applied = agent_on_port(succ, applied);
+        assert_eq!(applied.kind, AgentKind::Constructor);
+        let [pred, out] = &*applied.ports else {
+            panic!("expected a constructor with two ports");
+        };
+        let Term::Agent(pred) = pred else {
+            panic!("expected the predecessor to be a number");
+        };
+        assert_eq!((pred.kind, pred.data), (AgentKind::Number, 2));
+        assert!(matches!(out, Term::Port(port) if port.name.as_deref() == Some("out")));
+        assert_eq!(normal_form.len(), 2);
+    }
+}
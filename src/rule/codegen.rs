@@ -0,0 +1,371 @@
+//! Generating Rust code from a rule file, so that its rules run as native code
+//! like [`Builtin`](super::builtin::Builtin) does, rather than through a
+//! [`CompiledRule`](super::compiled::CompiledRule).
+//!
+//! The generated code is an enum with a variant for each rule, along with
+//! `all()`, `pattern()` and `rewrite()` like `Builtin` has, and `add_to()` to
+//! add every rule to a [`Rulebook`](super::rulebook::Rulebook) as a
+//! [`Rule::Native`](super::Rule::Native). It's meant to be generated by a build
+//! script:
+//!
+//! ```ignore
+//! // build.rs
+//! fn main() {
+//!     inet_rs::rule::codegen::build("rules", "Rules").unwrap();
+//! }
+//!
+//! // src/main.rs
+//! include!(concat!(env!("OUT_DIR"), "/rules.rs"));
+//! ```
+
+use std::{
+    fmt::Write as _,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use rustc_hash::FxHashMap as HashMap;
+
+use super::{parse::parse_rules, template::RuleTemplate};
+use crate::net::term::{AgentKind, Term};
+
+/// Path that generated code uses to refer to this crate.
+const CRATE: &str = "::inet_rs";
+
+#[derive(Debug)]
+pub enum CodegenError {
+    Io(io::Error),
+    /// The rule file couldn't be parsed. Lines and columns are numbered from
+    /// one.
+    Parse {
+        line: usize,
+        column: usize,
+        message: String,
+    },
+    /// There's more than one rule for the same pair of agents.
+    DuplicatePattern {
+        rule: String,
+    },
+}
+
+impl From<io::Error> for CodegenError {
+    fn from(value: io::Error) -> Self {
+        Self::Io(value)
+    }
+}
+
+/// Short name for a kind of agent, which variant names are made from.
+fn kind_name(kind: AgentKind) -> String {
+    match kind {
+        AgentKind::Eraser => "Era".to_string(),
+        AgentKind::Duplicator => "Dup".to_string(),
+        AgentKind::Constructor => "Ctr".to_string(),
+        AgentKind::Dynamic(id) => format!("Dyn{id}"),
+        AgentKind::Number => "Num".to_string(),
+        AgentKind::BinaryOp(op) => format!("Op{op:?}"),
+        AgentKind::PartialOp(op) => format!("Partial{op:?}"),
+        AgentKind::Switch => "Switch".to_string(),
+        AgentKind::Reference(id) => format!("Ref{id}"),
+    }
+}
+
+/// Rust expression for a kind of agent, assuming `AgentKind` is in scope.
+fn kind_expr(kind: AgentKind) -> String {
+    match kind {
+        AgentKind::BinaryOp(op) => {
+            format!("AgentKind::BinaryOp({CRATE}::net::term::Operator::{op:?})")
+        }
+        AgentKind::PartialOp(op) => {
+            format!("AgentKind::PartialOp({CRATE}::net::term::Operator::{op:?})")
+        }
+        kind => format!("AgentKind::{kind:?}")
+            .replace('[', "(")
+            .replace(']', ")"),
+    }
+}
+
+/// Writes the body of one rule's arm in `rewrite()`.
+struct RuleWriter<'a> {
+    out: &'a mut String,
+    /// Variable for each port, and whether it'll be used again after this.
+    ports: HashMap<usize, (String, bool)>,
+    wires: usize,
+}
+
+impl RuleWriter<'_> {
+    /// Declares a fresh port for each port that's only on the right-hand side.
+    fn declare_wires(&mut self, term: &Term) {
+        match term {
+            Term::Port(_) => {
+                if self.ports.contains_key(term.id()) {
+                    return;
+                }
+
+                let variable = format!("w{}", self.wires);
+                self.wires += 1;
+
+                writeln!(
+                    self.out,
+                    "                let {variable} = ctx.create_port();"
+                )
+                .unwrap();
+                self.ports.insert(*term.id(), (variable, true));
+            }
+            Term::Agent(agent) => agent.ports.iter().for_each(|port| self.declare_wires(port)),
+        }
+    }
+
+    fn term(&mut self, term: &Term) -> String {
+        match term {
+            Term::Port(_) => {
+                let (variable, used_again) = self.ports.get_mut(term.id()).unwrap();
+
+                if std::mem::replace(used_again, false) {
+                    format!("{variable}.clone()")
+                } else {
+                    variable.clone()
+                }
+            }
+            Term::Agent(agent) => {
+                let ports = if agent.ports.is_empty() {
+                    "Vec::<Term>::new()".to_string()
+                } else {
+                    let ports: Vec<_> = agent.ports.iter().map(|port| self.term(port)).collect();
+                    format!("[{}]", ports.join(", "))
+                };
+
+                let mut agent_expr = format!(
+                    "Agent::new(ctx.id_alloc.create_id(), {}, {ports})",
+                    kind_expr(agent.kind)
+                );
+                if agent.data != 0 {
+                    write!(agent_expr, ".with_data({})", agent.data).unwrap();
+                }
+
+                format!("Term::Agent({agent_expr})")
+            }
+        }
+    }
+
+    fn rule(&mut self, template: &RuleTemplate) {
+        writeln!(self.out, "                ctx.id_alloc.retire_id(a.id);").unwrap();
+        writeln!(self.out, "                ctx.id_alloc.retire_id(b.id);").unwrap();
+        writeln!(self.out).unwrap();
+
+        for (side, agent) in [("a", template.left()), ("b", template.right())] {
+            if agent.ports.is_empty() {
+                continue;
+            }
+
+            let mut variables = Vec::new();
+            for (index, port) in agent.ports.iter().enumerate() {
+                let variable = format!("{side}{index}");
+                variables.push(variable.clone());
+                self.ports.insert(*port.id(), (variable, false));
+            }
+
+            writeln!(
+                self.out,
+                "                let [{}] = {side}.ports_array().unwrap();",
+                variables.join(", ")
+            )
+            .unwrap();
+        }
+
+        for connection in template.connections() {
+            self.declare_wires(connection.left());
+            self.declare_wires(connection.right());
+        }
+
+        if template.connections().is_empty() {
+            writeln!(self.out, "                RewriteResult::empty()").unwrap();
+            return;
+        }
+
+        if self.out.ends_with(";\n") {
+            writeln!(self.out).unwrap();
+        }
+        writeln!(self.out, "                RewriteResult {{").unwrap();
+        writeln!(self.out, "                    new_connections: vec![").unwrap();
+        for connection in template.connections() {
+            let left = self.term(connection.left());
+            let right = self.term(connection.right());
+            writeln!(
+                self.out,
+                "                        Connection({left}, {right}),"
+            )
+            .unwrap();
+        }
+        writeln!(self.out, "                    ],").unwrap();
+        writeln!(self.out, "                }}").unwrap();
+    }
+}
+
+/// Generates an enum called `name` with a variant for each rule.
+pub fn generate(templates: &[RuleTemplate], name: &str) -> Result<String, CodegenError> {
+    let mut variants = Vec::<String>::with_capacity(templates.len());
+    for template in templates {
+        let variant = format!(
+            "{}{}",
+            kind_name(template.left().kind),
+            kind_name(template.right().kind)
+        );
+
+        if variants.contains(&variant) {
+            return Err(CodegenError::DuplicatePattern {
+                rule: template.to_string(),
+            });
+        }
+        variants.push(variant);
+    }
+
+    let mut out = String::new();
+    let o = &mut out;
+
+    writeln!(
+        o,
+        "// Generated by inet-rs from a rule file. Don't edit it by hand."
+    )
+    .unwrap();
+    writeln!(o).unwrap();
+    writeln!(o, "#[derive(Clone, Copy, Debug, PartialEq, Eq)]").unwrap();
+    writeln!(o, "pub enum {name} {{").unwrap();
+    for (variant, template) in variants.iter().zip(templates) {
+        writeln!(o, "    /// `{template}`").unwrap();
+        writeln!(o, "    {variant},").unwrap();
+    }
+    writeln!(o, "}}").unwrap();
+    writeln!(o).unwrap();
+
+    writeln!(o, "impl {name} {{").unwrap();
+
+    writeln!(o, "    pub fn all() -> Vec<Self> {{").unwrap();
+    writeln!(o, "        vec![").unwrap();
+    for variant in &variants {
+        writeln!(o, "            Self::{variant},").unwrap();
+    }
+    writeln!(o, "        ]").unwrap();
+    writeln!(o, "    }}").unwrap();
+    writeln!(o).unwrap();
+
+    writeln!(
+        o,
+        "    pub fn pattern(&self) -> {CRATE}::rule::rulebook::ActivePairPattern {{"
+    )
+    .unwrap();
+    writeln!(
+        o,
+        "        use {CRATE}::{{net::term::AgentKind, rule::rulebook::ActivePairPattern}};"
+    )
+    .unwrap();
+    writeln!(o).unwrap();
+    writeln!(o, "        match self {{").unwrap();
+    for (variant, template) in variants.iter().zip(templates) {
+        writeln!(
+            o,
+            "            Self::{variant} => ActivePairPattern::new({}, {}),",
+            kind_expr(template.left().kind),
+            kind_expr(template.right().kind)
+        )
+        .unwrap();
+    }
+    writeln!(o, "        }}").unwrap();
+    writeln!(o, "    }}").unwrap();
+    writeln!(o).unwrap();
+
+    writeln!(
+        o,
+        "    /// Agents passed into this function are sorted by their AgentKind."
+    )
+    .unwrap();
+    writeln!(o, "    pub fn rewrite(").unwrap();
+    writeln!(o, "        &self,").unwrap();
+    writeln!(o, "        ctx: &{CRATE}::rule::context::RewriteContext,").unwrap();
+    writeln!(o, "        a: {CRATE}::net::term::Agent,").unwrap();
+    writeln!(o, "        b: {CRATE}::net::term::Agent,").unwrap();
+    writeln!(o, "    ) -> {CRATE}::rule::RewriteResult {{").unwrap();
+    writeln!(o, "        #[allow(unused_imports)]").unwrap();
+    writeln!(o, "        use {CRATE}::{{").unwrap();
+    writeln!(o, "            net::{{").unwrap();
+    writeln!(o, "                connection::Connection,").unwrap();
+    writeln!(o, "                term::{{Agent, AgentKind, Term}},").unwrap();
+    writeln!(o, "            }},").unwrap();
+    writeln!(o, "            rule::RewriteResult,").unwrap();
+    writeln!(o, "        }};").unwrap();
+    writeln!(o).unwrap();
+    writeln!(o, "        match self {{").unwrap();
+    for (variant, template) in variants.iter().zip(templates) {
+        writeln!(o, "            Self::{variant} => {{").unwrap();
+        RuleWriter {
+            out: o,
+            ports: HashMap::default(),
+            wires: 0,
+        }
+        .rule(template);
+        writeln!(o, "            }}").unwrap();
+    }
+    writeln!(o, "        }}").unwrap();
+    writeln!(o, "    }}").unwrap();
+    writeln!(o).unwrap();
+
+    writeln!(
+        o,
+        "    /// Adds every rule to a rulebook, replacing any it already has for the"
+    )
+    .unwrap();
+    writeln!(o, "    /// same patterns.").unwrap();
+    writeln!(
+        o,
+        "    pub fn add_to(rulebook: &mut {CRATE}::rule::rulebook::Rulebook) {{"
+    )
+    .unwrap();
+    writeln!(o, "        use {CRATE}::rule::Rule;").unwrap();
+    writeln!(o).unwrap();
+    for variant in &variants {
+        writeln!(o, "        rulebook.add_rule(").unwrap();
+        writeln!(o, "            Self::{variant}.pattern(),").unwrap();
+        writeln!(
+            o,
+            "            Rule::Native(|ctx, a, b| Self::{variant}.rewrite(ctx, a, b)),"
+        )
+        .unwrap();
+        writeln!(o, "        );").unwrap();
+    }
+    writeln!(o, "    }}").unwrap();
+    writeln!(o, "}}").unwrap();
+
+    Ok(out)
+}
+
+/// Generates code for a rule file from a build script, and writes it to
+/// `$OUT_DIR`, named after the rule file with an `.rs` extension. Returns the
+/// path it was written to.
+pub fn build(rule_file: impl AsRef<Path>, name: &str) -> Result<PathBuf, CodegenError> {
+    let rule_file = rule_file.as_ref();
+    println!("cargo:rerun-if-changed={}", rule_file.display());
+
+    let src = fs::read_to_string(rule_file)?;
+    let templates = parse_rules(&src).map_err(|error| {
+        let (line, column) = error.line_column(&src);
+        CodegenError::Parse {
+            line,
+            column,
+            message: error.message,
+        }
+    })?;
+
+    let out_dir = std::env::var_os("OUT_DIR").ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            "OUT_DIR isn't set, so this isn't running in a build script",
+        )
+    })?;
+
+    let mut out_file = PathBuf::from(out_dir);
+    out_file.push(rule_file.file_stem().unwrap_or(rule_file.as_os_str()));
+    out_file.set_extension("rs");
+
+    fs::write(&out_file, generate(&templates, name)?)?;
+
+    Ok(out_file)
+}
@@ -0,0 +1,263 @@
+use rustc_hash::FxHashMap as HashMap;
+
+use super::{context::RewriteContext, template::RuleTemplate, RewriteResult};
+use crate::net::{
+    connection::Connection,
+    term::{Agent, AgentKind, Port, Term},
+};
+
+/// One step of a [`CompiledRule`].
+///
+/// Instructions work on a row of slots, each of which holds a term until an
+/// instruction takes it out. The first slots are filled with the auxiliary
+/// ports of the active pair, left agent first, before the first instruction
+/// runs.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Instruction {
+    /// Creates a fresh port, and puts one occurrence of it in each slot.
+    Wire { a: u32, b: u32 },
+    /// Creates an agent, taking its ports from the slots listed in the
+    /// operands from `first` on, and puts it in `slot`.
+    Node {
+        slot: u32,
+        kind: AgentKind,
+        data: u64,
+        first: u32,
+        arity: u32,
+    },
+    /// Connects the terms in two slots.
+    Link { a: u32, b: u32 },
+}
+
+/// A [`RuleTemplate`] compiled to a flat list of [`Instruction`]s, so that
+/// using it doesn't have to walk the template or look up ports by ID.
+///
+/// Names in the template are left out of the net it creates.
+pub struct CompiledRule {
+    template: RuleTemplate,
+    instructions: Vec<Instruction>,
+    /// Slots that [`Instruction::Node`]s take their ports from.
+    operands: Vec<u32>,
+    slot_count: usize,
+}
+
+/// Assigns slots to the terms of a template as it's compiled.
+struct Compiler {
+    instructions: Vec<Instruction>,
+    operands: Vec<u32>,
+    slot_count: u32,
+    /// Ports that have a slot waiting for them: the ports of the active pair,
+    /// and the second occurrence of fresh ports that have been seen once.
+    ports: HashMap<usize, u32>,
+}
+
+impl Compiler {
+    fn slot(&mut self) -> u32 {
+        self.slot_count += 1;
+        self.slot_count - 1
+    }
+
+    /// Emits the instructions that build a term, returning the slot it ends up
+    /// in.
+    fn term(&mut self, term: &Term) -> u32 {
+        match term {
+            Term::Port(_) => {
+                if let Some(slot) = self.ports.remove(term.id()) {
+                    return slot;
+                }
+
+                let (a, b) = (self.slot(), self.slot());
+                self.instructions.push(Instruction::Wire { a, b });
+                self.ports.insert(*term.id(), b);
+                a
+            }
+            Term::Agent(agent) => {
+                let ports: Vec<_> = agent.ports.iter().map(|port| self.term(port)).collect();
+
+                let first = self.operands.len() as u32;
+                self.operands.extend(ports);
+
+                let slot = self.slot();
+                self.instructions.push(Instruction::Node {
+                    slot,
+                    kind: agent.kind,
+                    data: agent.data,
+                    first,
+                    arity: agent.ports.len() as u32,
+                });
+                slot
+            }
+        }
+    }
+}
+
+impl CompiledRule {
+    /// Compiles a template, which should be [valid](RuleTemplate::validate).
+    /// [`Rulebook::add_template`] makes sure that it is.
+    ///
+    /// [`Rulebook::add_template`]: super::rulebook::Rulebook::add_template
+    pub fn compile(template: RuleTemplate) -> Self {
+        let mut compiler = Compiler {
+            instructions: Vec::new(),
+            operands: Vec::new(),
+            slot_count: 0,
+            ports: HashMap::default(),
+        };
+
+        for port in template
+            .left()
+            .ports
+            .iter()
+            .chain(template.right().ports.iter())
+        {
+            let slot = compiler.slot();
+            compiler.ports.insert(*port.id(), slot);
+        }
+
+        for connection in template.connections() {
+            let a = compiler.term(connection.left());
+            let b = compiler.term(connection.right());
+            compiler.instructions.push(Instruction::Link { a, b });
+        }
+
+        Self {
+            template,
+            instructions: compiler.instructions,
+            operands: compiler.operands,
+            slot_count: compiler.slot_count as usize,
+        }
+    }
+
+    /// The template that the rule was compiled from.
+    pub fn template(&self) -> &RuleTemplate {
+        &self.template
+    }
+
+    pub fn instructions(&self) -> &[Instruction] {
+        &self.instructions
+    }
+
+    /// Slots that [`Instruction::Node`]s take their ports from.
+    pub fn operands(&self) -> &[u32] {
+        &self.operands
+    }
+
+    /// The number of slots the instructions use, including the ones for the
+    /// ports of the active pair.
+    pub fn slot_count(&self) -> usize {
+        self.slot_count
+    }
+
+    /// Rewrites an active pair, adding the connections it creates to `out`.
+    ///
+    /// `slots` is scratch space, which can be reused between calls to save
+    /// allocating it every time.
+    pub fn execute(
+        &self,
+        ctx: &RewriteContext,
+        a: Agent,
+        b: Agent,
+        slots: &mut Vec<Option<Term>>,
+        out: &mut Vec<Connection>,
+    ) {
+        debug_assert!(a.kind == self.template.left().kind && b.kind == self.template.right().kind);
+
+        ctx.id_alloc.retire_id(a.id);
+        ctx.id_alloc.retire_id(b.id);
+
+        slots.clear();
+        slots.extend(Vec::from(a.ports).into_iter().map(Some));
+        slots.extend(Vec::from(b.ports).into_iter().map(Some));
+        assert!(
+            slots.len() == self.template.left().ports.len() + self.template.right().ports.len()
+        );
+        slots.resize(self.slot_count, None);
+
+        fn take(slots: &mut [Option<Term>], slot: u32) -> Term {
+            slots[slot as usize]
+                .take()
+                .expect("compiled rule used a slot twice")
+        }
+
+        for instruction in &self.instructions {
+            match *instruction {
+                Instruction::Wire { a, b } => {
+                    let port = Term::Port(Port::new(ctx.id_alloc.create_id()));
+                    slots[a as usize] = Some(port.clone());
+                    slots[b as usize] = Some(port);
+                }
+                Instruction::Node {
+                    slot,
+                    kind,
+                    data,
+                    first,
+                    arity,
+                } => {
+                    let operands = &self.operands[first as usize..(first + arity) as usize];
+                    let ports: Vec<_> = operands
+                        .iter()
+                        .map(|&operand| take(slots, operand))
+                        .collect();
+
+                    let agent = Agent::new(ctx.id_alloc.create_id(), kind, ports).with_data(data);
+                    slots[slot as usize] = Some(Term::Agent(agent));
+                }
+                Instruction::Link { a, b } => {
+                    out.push(Connection(take(slots, a), take(slots, b)));
+                }
+            }
+        }
+    }
+
+    pub fn rewrite(&self, ctx: &RewriteContext, a: Agent, b: Agent) -> RewriteResult {
+        let mut new_connections = Vec::new();
+        self.execute(ctx, a, b, &mut Vec::new(), &mut new_connections);
+
+        new_connections.into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        net::{canonical::CanonicalNet, id::IdAllocator},
+        rule::parse::parse_rules,
+    };
+
+    /// An agent like one in a template, with named interface ports.
+    fn instance(ctx: &RewriteContext, agent: &Agent, prefix: &str) -> Agent {
+        let ports: Vec<_> = (0..agent.ports.len())
+            .map(|index| ctx.create_port().with_name(format!("{prefix}{index}")))
+            .collect();
+
+        Agent::new(ctx.id_alloc.create_id(), agent.kind, ports).with_data(agent.data)
+    }
+
+    #[test]
+    fn compiled_rules_rewrite_like_their_templates() {
+        let templates = parse_rules(include_str!("../../rules")).unwrap();
+        assert!(!templates.is_empty());
+
+        for template in templates {
+            let ctx = RewriteContext::new(IdAllocator::new());
+            let left = instance(&ctx, template.left(), "l");
+            let right = instance(&ctx, template.right(), "r");
+
+            // each rewrite retires the pair, so they each get their own
+            // copy of the allocator
+            let other_ctx = RewriteContext::new(IdAllocator::from_state(ctx.id_alloc.state()));
+
+            let expected = template.rewrite(&ctx, left.clone(), right.clone());
+            let compiled = CompiledRule::compile(template);
+            let actual = compiled.rewrite(&other_ctx, left, right);
+
+            assert!(
+                CanonicalNet::new(actual.new_connections)
+                    == CanonicalNet::new(expected.new_connections),
+                "{} compiles to a different rule",
+                compiled.template()
+            );
+        }
+    }
+}
@@ -21,6 +21,10 @@ impl RewriteContext {
         (self.create_port(), self.create_port())
     }
 
+    pub fn create_number(&self, value: u64) -> Term {
+        Term::Agent(Agent::new_number(self.id_alloc.create_id(), value))
+    }
+
     pub fn create_agent(&self, kind: AgentKind, ports: &[Term]) -> Term {
         Term::Agent(Agent::new(self.id_alloc.create_id(), kind, ports))
     }
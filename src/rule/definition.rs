@@ -0,0 +1,41 @@
+use super::{context::RewriteContext, template::Instantiator, RewriteResult};
+use crate::net::{
+    connection::Connection,
+    term::{Agent, Term},
+};
+
+/// A named net that a [`Reference`](crate::net::term::AgentKind::Reference)
+/// agent expands into.
+///
+/// Every port in a definition should show up exactly twice, so that copies of
+/// it don't share any wires.
+pub struct Definition {
+    /// The term that takes the place of the reference agent.
+    pub root: Term,
+    /// Any other connections in the net.
+    pub connections: Vec<Connection>,
+}
+
+impl Definition {
+    pub fn new(root: Term, connections: Vec<Connection>) -> Self {
+        Self { root, connections }
+    }
+
+    /// Makes a fresh copy of the definition, with its root connected to the
+    /// agent that the reference met.
+    pub fn expand(&self, ctx: &RewriteContext, partner: Agent) -> RewriteResult {
+        let mut instantiator = Instantiator::new(ctx);
+
+        let mut new_connections = vec![Connection(
+            instantiator.instantiate(&self.root),
+            Term::Agent(partner),
+        )];
+        new_connections.extend(
+            self.connections
+                .iter()
+                .map(|connection| instantiator.instantiate_connection(connection)),
+        );
+
+        RewriteResult { new_connections }
+    }
+}
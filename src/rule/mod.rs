@@ -1,11 +1,17 @@
-mod builtin;
+pub mod builtin;
+pub mod codegen;
+pub mod compiled;
 pub mod context;
+pub mod definition;
+pub mod parse;
 pub mod rulebook;
+pub mod template;
 
 use self::context::RewriteContext;
 
 use super::net::{connection::Connection, term::Agent};
 use builtin::Builtin;
+use compiled::CompiledRule;
 
 pub struct RewriteResult {
     pub new_connections: Vec<Connection>,
@@ -25,11 +31,24 @@ impl From<Vec<Connection>> for RewriteResult {
     }
 }
 
-type RewriteRule = dyn Fn(&RewriteContext, Agent, Agent) -> RewriteResult;
+/// Dynamic rules have to be `Send + Sync` so that a [`Rulebook`] can be shared
+/// between the threads of a parallel runtime.
+///
+/// [`Rulebook`]: rulebook::Rulebook
+type RewriteRule = dyn Fn(&RewriteContext, Agent, Agent) -> RewriteResult + Send + Sync;
+
+/// A rule that's a plain function, like the ones [`codegen`] generates.
+pub type NativeRule = fn(&RewriteContext, Agent, Agent) -> RewriteResult;
 
 pub enum Rule {
     Builtin(Builtin),
     Dynamic(Box<RewriteRule>),
+    /// A plain function, which is called directly rather than through a
+    /// boxed closure.
+    Native(NativeRule),
+    /// A [`RuleTemplate`](template::RuleTemplate), compiled so that it's
+    /// quick to use.
+    Template(CompiledRule),
 }
 
 impl Rule {
@@ -37,6 +56,8 @@ impl Rule {
         match self {
             Self::Builtin(builtin) => builtin.rewrite(ctx, a, b),
             Self::Dynamic(f) => f(ctx, a, b),
+            Self::Native(f) => f(ctx, a, b),
+            Self::Template(rule) => rule.rewrite(ctx, a, b),
         }
     }
 }
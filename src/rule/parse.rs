@@ -0,0 +1,273 @@
+//! Parsing rule files, like the `rules` file at the root of the repository:
+//!
+//! ```text
+//! // Erasers erase each other
+//! E = E -> ()
+//!
+//! // Eraser distributes
+//! E = D(a, b) -> E = a, E = b
+//! ```
+//!
+//! Each rule is an active pair, `->`, and the connections it rewrites to
+//! separated by commas (or `()` if there are none). Agents start with an
+//! uppercase letter: `E`, `D` and `C` are short for erasers, duplicators and
+//! constructors, and any other kind is written like it's debug-printed, such as
+//! `Dynamic[0]` or `BinaryOp[Add]`. Agents with data have it in braces after
+//! their kind, like `Number{42}`. Anything else is a port, which should show up
+//! twice in a rule. Comments start with `//`.
+
+use rustc_hash::FxHashMap as HashMap;
+
+use super::template::{RuleTemplate, TemplateError};
+use crate::net::{
+    connection::Connection,
+    term::{Agent, AgentKind, Operator, Port, Term},
+    text::ParseError,
+};
+
+struct Parser<'a> {
+    src: &'a str,
+    position: usize,
+    /// Ports of the rule being parsed, by name.
+    ports: HashMap<&'a str, usize>,
+    next_id: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn error(&self, message: impl Into<String>) -> ParseError {
+        ParseError {
+            position: self.position,
+            message: message.into(),
+        }
+    }
+
+    fn rest(&self) -> &'a str {
+        &self.src[self.position..]
+    }
+
+    fn skip_whitespace(&mut self) {
+        loop {
+            let rest = self.rest();
+            self.position += rest.len() - rest.trim_start().len();
+
+            if !self.rest().starts_with("//") {
+                return;
+            }
+
+            let comment = self.rest().find('\n').unwrap_or(self.rest().len());
+            self.position += comment;
+        }
+    }
+
+    fn eat(&mut self, token: &str) -> bool {
+        self.skip_whitespace();
+
+        if self.rest().starts_with(token) {
+            self.position += token.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, token: &str) -> Result<(), ParseError> {
+        if self.eat(token) {
+            Ok(())
+        } else {
+            Err(self.error(format!("expected `{token}`")))
+        }
+    }
+
+    fn number<T: std::str::FromStr>(&mut self) -> Result<T, ParseError> {
+        let ident = self.ident()?;
+        ident
+            .parse()
+            .map_err(|_| self.error(format!("`{ident}` is not a number")))
+    }
+
+    fn ident(&mut self) -> Result<&'a str, ParseError> {
+        self.skip_whitespace();
+
+        let rest = self.rest();
+        let len = rest
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+            .unwrap_or(rest.len());
+
+        if len == 0 {
+            return Err(self.error("expected a port or an agent"));
+        }
+
+        self.position += len;
+        Ok(&rest[..len])
+    }
+
+    fn operator(&mut self) -> Result<Operator, ParseError> {
+        self.expect("[")?;
+        let name = self.ident()?;
+        let operator = Operator::ALL
+            .into_iter()
+            .find(|op| format!("{op:?}") == name)
+            .ok_or_else(|| self.error(format!("`{name}` is not an operator")))?;
+        self.expect("]")?;
+
+        Ok(operator)
+    }
+
+    fn kind(&mut self, name: &str) -> Result<AgentKind, ParseError> {
+        Ok(match name {
+            "E" | "Eraser" => AgentKind::Eraser,
+            "D" | "Duplicator" => AgentKind::Duplicator,
+            "C" | "Constructor" => AgentKind::Constructor,
+            "Number" => AgentKind::Number,
+            "Switch" => AgentKind::Switch,
+            "BinaryOp" => AgentKind::BinaryOp(self.operator()?),
+            "PartialOp" => AgentKind::PartialOp(self.operator()?),
+            "Dynamic" | "Reference" => {
+                self.expect("[")?;
+                let id = self.number()?;
+                self.expect("]")?;
+
+                match name {
+                    "Dynamic" => AgentKind::Dynamic(id),
+                    _ => AgentKind::Reference(id),
+                }
+            }
+            _ => {
+                return Err(ParseError {
+                    position: self.position - name.len(),
+                    message: format!("unknown kind of agent `{name}`"),
+                })
+            }
+        })
+    }
+
+    fn term(&mut self) -> Result<Term, ParseError> {
+        let name = self.ident()?;
+
+        if !name.starts_with(|c: char| c.is_ascii_uppercase()) {
+            let next_id = &mut self.next_id;
+            let id = *self.ports.entry(name).or_insert_with(|| {
+                *next_id += 1;
+                *next_id - 1
+            });
+
+            return Ok(Term::Port(Port::new(id).with_name(name)));
+        }
+
+        let kind = self.kind(name)?;
+        let data = if self.eat("{") {
+            let data = self.number()?;
+            self.expect("}")?;
+            data
+        } else {
+            0
+        };
+
+        let mut ports = Vec::new();
+        if self.eat("(") && !self.eat(")") {
+            loop {
+                ports.push(self.term()?);
+                if self.eat(")") {
+                    break;
+                }
+                self.expect(",")?;
+            }
+        }
+
+        self.next_id += 1;
+        Ok(Term::Agent(
+            Agent::new(self.next_id - 1, kind, ports).with_data(data),
+        ))
+    }
+
+    fn agent(&mut self) -> Result<Agent, ParseError> {
+        let position = self.position;
+
+        match self.term()? {
+            Term::Agent(agent) => Ok(agent),
+            Term::Port(_) => {
+                self.position = position;
+                self.skip_whitespace();
+                Err(self.error("expected an agent"))
+            }
+        }
+    }
+
+    fn rule(&mut self) -> Result<RuleTemplate, ParseError> {
+        self.ports.clear();
+        self.skip_whitespace();
+        let position = self.position;
+
+        let left = self.agent()?;
+        self.expect("=")?;
+        let right = self.agent()?;
+        self.expect("->")?;
+
+        let mut connections = Vec::new();
+        if self.eat("(") {
+            self.expect(")")?;
+        } else {
+            loop {
+                let left = self.term()?;
+                self.expect("=")?;
+                let right = self.term()?;
+                connections.push(Connection(left, right));
+
+                if !self.eat(",") {
+                    break;
+                }
+            }
+        }
+
+        let template = RuleTemplate::new(left, right, connections);
+        template.validate().map_err(|error| ParseError {
+            position,
+            message: describe_template_error(error, &self.ports),
+        })?;
+
+        Ok(template)
+    }
+}
+
+/// Explains what's wrong with a rule, given the names of its ports.
+pub(crate) fn describe_template_error(
+    error: TemplateError,
+    ports: &HashMap<&str, usize>,
+) -> String {
+    match error {
+        TemplateError::NestedAgent { .. } => {
+            "the agents of the active pair can only have ports".to_string()
+        }
+        TemplateError::PortCount { port_id, count } => {
+            let name = ports
+                .iter()
+                .find_map(|(name, &id)| (id == port_id).then_some(name))
+                .unwrap();
+
+            match count {
+                1 => format!("`{name}` shows up once instead of twice"),
+                _ => format!("`{name}` shows up {count} times instead of twice"),
+            }
+        }
+    }
+}
+
+/// Parses every rule in a rule file.
+pub fn parse_rules(src: &str) -> Result<Vec<RuleTemplate>, ParseError> {
+    let mut parser = Parser {
+        src,
+        position: 0,
+        ports: HashMap::default(),
+        next_id: 0,
+    };
+
+    let mut rules = Vec::new();
+    loop {
+        parser.skip_whitespace();
+        if parser.rest().is_empty() {
+            return Ok(rules);
+        }
+
+        rules.push(parser.rule()?);
+    }
+}
@@ -1,12 +1,24 @@
 use std::collections::BTreeMap;
 
-use super::{builtin::Builtin, context::RewriteContext, RewriteResult, Rule};
+use super::{
+    builtin::Builtin,
+    compiled::CompiledRule,
+    context::RewriteContext,
+    definition::Definition,
+    template::{RuleTemplate, TemplateError},
+    RewriteResult, Rule,
+};
 use crate::net::{
     connection::Connection,
     term::{Agent, AgentKind},
 };
 
 #[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(from = "(AgentKind, AgentKind)")
+)]
 pub struct ActivePairPattern(AgentKind, AgentKind);
 
 impl ActivePairPattern {
@@ -31,9 +43,19 @@ impl ActivePairPattern {
     }
 }
 
+impl From<(AgentKind, AgentKind)> for ActivePairPattern {
+    fn from((a, b): (AgentKind, AgentKind)) -> Self {
+        Self::new(a, b)
+    }
+}
+
 pub struct Rulebook {
     /// Map from agent kinds to rewrite rule.
     map: BTreeMap<ActivePairPattern, Rule>,
+    /// Definitions that reference agents expand into, indexed by the ID in
+    /// their [`AgentKind::Reference`]. A definition that has been referred to
+    /// but not defined yet is `None`.
+    definitions: Vec<(String, Option<Definition>)>,
 }
 
 impl Rulebook {
@@ -43,8 +65,153 @@ impl Rulebook {
         self
     }
 
+    /// Compiles the template and adds it as the rule for its pattern, if it's
+    /// [valid](RuleTemplate::validate).
+    pub fn add_template(&mut self, template: RuleTemplate) -> Result<&mut Self, TemplateError> {
+        template.validate()?;
+
+        Ok(self.add_rule(
+            template.pattern(),
+            Rule::Template(CompiledRule::compile(template)),
+        ))
+    }
+
+    /// Declares a [`Dynamic`](AgentKind::Dynamic) kind of agent with the
+    /// given number of auxiliary ports, and adds rules for erasing and
+    /// duplicating it. Rules that have already been added are kept.
+    pub fn declare_agent(&mut self, id: usize, arity: usize) -> AgentKind {
+        for builtin in [Builtin::DynEra { id, arity }, Builtin::DynDup { id, arity }] {
+            self.map
+                .entry(builtin.pattern())
+                .or_insert(Rule::Builtin(builtin));
+        }
+
+        AgentKind::Dynamic(id)
+    }
+
+    /// Gets the kind of agent that refers to the definition with this name.
+    ///
+    /// The definition doesn't need to exist yet, so definitions can refer to
+    /// each other (or themselves) in any order. It does need to exist by the
+    /// time a reference to it is expanded, or reducing the net panics.
+    pub fn reference(&mut self, name: &str) -> AgentKind {
+        let id = match self.definitions.iter().position(|(n, _)| n == name) {
+            Some(id) => id,
+            None => {
+                self.definitions.push((name.to_string(), None));
+                self.definitions.len() - 1
+            }
+        };
+
+        AgentKind::Reference(id)
+    }
+
+    /// Sets the net that references to `name` expand into, and returns the
+    /// kind of agent that refers to it.
+    pub fn define(&mut self, name: &str, definition: Definition) -> AgentKind {
+        let kind @ AgentKind::Reference(id) = self.reference(name) else {
+            unreachable!();
+        };
+
+        self.definitions[id].1 = Some(definition);
+
+        kind
+    }
+
+    pub fn definition(&self, id: usize) -> Option<(&str, Option<&Definition>)> {
+        self.definitions
+            .get(id)
+            .map(|(name, definition)| (name.as_str(), definition.as_ref()))
+    }
+
+    /// Gets the rule for a pair if it's a compiled template, so that the
+    /// runtime can run it without going through [`Rulebook::rewrite`]. It
+    /// expects its agents in the same order as its pattern.
+    pub fn compiled_rule(&self, left: &Agent, right: &Agent) -> Option<&CompiledRule> {
+        match self.map.get(&ActivePairPattern::from_agents(left, right))? {
+            Rule::Template(rule) => Some(rule),
+            _ => None,
+        }
+    }
+
+    pub fn rules(&self) -> impl Iterator<Item = (&ActivePairPattern, &Rule)> {
+        self.map.iter()
+    }
+
+    /// Whether an active pair of these agents can be rewritten, either by a
+    /// rule or by a reference being erased, copied or expanded. Pairs that
+    /// can't are left as they are by [`Rulebook::rewrite`].
+    pub fn has_rule(&self, left: &Agent, right: &Agent) -> bool {
+        self.map
+            .contains_key(&ActivePairPattern::from_agents(left, right))
+            || self.can_expand(left, right)
+    }
+
+    /// Whether a pair without a rule of its own has a reference in it.
+    fn can_expand(&self, left: &Agent, right: &Agent) -> bool {
+        matches!(left.kind, AgentKind::Reference(_))
+            || matches!(right.kind, AgentKind::Reference(_))
+    }
+
+    /// Erases or copies a reference that meets an eraser or a duplicator, and
+    /// expands it into its definition when it meets anything else.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the reference has to be expanded but its definition doesn't
+    /// exist.
+    fn expand(&self, ctx: &RewriteContext, left: Agent, right: Agent) -> RewriteResult {
+        let (reference, partner) = match left.kind {
+            AgentKind::Reference(_) => (left, right),
+            _ => (right, left),
+        };
+        let AgentKind::Reference(id) = reference.kind else {
+            unreachable!("expand should only be called on a pair with a reference");
+        };
+
+        ctx.id_alloc.retire_id(reference.id);
+
+        // expanding a reference just to erase or copy it is wasted work, and
+        // would never finish for recursive definitions
+        match partner.kind {
+            AgentKind::Eraser => {
+                ctx.id_alloc.retire_id(partner.id);
+                return RewriteResult::empty();
+            }
+            AgentKind::Duplicator => {
+                ctx.id_alloc.retire_id(partner.id);
+
+                let [a, b] = partner.ports_array().unwrap();
+                return RewriteResult {
+                    new_connections: vec![
+                        ctx.create_agent(reference.kind, &[]).connect(a),
+                        ctx.create_agent(reference.kind, &[]).connect(b),
+                    ],
+                };
+            }
+            _ => {}
+        }
+
+        let definition = match self.definition(id) {
+            Some((_, Some(definition))) => definition,
+            Some((name, None)) => panic!("`{name}` is referred to but never defined"),
+            None => panic!("reference to definition {id}, which doesn't exist"),
+        };
+
+        definition.expand(ctx, partner)
+    }
+
+    /// Rewrites an active pair by its rule, or by expanding a reference.
+    ///
+    /// A pair that [`Rulebook::has_rule`] is false for comes back unchanged,
+    /// with a warning. Runtimes check first and leave such pairs out of the
+    /// reduction, since pushing the result back would only find it again.
     pub fn rewrite(&self, ctx: &RewriteContext, left: Agent, right: Agent) -> RewriteResult {
         let Some(rule) = self.map.get(&ActivePairPattern::from_agents(&left, &right)) else {
+            if self.can_expand(&left, &right) {
+                return self.expand(ctx, left, right);
+            }
+
             eprintln!(
                 "warn: no rewrite rule for {:?} <-> {:?}",
                 left.kind, right.kind
@@ -53,7 +220,12 @@ impl Rulebook {
             return vec![Connection::from_agents(left, right)].into();
         };
 
-        rule.rewrite(ctx, left, right)
+        // rules expect their agents in the same order as their pattern
+        if left.kind <= right.kind {
+            rule.rewrite(ctx, left, right)
+        } else {
+            rule.rewrite(ctx, right, left)
+        }
     }
 }
 
@@ -65,6 +237,243 @@ impl Default for Rulebook {
                 .map(|builtin| (builtin.pattern(), Rule::Builtin(builtin))),
         );
 
-        Self { map: rules }
+        Self {
+            map: rules,
+            definitions: Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        net::{id::IdAllocator, term::Port, term::Term, text::parse_connection},
+        runtime::Runtime,
+    };
+
+    /// A rulebook where `@id` expands into `(x x)`.
+    fn rulebook() -> (Rulebook, AgentKind) {
+        let mut rulebook = Rulebook::default();
+        let root = Agent::new_constructor(0, Term::Port(Port::new(1)), Term::Port(Port::new(1)));
+        let id = rulebook.define("id", Definition::new(Term::Agent(root), Vec::new()));
+
+        (rulebook, id)
+    }
+
+    fn kinds(result: &RewriteResult) -> Vec<AgentKind> {
+        result
+            .new_connections
+            .iter()
+            .flat_map(|Connection(left, right)| [left, right])
+            .filter_map(|term| match term {
+                Term::Agent(agent) => Some(agent.kind),
+                Term::Port(_) => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn reference_meeting_a_duplicator_is_copied() {
+        let (rulebook, id) = rulebook();
+        let ctx = RewriteContext::new(IdAllocator::new());
+        let Term::Agent(dup) = ctx.create_agent(
+            AgentKind::Duplicator,
+            &[ctx.create_port(), ctx.create_port()],
+        ) else {
+            unreachable!();
+        };
+        let Term::Agent(reference) = ctx.create_agent(id, &[]) else {
+            unreachable!();
+        };
+        let consumed = [dup.id, reference.id];
+
+        assert!(rulebook.has_rule(&dup, &reference));
+        let result = rulebook.rewrite(&ctx, dup, reference);
+
+        assert_eq!(kinds(&result), [id, id]);
+        for id in consumed {
+            assert!(!ctx.id_alloc.is_live(id));
+        }
+    }
+
+    #[test]
+    fn reference_meeting_an_eraser_is_erased() {
+        let (rulebook, id) = rulebook();
+        let ctx = RewriteContext::new(IdAllocator::new());
+        let Term::Agent(era) = ctx.create_agent(AgentKind::Eraser, &[]) else {
+            unreachable!();
+        };
+        let Term::Agent(reference) = ctx.create_agent(id, &[]) else {
+            unreachable!();
+        };
+
+        let result = rulebook.rewrite(&ctx, reference, era);
+
+        assert!(result.new_connections.is_empty());
+    }
+
+    #[test]
+    fn reference_meeting_a_constructor_is_expanded() {
+        let (rulebook, id) = rulebook();
+        let ctx = RewriteContext::new(IdAllocator::new());
+        let Term::Agent(ctr) = ctx.create_agent(
+            AgentKind::Constructor,
+            &[ctx.create_port(), ctx.create_port()],
+        ) else {
+            unreachable!();
+        };
+        let Term::Agent(reference) = ctx.create_agent(id, &[]) else {
+            unreachable!();
+        };
+
+        let result = rulebook.rewrite(&ctx, ctr, reference);
+
+        assert_eq!(
+            kinds(&result),
+            [AgentKind::Constructor, AgentKind::Constructor]
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "`missing` is referred to but never defined")]
+    fn expanding_an_undefined_reference_panics() {
+        let mut rulebook = Rulebook::default();
+        let missing = rulebook.reference("missing");
+        let ctx = RewriteContext::new(IdAllocator::new());
+        let Term::Agent(num) = ctx.create_number(1) else {
+            unreachable!();
+        };
+        let Term::Agent(reference) = ctx.create_agent(missing, &[]) else {
+            unreachable!();
+        };
+
+        rulebook.rewrite(&ctx, num, reference);
+    }
+
+    #[test]
+    fn add_template_rejects_invalid_templates() {
+        let mut rulebook = Rulebook::default();
+        let kind = rulebook.declare_agent(0, 1);
+        let port = |id| Term::Port(Port::new(id));
+
+        // the auxiliary port of the dynamic agent is never used
+        let template = RuleTemplate::new(
+            Agent::new(1, kind, [port(2)]),
+            Agent::new_constructor(3, port(4), port(5)),
+            vec![Connection(port(4), port(5))],
+        );
+        let pattern = template.pattern();
+
+        assert_eq!(
+            rulebook.add_template(template).err(),
+            Some(TemplateError::PortCount {
+                port_id: 2,
+                count: 1
+            })
+        );
+        assert!(rulebook.rules().all(|(other, _)| *other != pattern));
+
+        let template = RuleTemplate::new(
+            Agent::new(1, kind, [port(2)]),
+            Agent::new_constructor(3, port(4), port(5)),
+            vec![
+                Connection(port(4), port(5)),
+                Connection(port(2), Term::Agent(Agent::new_eraser(6))),
+            ],
+        );
+        assert!(rulebook.add_template(template).is_ok());
+        assert!(rulebook.rules().any(|(other, _)| *other == pattern));
+    }
+
+    /// Reduces a net given in the text format to normal form.
+    fn normalize(rulebook: Rulebook, sources: &[&str]) -> Vec<(Term, Term)> {
+        let connections: Vec<_> = sources
+            .iter()
+            .map(|src| parse_connection(src).unwrap())
+            .collect();
+        let ctx = RewriteContext::new(IdAllocator::new_at(100));
+
+        Runtime::new(connections, rulebook, ctx)
+            .normalize()
+            .into_iter()
+            .collect()
+    }
+
+    /// What's connected to the interface port called `name`.
+    fn named<'a>(normal_form: &'a [(Term, Term)], name: &str) -> &'a Agent {
+        let is_named =
+            |term: &Term| matches!(term, Term::Port(port) if port.name.as_deref() == Some(name));
+
+        normal_form
+            .iter()
+            .find_map(|(left, right)| match (left, right) {
+                (port, Term::Agent(agent)) | (Term::Agent(agent), port) if is_named(port) => {
+                    Some(agent)
+                }
+                _ => None,
+            })
+            .unwrap_or_else(|| panic!("expected `{name}` to be connected to an agent"))
+    }
+
+    #[test]
+    fn erasing_a_declared_agent_erases_each_of_its_ports() {
+        let mut rulebook = Rulebook::default();
+        rulebook.declare_agent(0, 3);
+
+        let normal_form = normalize(
+            rulebook,
+            &[r#"Eraser#1() = Dynamic[0]#2($3:"a", $4:"b", $5:"c")"#],
+        );
+
+        assert_eq!(normal_form.len(), 3);
+        for name in ["a", "b", "c"] {
+            assert_eq!(named(&normal_form, name).kind, AgentKind::Eraser);
+        }
+    }
+
+    #[test]
+    fn duplicating_a_declared_agent_commutes() {
+        let mut rulebook = Rulebook::default();
+        let kind = rulebook.declare_agent(0, 2);
+
+        let normal_form = normalize(
+            rulebook,
+            &[r#"Duplicator#1($2:"x", $3:"y") = Dynamic[0]#4($5:"a", $6:"b")"#],
+        );
+
+        // a copy on each side of the duplicator, with a duplicator on each of
+        // the original's ports whose ports cross over to the copies
+        let (x, y) = (named(&normal_form, "x"), named(&normal_form, "y"));
+        assert_eq!((x.kind, y.kind), (kind, kind));
+        assert_ne!(x.id, y.id);
+        for (index, name) in ["a", "b"].into_iter().enumerate() {
+            let dup = named(&normal_form, name);
+            assert_eq!(dup.kind, AgentKind::Duplicator);
+            assert!(dup.ports[0] == x.ports[index], "`{name}` should go to x");
+            assert!(dup.ports[1] == y.ports[index], "`{name}` should go to y");
+        }
+        assert_eq!(normal_form.len(), 4);
+    }
+
+    #[test]
+    fn declaring_an_agent_keeps_the_rules_it_already_has() {
+        let mut rulebook = Rulebook::default();
+        rulebook.add_rule(
+            (AgentKind::Dynamic(0), AgentKind::Eraser).into(),
+            // hands back a number rather than erasing the port
+            Rule::Dynamic(Box::new(|ctx, a, b| {
+                let agent = if a.kind == AgentKind::Eraser { b } else { a };
+                let [port] = agent.ports_array().unwrap();
+
+                vec![ctx.create_number(7).connect(port)].into()
+            })),
+        );
+        rulebook.declare_agent(0, 1);
+
+        let normal_form = normalize(rulebook, &[r#"Eraser#1() = Dynamic[0]#2($3:"a")"#]);
+
+        let a = named(&normal_form, "a");
+        assert_eq!((a.kind, a.data), (AgentKind::Number, 7));
     }
 }
@@ -0,0 +1,254 @@
+use std::{
+    collections::BTreeMap,
+    fmt::{self, Display},
+};
+
+use rustc_hash::FxHashMap as HashMap;
+
+use super::{context::RewriteContext, rulebook::ActivePairPattern, RewriteResult};
+use crate::net::{
+    connection::Connection,
+    term::{Agent, AgentKind, Port, Term},
+};
+
+/// Copies terms with fresh IDs from a [`RewriteContext`].
+///
+/// A port that shows up twice in the copied terms gets the same fresh ID both
+/// times. A port can also be bound to a term beforehand, in which case that
+/// term is used in place of the port.
+pub struct Instantiator<'a> {
+    ctx: &'a RewriteContext,
+    /// Bound ports, and fresh ports that have been seen once so far.
+    ports: HashMap<usize, Term>,
+}
+
+impl<'a> Instantiator<'a> {
+    pub fn new(ctx: &'a RewriteContext) -> Self {
+        Self {
+            ctx,
+            ports: HashMap::default(),
+        }
+    }
+
+    /// Uses `term` in place of the port with this ID. The port should only
+    /// show up once in the copied terms.
+    pub fn bind(&mut self, port_id: usize, term: Term) {
+        self.ports.insert(port_id, term);
+    }
+
+    pub fn instantiate(&mut self, term: &Term) -> Term {
+        match term {
+            Term::Port(port) => {
+                if let Some(term) = self.ports.remove(term.id()) {
+                    return term;
+                }
+
+                let mut fresh = Port::new(self.ctx.id_alloc.create_id());
+                fresh.name = port.name.clone();
+                let fresh = Term::Port(fresh);
+
+                self.ports.insert(*term.id(), fresh.clone());
+                fresh
+            }
+            Term::Agent(agent) => {
+                let ports: Vec<_> = agent
+                    .ports
+                    .iter()
+                    .map(|port| self.instantiate(port))
+                    .collect();
+
+                let copy = Agent::new(self.ctx.id_alloc.create_id(), agent.kind, ports)
+                    .with_data(agent.data);
+
+                Term::Agent(match agent.name() {
+                    Some(name) => copy.with_name(name),
+                    None => copy,
+                })
+            }
+        }
+    }
+
+    pub fn instantiate_connection(&mut self, connection: &Connection) -> Connection {
+        Connection(
+            self.instantiate(connection.left()),
+            self.instantiate(connection.right()),
+        )
+    }
+}
+
+/// A rule whose right-hand side is a net, rather than a closure.
+///
+/// The auxiliary ports of the two agents on the left-hand side are ports, and
+/// each of them shows up once on the right-hand side. Any other port on the
+/// right-hand side shows up twice, and gets a fresh ID every time the rule is
+/// used, as do the agents.
+///
+/// It prints in the same syntax as the `rules` file:
+///
+/// ```text
+/// C(a0, a1) = C(b0, b1) -> a0 = b0, a1 = b1
+/// ```
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RuleTemplate {
+    left: Agent,
+    right: Agent,
+    connections: Vec<Connection>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TemplateError {
+    /// A port of an agent on the left-hand side is an agent.
+    NestedAgent { agent_id: usize },
+    /// A port shows up the wrong number of times. Ports of the agents on the
+    /// left-hand side should show up once on the right-hand side, and any
+    /// other port twice.
+    PortCount { port_id: usize, count: usize },
+}
+
+impl RuleTemplate {
+    pub fn new(left: Agent, right: Agent, connections: Vec<Connection>) -> Self {
+        // rules get their agents in the same order as their pattern
+        let (left, right) = if left.kind <= right.kind {
+            (left, right)
+        } else {
+            (right, left)
+        };
+
+        Self {
+            left,
+            right,
+            connections,
+        }
+    }
+
+    pub fn pattern(&self) -> ActivePairPattern {
+        ActivePairPattern::from_agents(&self.left, &self.right)
+    }
+
+    pub fn left(&self) -> &Agent {
+        &self.left
+    }
+
+    pub fn right(&self) -> &Agent {
+        &self.right
+    }
+
+    pub fn connections(&self) -> &[Connection] {
+        &self.connections
+    }
+
+    /// Checks that the ports of the template are wired up properly.
+    pub fn validate(&self) -> Result<(), TemplateError> {
+        fn count(term: &Term, counts: &mut BTreeMap<usize, usize>) {
+            match term {
+                Term::Port(_) => *counts.entry(*term.id()).or_default() += 1,
+                Term::Agent(agent) => agent.ports.iter().for_each(|port| count(port, counts)),
+            }
+        }
+
+        // ports of the active pair count as being used once already
+        let mut counts = BTreeMap::new();
+        for agent in [&self.left, &self.right] {
+            for port in agent.ports.iter() {
+                if let Term::Agent(agent) = port {
+                    return Err(TemplateError::NestedAgent { agent_id: agent.id });
+                }
+                count(port, &mut counts);
+            }
+        }
+
+        for connection in &self.connections {
+            count(connection.left(), &mut counts);
+            count(connection.right(), &mut counts);
+        }
+
+        match counts.into_iter().find(|&(_, count)| count != 2) {
+            Some((port_id, count)) => Err(TemplateError::PortCount { port_id, count }),
+            None => Ok(()),
+        }
+    }
+
+    pub fn rewrite(&self, ctx: &RewriteContext, a: Agent, b: Agent) -> RewriteResult {
+        debug_assert!(a.kind == self.left.kind && b.kind == self.right.kind);
+
+        ctx.id_alloc.retire_id(a.id);
+        ctx.id_alloc.retire_id(b.id);
+
+        let mut instantiator = Instantiator::new(ctx);
+        for (template, agent) in [(&self.left, a), (&self.right, b)] {
+            assert!(template.ports.len() == agent.ports.len());
+
+            for (variable, port) in template.ports.iter().zip(Vec::from(agent.ports)) {
+                instantiator.bind(*variable.id(), port);
+            }
+        }
+
+        RewriteResult {
+            new_connections: self
+                .connections
+                .iter()
+                .map(|connection| instantiator.instantiate_connection(connection))
+                .collect(),
+        }
+    }
+}
+
+/// Writes a term in the syntax of the `rules` file, where ports are written as
+/// their name (or `_` and their ID if they don't have one).
+pub fn write_rule_term(f: &mut impl fmt::Write, term: &Term) -> fmt::Result {
+    let agent = match term {
+        Term::Port(port) => {
+            return match &port.name {
+                Some(name) => write!(f, "{name}"),
+                None => write!(f, "_{}", term.id()),
+            }
+        }
+        Term::Agent(agent) => agent,
+    };
+
+    match agent.kind {
+        AgentKind::Eraser => write!(f, "E")?,
+        AgentKind::Duplicator => write!(f, "D")?,
+        AgentKind::Constructor => write!(f, "C")?,
+        kind => write!(f, "{kind:?}")?,
+    }
+
+    if agent.kind.has_data() || agent.data != 0 {
+        write!(f, "{{{}}}", agent.data)?;
+    }
+
+    if agent.ports.is_empty() {
+        return Ok(());
+    }
+
+    write!(f, "(")?;
+    for (index, port) in agent.ports.iter().enumerate() {
+        if index > 0 {
+            write!(f, ", ")?;
+        }
+        write_rule_term(f, port)?;
+    }
+    write!(f, ")")
+}
+
+impl Display for RuleTemplate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_rule_term(f, &Term::Agent(self.left.clone()))?;
+        write!(f, " = ")?;
+        write_rule_term(f, &Term::Agent(self.right.clone()))?;
+        write!(f, " ->")?;
+
+        if self.connections.is_empty() {
+            return write!(f, " ()");
+        }
+
+        for (index, connection) in self.connections.iter().enumerate() {
+            write!(f, "{}", if index > 0 { ", " } else { " " })?;
+            write_rule_term(f, connection.left())?;
+            write!(f, " = ")?;
+            write_rule_term(f, connection.right())?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,380 @@
+//! Checking confluence empirically, by reducing the same net in different
+//! orders and making sure they all end up in the same place.
+//!
+//! Interaction nets are confluent, so every order should reach an isomorphic
+//! normal form in the same number of interactions. When one doesn't, either a
+//! [`Rule::Dynamic`](crate::rule::Rule::Dynamic) is wrong, or wires were
+//! merged wrongly while the net was being rewritten.
+
+use super::Runtime;
+use crate::net::{canonical::CanonicalNet, connection::Connection};
+
+/// A small, seedable random number generator (SplitMix64), so that random
+/// orders can be reproduced from their seed.
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    /// A number below `bound`, which shouldn't be zero.
+    fn below(&mut self, bound: usize) -> usize {
+        (self.next() % bound as u64) as usize
+    }
+}
+
+impl Runtime {
+    /// Reduces active pairs until there are none left, picking the next one
+    /// to reduce at random. The same seed always picks the same order.
+    pub fn reduce_in_random_order(&mut self, seed: u64) {
+        let mut rng = SplitMix64(seed);
+        self.reduce_choosing(|pending| rng.below(pending));
+    }
+
+    /// Reduces active pairs until there are none left. Before each
+    /// interaction, `choose` is given the number of pending active pairs and
+    /// picks one, counting back from the one [`Runtime::reduce`] would pick.
+    fn reduce_choosing(&mut self, mut choose: impl FnMut(usize) -> usize) {
+        while !self.action_stack.is_empty() {
+            let pending = self.action_stack.len();
+            let choice = choose(pending);
+            assert!(choice < pending);
+
+            let action = self.action_stack.swap_remove(pending - 1 - choice);
+            self.apply(action);
+        }
+    }
+
+    fn canonical_normal_form(mut self) -> (CanonicalNet, usize) {
+        self.reduce();
+        let interactions = self.interactions;
+        let normal_form = CanonicalNet::new(self.normalize().into_iter().map(Connection::from));
+
+        (normal_form, interactions)
+    }
+}
+
+/// An order that a net was reduced in.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Order {
+    /// A random order, from [`Runtime::reduce_in_random_order`].
+    Random { seed: u64 },
+    /// Which pending active pair was picked before each interaction, counting
+    /// back from the one [`Runtime::reduce`] would pick. Once the choices run
+    /// out, it's the default order.
+    Choices(Vec<usize>),
+}
+
+#[derive(Debug)]
+pub enum ConfluenceError {
+    /// Reducing in this order reached a normal form that isn't isomorphic to
+    /// the one from the default order.
+    NormalForm {
+        order: Order,
+        expected: CanonicalNet,
+        found: CanonicalNet,
+    },
+    /// Reducing in this order took a different number of interactions than
+    /// the default order did.
+    Interactions {
+        order: Order,
+        expected: usize,
+        found: usize,
+    },
+}
+
+#[derive(Debug)]
+pub struct Exploration {
+    /// The normal form that every order reached.
+    pub normal_form: CanonicalNet,
+    /// The number of interactions that every order took.
+    pub interactions: usize,
+    /// How many orders were tried, including the default one.
+    pub orders: usize,
+    /// Whether every possible order was tried.
+    pub exhaustive: bool,
+}
+
+struct Checker {
+    normal_form: CanonicalNet,
+    interactions: usize,
+    orders: usize,
+}
+
+impl Checker {
+    fn new(runtime: Runtime) -> Self {
+        let (normal_form, interactions) = runtime.canonical_normal_form();
+
+        Self {
+            normal_form,
+            interactions,
+            orders: 1,
+        }
+    }
+
+    fn check(
+        &mut self,
+        runtime: Runtime,
+        order: impl FnOnce() -> Order,
+    ) -> Result<(), ConfluenceError> {
+        let (normal_form, interactions) = runtime.canonical_normal_form();
+        self.orders += 1;
+
+        if normal_form != self.normal_form {
+            return Err(ConfluenceError::NormalForm {
+                order: order(),
+                expected: std::mem::replace(&mut self.normal_form, CanonicalNet::new([])),
+                found: normal_form,
+            });
+        }
+
+        if interactions != self.interactions {
+            return Err(ConfluenceError::Interactions {
+                order: order(),
+                expected: self.interactions,
+                found: interactions,
+            });
+        }
+
+        Ok(())
+    }
+
+    fn finish(self, exhaustive: bool) -> Exploration {
+        Exploration {
+            normal_form: self.normal_form,
+            interactions: self.interactions,
+            orders: self.orders,
+            exhaustive,
+        }
+    }
+}
+
+/// Reduces a net in the default order, then in `runs` random orders seeded
+/// from `seed` onwards, and checks that they all agree. `make` should build a
+/// fresh runtime for the same net each time it's called.
+pub fn check_random_orders(
+    mut make: impl FnMut() -> Runtime,
+    seed: u64,
+    runs: usize,
+) -> Result<Exploration, ConfluenceError> {
+    let mut checker = Checker::new(make());
+
+    for seed in (seed..).take(runs) {
+        let mut runtime = make();
+        runtime.reduce_in_random_order(seed);
+        checker.check(runtime, || Order::Random { seed })?;
+    }
+
+    Ok(checker.finish(false))
+}
+
+/// Reduces a net in every possible order, up to `max_orders` of them, and
+/// checks that they all agree. `make` should build a fresh runtime for the
+/// same net each time it's called.
+///
+/// The number of orders grows very quickly with the size of the net, so this
+/// is only useful for small ones.
+pub fn check_all_orders(
+    mut make: impl FnMut() -> Runtime,
+    max_orders: usize,
+) -> Result<Exploration, ConfluenceError> {
+    let mut checker: Option<Checker> = None;
+    // the choice made before each interaction of the last order, and how many
+    // there were to choose from. it starts out as the default order.
+    let mut choices = Vec::<(usize, usize)>::new();
+
+    loop {
+        let mut runtime = make();
+        let mut step = 0;
+        runtime.reduce_choosing(|pending| {
+            if step == choices.len() {
+                choices.push((0, pending));
+            }
+            step += 1;
+
+            choices[step - 1].0
+        });
+        choices.truncate(step);
+
+        match &mut checker {
+            Some(checker) => checker.check(runtime, || {
+                Order::Choices(choices.iter().map(|&(choice, _)| choice).collect())
+            })?,
+            None => checker = Some(Checker::new(runtime)),
+        }
+
+        // move on to the next order, like an odometer
+        while let Some((choice, pending)) = choices.pop() {
+            if choice + 1 < pending {
+                choices.push((choice + 1, pending));
+                break;
+            }
+        }
+
+        let exhaustive = choices.is_empty();
+        if exhaustive || checker.as_ref().unwrap().orders >= max_orders {
+            return Ok(checker.unwrap().finish(exhaustive));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    };
+
+    use super::*;
+    use crate::{
+        hvm,
+        net::{id::IdAllocator, term::AgentKind, text::parse_connection},
+        rule::{context::RewriteContext, rulebook::Rulebook, RewriteResult, Rule},
+    };
+
+    fn runtime(sources: &[&str], rulebook: Rulebook) -> Runtime {
+        let connections: Vec<_> = sources
+            .iter()
+            .map(|src| parse_connection(src).unwrap())
+            .collect();
+
+        Runtime::new(
+            connections,
+            rulebook,
+            RewriteContext::new(IdAllocator::new_at(100)),
+        )
+    }
+
+    /// A net with a couple of independent erasures, and a rulebook where
+    /// `Dynamic[0]` meeting an eraser numbers its result by how many of them
+    /// came before it, which depends on the order.
+    fn counter(sources: &[&str]) -> Runtime {
+        let mut rulebook = Rulebook::default();
+        let count = AtomicU64::new(0);
+        rulebook.add_rule(
+            (AgentKind::Dynamic(0), AgentKind::Eraser).into(),
+            Rule::Dynamic(Box::new(move |ctx, _, agent| {
+                let [out] = agent.ports_array().unwrap();
+                let n = count.fetch_add(1, Ordering::Relaxed);
+                vec![ctx.create_number(n).connect(out)].into()
+            })),
+        );
+
+        runtime(sources, rulebook)
+    }
+
+    #[test]
+    fn random_orders_agree_on_a_confluent_net() {
+        let book = "@main = r & {a b} ~ @tree & (a (b r)) ~ ((x x) ((y y) *))\n\
+                    @tree = ((* *) (* *))";
+        let make = || {
+            let book = hvm::parse_book(book).unwrap();
+            Runtime::new(book.connections, book.rulebook, book.ctx)
+        };
+
+        let exploration = check_random_orders(make, 7, 20).unwrap();
+        assert_eq!(exploration.orders, 21);
+        assert!(!exploration.exhaustive);
+        assert_eq!(exploration.interactions, {
+            let mut runtime = make();
+            runtime.reduce();
+            runtime.interactions()
+        });
+    }
+
+    #[test]
+    fn tries_every_order_of_a_small_net() {
+        let net = [
+            "Eraser#1() = Eraser#2()",
+            "Eraser#3() = Constructor#4($5, $6)",
+            "Eraser#7() = Duplicator#8($5, $6)",
+        ];
+
+        let exploration = check_all_orders(|| runtime(&net, Rulebook::default()), 100).unwrap();
+        // the constructor and duplicator in either order, then the two pairs
+        // of erasers they leave behind in either order, with the first pair
+        // of erasers reduced at any of five points along the way
+        assert!(exploration.exhaustive);
+        assert_eq!(exploration.orders, 2 * 2 * 5);
+        assert_eq!(exploration.interactions, 5);
+    }
+
+    #[test]
+    fn stops_after_the_most_orders_it_was_allowed() {
+        let net = [
+            "Eraser#1() = Eraser#2()",
+            "Eraser#3() = Eraser#4()",
+            "Eraser#5() = Eraser#6()",
+        ];
+
+        let exploration = check_all_orders(|| runtime(&net, Rulebook::default()), 4).unwrap();
+        assert!(!exploration.exhaustive);
+        assert_eq!(exploration.orders, 4);
+    }
+
+    #[test]
+    fn finds_a_rule_that_depends_on_the_order() {
+        let net = [
+            r#"Dynamic[0]#1($2:"a") = Eraser#3()"#,
+            r#"Dynamic[0]#4($5:"b") = Eraser#6()"#,
+        ];
+
+        let error = check_all_orders(|| counter(&net), 100).unwrap_err();
+        let ConfluenceError::NormalForm { order, .. } = error else {
+            panic!("expected different normal forms, not {error:?}");
+        };
+        assert_eq!(order, Order::Choices(vec![1, 0]));
+
+        assert!(matches!(
+            check_random_orders(|| counter(&net), 0, 50),
+            Err(ConfluenceError::NormalForm {
+                order: Order::Random { .. },
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn finds_rules_that_take_more_interactions_in_some_orders() {
+        // `Dynamic[1]` leaves an extra pair of erasers behind if it's reduced
+        // before `Dynamic[0]`, which doesn't change the normal form
+        let rulebook = || {
+            let mut rulebook = Rulebook::default();
+            let erased = Arc::new(AtomicU64::new(0));
+
+            let count = erased.clone();
+            rulebook.add_rule(
+                (AgentKind::Dynamic(0), AgentKind::Eraser).into(),
+                Rule::Dynamic(Box::new(move |_, _, _| {
+                    count.fetch_add(1, Ordering::Relaxed);
+                    RewriteResult::empty()
+                })),
+            );
+            rulebook.add_rule(
+                (AgentKind::Dynamic(1), AgentKind::Eraser).into(),
+                Rule::Dynamic(Box::new(move |ctx, _, _| {
+                    if erased.load(Ordering::Relaxed) > 0 {
+                        return RewriteResult::empty();
+                    }
+
+                    let era = ctx.create_agent(AgentKind::Eraser, &[]);
+                    vec![era.connect(ctx.create_agent(AgentKind::Eraser, &[]))].into()
+                })),
+            );
+
+            rulebook
+        };
+        let net = ["Dynamic[0]#1() = Eraser#2()", "Dynamic[1]#3() = Eraser#4()"];
+
+        let error = check_all_orders(|| runtime(&net, rulebook()), 100).unwrap_err();
+        assert!(
+            matches!(error, ConfluenceError::Interactions { .. }),
+            "expected different interaction counts, not {error:?}"
+        );
+    }
+}
@@ -0,0 +1,157 @@
+//! Getting rid of parts of the net that the interface can't see.
+//!
+//! Results are read from the interface, so anything that isn't connected to
+//! it, like an erased subnet that was cut loose or a vicious circle, can never
+//! change them. It still takes up memory and IDs, and its active pairs still
+//! get reduced, so collecting it can save work as well.
+
+use rustc_hash::FxHashSet as HashSet;
+
+use super::{Action, Runtime};
+use crate::net::{interface, term::Term};
+
+#[derive(Clone, Debug, Default)]
+pub struct GarbageStats {
+    /// Number of connections that were removed.
+    pub connections: usize,
+    /// Number of agents in them.
+    pub agents: usize,
+    /// Number of distinct ports in them.
+    pub ports: usize,
+}
+
+impl Runtime {
+    /// Removes every part of the net that can't be reached from an interface
+    /// port, including vicious circles and active pairs that would never
+    /// stop reducing, and retires their IDs. A net without any interface is
+    /// all garbage.
+    ///
+    /// Erasers do the same job one interaction at a time, but can't get rid
+    /// of parts that nothing is connected to.
+    ///
+    /// Any recorded history starts over from here, since the net it recorded
+    /// can't be gone back to without the garbage.
+    pub fn collect_garbage(&mut self) -> GarbageStats {
+        self.restart_history();
+
+        fn collect_ids(term: &Term, agents: &mut Vec<usize>, ports: &mut HashSet<usize>) {
+            match term {
+                Term::Port(_) => {
+                    ports.insert(*term.id());
+                }
+                Term::Agent(agent) => {
+                    agents.push(agent.id);
+                    for port in agent.ports.iter() {
+                        collect_ids(port, agents, ports);
+                    }
+                }
+            }
+        }
+
+        let reachable = interface::reachable_from_interface(
+            self.connections.iter().map(|(left, right)| (left, right)),
+        );
+        let garbage: Vec<_> = self
+            .connections
+            .iter()
+            .zip(reachable)
+            .filter(|(_, reachable)| !reachable)
+            .map(|((left, _), _)| *left.id())
+            .collect();
+
+        let mut agents = Vec::new();
+        let mut ports = HashSet::default();
+        for key in &garbage {
+            let (left, right) = self.connections.remove_by_left_key(key).unwrap();
+            collect_ids(&left, &mut agents, &mut ports);
+            collect_ids(&right, &mut agents, &mut ports);
+        }
+
+        let stats = GarbageStats {
+            connections: garbage.len(),
+            agents: agents.len(),
+            ports: ports.len(),
+        };
+        for id in agents.into_iter().chain(ports) {
+            self.ctx.id_alloc.retire_id(id);
+        }
+
+        let garbage: HashSet<_> = garbage.into_iter().collect();
+        self.action_stack
+            .retain(|Action::Reduce(id)| !garbage.contains(id));
+
+        stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        net::{id::IdAllocator, text::parse_connection},
+        rule::{context::RewriteContext, rulebook::Rulebook},
+    };
+
+    fn runtime(sources: &[&str]) -> Runtime {
+        let connections: Vec<_> = sources
+            .iter()
+            .map(|src| parse_connection(src).unwrap())
+            .collect();
+
+        Runtime::new(
+            connections,
+            Rulebook::default(),
+            RewriteContext::new(IdAllocator::new_at(100)),
+        )
+    }
+
+    #[test]
+    fn removes_what_the_interface_cant_reach() {
+        let mut runtime = runtime(&[
+            r#"$1:"out" = Constructor#2($3, Eraser#4())"#,
+            "$3 = Eraser#5()",
+            "Constructor#6($7, $8) = Duplicator#9($7, $8)",
+            "Constructor#10($11, Eraser#12()) = $11",
+        ]);
+
+        let stats = runtime.collect_garbage();
+        assert_eq!((stats.connections, stats.agents, stats.ports), (2, 4, 3));
+
+        // the kept part is untouched
+        let kept = runtime.query("out").unwrap();
+        assert_eq!(kept.len(), 2);
+        assert_eq!(runtime.interface().len(), 1);
+
+        // the garbage's IDs were retired, the rest are still in use
+        let ids = &runtime.ctx.id_alloc;
+        for id in [6, 7, 8, 9, 10, 11, 12] {
+            assert!(!ids.is_live(id), "{id} should be retired");
+        }
+        for id in [1, 2, 3, 4, 5] {
+            assert!(ids.is_live(id), "{id} should be in use");
+        }
+    }
+
+    #[test]
+    fn drops_the_active_pairs_it_removes() {
+        let mut runtime = runtime(&["Constructor#1($2, $3) = Duplicator#4($2, $3)"]);
+        assert_eq!(runtime.action_stack.len(), 1);
+
+        let stats = runtime.collect_garbage();
+        assert_eq!(stats.connections, 1);
+        assert!(runtime.action_stack.is_empty());
+
+        runtime.reduce();
+        assert_eq!(runtime.interactions(), 0);
+        assert_eq!(runtime.normalize().into_iter().count(), 0);
+    }
+
+    #[test]
+    fn keeps_a_net_without_garbage() {
+        let mut runtime = runtime(&[r#"$1:"a" = Constructor#2($3:"b", $4:"c")"#]);
+
+        let stats = runtime.collect_garbage();
+        assert_eq!((stats.connections, stats.agents, stats.ports), (0, 0, 0));
+        assert_eq!(runtime.interface().len(), 3);
+    }
+}
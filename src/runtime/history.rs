@@ -0,0 +1,441 @@
+//! Going backwards and forwards through a run, one interaction at a time.
+//!
+//! While a history is being recorded, every change that reducing makes to the
+//! map of connections is kept: the active pair that was taken out, any
+//! connections that were taken out to merge wires, and the connections that
+//! were put in. Undoing an interaction makes the same changes in reverse, and
+//! redoing it makes them again, so rules are never rerun. This is enough to
+//! bisect a long run for the interaction where something went wrong.
+//!
+//! Only the net goes back in time. The ID allocator keeps going forwards, so
+//! IDs that an undone interaction retired stay retired, which is fine because
+//! an ID that gets handed out again has a newer generation. Reducing from an
+//! earlier interaction throws away the history after it.
+
+use std::mem;
+
+use super::{Action, Runtime};
+use crate::{map::ConnectionMap, net::term::Term};
+
+/// A change to the map of connections.
+#[derive(Debug)]
+pub enum Change {
+    /// A connection was taken out: the active pair at the start of an
+    /// interaction, or a connection that a new one was merged with.
+    Removed(Term, Term),
+    /// A connection was put in.
+    Inserted(Term, Term),
+}
+
+/// What a single interaction did.
+#[derive(Debug, Default)]
+pub struct Step {
+    changes: Vec<Change>,
+}
+
+fn has_agent(term: &Term, agent_id: usize) -> bool {
+    match term {
+        Term::Agent(agent) => {
+            agent.id == agent_id || agent.ports.iter().any(|port| has_agent(port, agent_id))
+        }
+        Term::Port(_) => false,
+    }
+}
+
+impl Step {
+    /// The active pair that was reduced.
+    pub fn pair(&self) -> (&Term, &Term) {
+        match &self.changes[0] {
+            Change::Removed(left, right) => (left, right),
+            Change::Inserted(..) => unreachable!("a step should start by removing its pair"),
+        }
+    }
+
+    /// Every change to the map, in the order it was made.
+    pub fn changes(&self) -> &[Change] {
+        &self.changes
+    }
+
+    /// Whether the agent was put in without having been taken out, rather
+    /// than just moved to a new connection.
+    fn created(&self, agent_id: usize) -> bool {
+        let mut removed = false;
+        let mut inserted = false;
+
+        for change in &self.changes {
+            match change {
+                Change::Removed(left, right) => {
+                    removed |= has_agent(left, agent_id) || has_agent(right, agent_id);
+                }
+                Change::Inserted(left, right) => {
+                    inserted |= has_agent(left, agent_id) || has_agent(right, agent_id);
+                }
+            }
+        }
+
+        inserted && !removed
+    }
+
+    fn undo(&self, connections: &mut ConnectionMap<Term, Term>) {
+        for change in self.changes.iter().rev() {
+            match change {
+                Change::Removed(left, right) => {
+                    connections.insert(left.clone(), right.clone()).unwrap();
+                }
+                Change::Inserted(left, _) => {
+                    connections.remove_by_left_key(left.id()).unwrap();
+                }
+            }
+        }
+    }
+
+    fn redo(&self, connections: &mut ConnectionMap<Term, Term>) {
+        for change in &self.changes {
+            match change {
+                Change::Removed(left, _) => {
+                    connections.remove_by_left_key(left.id()).unwrap();
+                }
+                Change::Inserted(left, right) => {
+                    connections.insert(left.clone(), right.clone()).unwrap();
+                }
+            }
+        }
+    }
+}
+
+/// Every interaction since [`Runtime::record_history`] was called, as the
+/// changes it made, along with where the recording starts.
+pub struct History {
+    /// The number of interactions there had been when recording started.
+    start: usize,
+    steps: Vec<Step>,
+    /// The action stack after the last recorded interaction, kept while the
+    /// runtime is back at an earlier one.
+    latest_actions: Option<Vec<Action>>,
+}
+
+impl History {
+    fn new(start: usize) -> Self {
+        Self {
+            start,
+            steps: Vec::new(),
+            latest_actions: None,
+        }
+    }
+
+    /// Starts recording a new interaction, after throwing away any that come
+    /// after the current one.
+    pub(super) fn begin(&mut self, interactions: usize) {
+        self.steps.truncate(interactions - self.start);
+        self.latest_actions = None;
+        self.steps.push(Step::default());
+    }
+
+    pub(super) fn record(&mut self, change: Change) {
+        self.steps.last_mut().unwrap().changes.push(change);
+    }
+
+    /// The number of interactions there had been when recording started.
+    pub fn start(&self) -> usize {
+        self.start
+    }
+
+    /// The number of interactions there had been after the last recorded
+    /// one.
+    pub fn end(&self) -> usize {
+        self.start + self.steps.len()
+    }
+
+    /// What interaction `n` did, counting from one like
+    /// [`Runtime::interactions`], or `None` if it wasn't recorded.
+    pub fn interaction(&self, n: usize) -> Option<&Step> {
+        self.steps.get(n.checked_sub(self.start + 1)?)
+    }
+
+    /// The first recorded interaction that created the agent with this ID,
+    /// counting from one like [`Runtime::interactions`].
+    pub fn created_by(&self, agent_id: usize) -> Option<usize> {
+        let index = self.steps.iter().position(|step| step.created(agent_id))?;
+
+        Some(self.start + index + 1)
+    }
+}
+
+impl Runtime {
+    /// Starts recording every interaction from here on, so that they can be
+    /// stepped back through. Does nothing if a history is already being
+    /// recorded.
+    pub fn record_history(&mut self) {
+        if self.history.is_none() {
+            self.history = Some(History::new(self.interactions));
+        }
+    }
+
+    /// The interactions recorded so far, or `None` if
+    /// [`Runtime::record_history`] hasn't been called.
+    pub fn history(&self) -> Option<&History> {
+        self.history.as_ref()
+    }
+
+    pub(super) fn restart_history(&mut self) {
+        if let Some(history) = &mut self.history {
+            *history = History::new(self.interactions);
+        }
+    }
+
+    /// Undoes the last interaction. Returns `false` if it wasn't recorded.
+    pub fn step_back(&mut self) -> bool {
+        match self.interactions.checked_sub(1) {
+            Some(interaction) => self.jump_to(interaction),
+            None => false,
+        }
+    }
+
+    /// Redoes the next recorded interaction. Returns `false` if there isn't
+    /// one.
+    pub fn step_forward(&mut self) -> bool {
+        self.jump_to(self.interactions + 1)
+    }
+
+    /// Undoes or redoes recorded interactions until there have been
+    /// `interaction` of them. Returns `false`, without changing anything, if
+    /// the history doesn't reach that far.
+    ///
+    /// Away from the last recorded interaction, the active pairs are queued
+    /// up by ID rather than in the order they originally were, which only
+    /// matters if reducing carries on from there.
+    pub fn jump_to(&mut self, interaction: usize) -> bool {
+        let Some(history) = &mut self.history else {
+            return false;
+        };
+        if !(history.start..=history.end()).contains(&interaction) {
+            return false;
+        }
+        if interaction == self.interactions {
+            return true;
+        }
+
+        if self.interactions == history.end() {
+            history.latest_actions = Some(mem::take(&mut self.action_stack));
+        }
+
+        while self.interactions > interaction {
+            self.interactions -= 1;
+            history.steps[self.interactions - history.start].undo(&mut self.connections);
+        }
+        while self.interactions < interaction {
+            history.steps[self.interactions - history.start].redo(&mut self.connections);
+            self.interactions += 1;
+        }
+
+        self.action_stack = if interaction == history.end() {
+            history
+                .latest_actions
+                .take()
+                .expect("invalid runtime state: history lost the latest actions")
+        } else {
+            let mut pairs: Vec<_> = self
+                .connections
+                .iter()
+                .filter(|(left, right)| matches!((left, right), (Term::Agent(_), Term::Agent(_))))
+                .map(|(left, _)| *left.id())
+                .collect();
+            pairs.sort_unstable();
+
+            pairs.into_iter().map(Action::Reduce).collect()
+        };
+
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rustc_hash::FxHashSet as HashSet;
+
+    use super::*;
+    use crate::{
+        net::{
+            canonical::CanonicalNet, connection::Connection, id::IdAllocator,
+            text::parse_connection,
+        },
+        rule::{context::RewriteContext, rulebook::Rulebook},
+    };
+
+    /// The map, in a form that can be compared, and the action stack.
+    type State = (Vec<String>, Vec<usize>);
+
+    fn runtime() -> Runtime {
+        let connections = [
+            r#"Duplicator#1($2:"a", $3:"b") = Constructor#4(Constructor#5(Eraser#6(), $7), $8)"#,
+            r#"$7 = Number{3}#9()"#,
+            r#"BinaryOp[Add]#10($8, $11:"sum") = Number{2}#12()"#,
+        ]
+        .map(|src| parse_connection(src).unwrap());
+
+        Runtime::new(
+            connections,
+            Rulebook::default(),
+            RewriteContext::new(IdAllocator::new_at(100)),
+        )
+    }
+
+    fn state(runtime: &Runtime) -> State {
+        let mut connections: Vec<_> = runtime
+            .connections
+            .iter()
+            .map(|(left, right)| format!("{left:?} = {right:?}"))
+            .collect();
+        connections.sort_unstable();
+        let actions = runtime
+            .action_stack
+            .iter()
+            .map(|Action::Reduce(id)| *id)
+            .collect();
+
+        (connections, actions)
+    }
+
+    /// Reduces one pair at a time, keeping the state after each interaction.
+    fn reduce(runtime: &mut Runtime) -> Vec<State> {
+        let mut states = vec![state(runtime)];
+        while let Some(action) = runtime.action_stack.pop() {
+            runtime.apply(action);
+            states.push(state(runtime));
+        }
+
+        states
+    }
+
+    fn reduce_once(runtime: &mut Runtime) {
+        let action = runtime.action_stack.pop().unwrap();
+        runtime.apply(action);
+    }
+
+    /// Away from the end, the action stack is rebuilt in order of ID.
+    fn sorted((connections, mut actions): State) -> State {
+        actions.sort_unstable();
+        (connections, actions)
+    }
+
+    fn canonical(runtime: &Runtime) -> CanonicalNet {
+        CanonicalNet::new(
+            runtime
+                .connections
+                .iter()
+                .map(|(left, right)| Connection(left.clone(), right.clone())),
+        )
+    }
+
+    fn agent_ids(runtime: &Runtime) -> HashSet<usize> {
+        fn collect(term: &Term, ids: &mut HashSet<usize>) {
+            if let Term::Agent(agent) = term {
+                ids.insert(agent.id);
+                for port in agent.ports.iter() {
+                    collect(port, ids);
+                }
+            }
+        }
+
+        let mut ids = HashSet::default();
+        for (left, right) in runtime.connections.iter() {
+            collect(left, &mut ids);
+            collect(right, &mut ids);
+        }
+        ids
+    }
+
+    #[test]
+    fn steps_back_and_forward_through_every_interaction() {
+        let mut runtime = runtime();
+        runtime.record_history();
+        let states = reduce(&mut runtime);
+        let end = states.len() - 1;
+        assert!(end > 3);
+        assert_eq!(runtime.history().unwrap().end(), end);
+
+        for interaction in (0..end).rev() {
+            assert!(runtime.step_back());
+            assert_eq!(runtime.interactions(), interaction);
+            assert_eq!(state(&runtime), sorted(states[interaction].clone()));
+        }
+        assert!(!runtime.step_back());
+
+        for expected in &states[1..end] {
+            assert!(runtime.step_forward());
+            assert_eq!(state(&runtime), sorted(expected.clone()));
+        }
+        assert!(runtime.step_forward());
+        assert_eq!(state(&runtime), states[end]);
+        assert!(!runtime.step_forward());
+    }
+
+    #[test]
+    fn jumps_to_any_recorded_interaction() {
+        let mut runtime = runtime();
+        reduce_once(&mut runtime);
+        runtime.record_history();
+        let states = reduce(&mut runtime);
+        let end = states.len();
+
+        assert!(runtime.jump_to(1));
+        assert_eq!(state(&runtime), sorted(states[0].clone()));
+        assert!(runtime.jump_to(3));
+        assert_eq!(state(&runtime), sorted(states[2].clone()));
+        assert!(runtime.jump_to(end));
+        assert_eq!(state(&runtime), states[end - 1]);
+
+        // before recording started, and after the last interaction
+        assert!(!runtime.jump_to(0));
+        assert!(!runtime.jump_to(end + 1));
+        assert_eq!(state(&runtime), states[end - 1]);
+    }
+
+    #[test]
+    fn reducing_from_an_earlier_interaction_replaces_what_came_after() {
+        let mut runtime = runtime();
+        runtime.record_history();
+        let states = reduce(&mut runtime);
+
+        let expected = canonical(&runtime);
+
+        assert!(runtime.jump_to(1));
+        runtime.reduce();
+        let history = runtime.history().unwrap();
+        assert_eq!(history.end(), runtime.interactions());
+        assert_eq!(history.end(), states.len() - 1);
+        assert!(history.interaction(history.end() + 1).is_none());
+        // the IDs have moved on, but it's the same net
+        assert_eq!(canonical(&runtime), expected);
+    }
+
+    #[test]
+    fn finds_the_interaction_that_created_an_agent() {
+        let mut runtime = runtime();
+        runtime.record_history();
+
+        let mut seen = agent_ids(&runtime);
+        let mut created = 0;
+        for interaction in 1.. {
+            let Some(action) = runtime.action_stack.pop() else {
+                break;
+            };
+            runtime.apply(action);
+
+            for id in agent_ids(&runtime) {
+                if seen.insert(id) {
+                    let history = runtime.history().unwrap();
+                    assert_eq!(history.created_by(id), Some(interaction), "agent {id}");
+                    created += 1;
+                }
+            }
+        }
+        assert!(created > 0);
+
+        // the agents that were there to begin with weren't created by any
+        let history = runtime.history().unwrap();
+        for id in [1, 4, 5, 6, 9, 10, 12] {
+            assert_eq!(history.created_by(id), None, "agent {id}");
+        }
+    }
+}
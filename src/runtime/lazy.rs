@@ -0,0 +1,498 @@
+//! Reducing only what the interface depends on.
+//!
+//! [`Runtime::reduce`] reduces every active pair, including ones in garbage
+//! or in parts of the net that never stop growing. A lazy reduction only
+//! reduces the active pairs that stand between a named interface port and the
+//! agent it's waiting for, a bit like weak head normal form: once an agent's
+//! principal port faces the interface port, there's nothing left to do there.
+//! With a depth above zero, the same is done for the auxiliary ports of that
+//! agent, and so on, so an infinite structure can be looked at to a given
+//! depth without diverging.
+//!
+//! The net is indexed once when a lazy reduction or a
+//! [readback](Runtime::read_back) starts, and the runtime keeps the index up
+//! to date as it changes the net, so each interaction only costs as much as
+//! the connections it touches.
+
+use rustc_hash::{FxHashMap as HashMap, FxHashSet as HashSet};
+
+use super::{Action, Runtime};
+use crate::{
+    net::term::{Agent, AgentKind, Port, Term},
+    rule::rulebook::Rulebook,
+};
+
+/// Where a port shows up in the net.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+enum Occurrence {
+    /// In an auxiliary port of an agent, by the agent's ID and the index of
+    /// the port.
+    Aux(usize, usize),
+    /// On one side of a connection in the map, by the ID of its left side.
+    Top(usize),
+}
+
+/// One end of a wire: a port of an agent, by its ID, where zero is the
+/// principal port and `i + 1` is auxiliary port `i`, or an interface port.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub(super) enum End {
+    Agent(usize, usize),
+    Free,
+}
+
+/// What an agent's principal port is connected to, before following wires.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Principal {
+    Aux(usize, usize),
+    /// The agent is on the right of a connection to a port, by the port's ID.
+    Port(usize),
+    /// The agent is in an active pair, by the ID of the other agent and the
+    /// ID that the pair's action uses. A pair that the rulebook has no rule
+    /// for never reduces, so it has no action.
+    Pair(usize, Option<usize>),
+}
+
+/// What's in an auxiliary port of an agent.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Aux {
+    Agent(usize),
+    Port(usize),
+}
+
+/// The parts of an agent that the index needs.
+#[derive(PartialEq, Eq, Debug)]
+pub(super) struct AgentInfo {
+    pub(super) kind: AgentKind,
+    pub(super) data: u64,
+    ports: Box<[Aux]>,
+    principal: Principal,
+}
+
+impl AgentInfo {
+    pub(super) fn arity(&self) -> usize {
+        self.ports.len()
+    }
+}
+
+/// A side of a connection in the map.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Side {
+    Agent(usize),
+    Port(usize),
+}
+
+impl Side {
+    fn of(term: &Term) -> Self {
+        match term {
+            Term::Agent(_) => Self::Agent(*term.id()),
+            Term::Port(_) => Self::Port(*term.id()),
+        }
+    }
+}
+
+/// An index of the net for finding out which active pairs are needed.
+#[derive(Default, Debug)]
+pub(super) struct Demand {
+    agents: HashMap<usize, AgentInfo>,
+    ports: HashMap<usize, Vec<Occurrence>>,
+    /// Both sides of each connection in the map, by the ID of its left side.
+    tops: HashMap<usize, (Side, Side)>,
+    /// The names of the named ports.
+    names: HashMap<usize, String>,
+}
+
+impl Demand {
+    pub(super) fn new(runtime: &Runtime) -> Self {
+        let mut demand = Self::default();
+
+        for (left, right) in runtime.connections.iter() {
+            demand.insert(&runtime.rulebook, left, right);
+        }
+
+        demand
+    }
+
+    /// Adds a connection that has been put into the map.
+    pub(super) fn insert(&mut self, rulebook: &Rulebook, left: &Term, right: &Term) {
+        let key = *left.id();
+        match (left, right) {
+            (Term::Agent(left), Term::Agent(right)) => {
+                let action = rulebook.has_rule(left, right).then_some(key);
+                self.add_agent(left, Principal::Pair(right.id, action));
+                self.add_agent(right, Principal::Pair(left.id, action));
+            }
+            (Term::Port(_), Term::Agent(agent)) => {
+                self.add_port(left, Occurrence::Top(key));
+                self.add_agent(agent, Principal::Port(key));
+            }
+            (Term::Port(_), Term::Port(_)) => {
+                self.add_port(left, Occurrence::Top(key));
+                self.add_port(right, Occurrence::Top(key));
+            }
+            (Term::Agent(_), Term::Port(_)) => {
+                unreachable!("invalid runtime state: agent on the left of a port")
+            }
+        }
+
+        self.tops.insert(key, (Side::of(left), Side::of(right)));
+    }
+
+    /// Takes out a connection that has been taken out of the map.
+    pub(super) fn remove(&mut self, left: &Term, right: &Term) {
+        let key = *left.id();
+        self.tops.remove(&key);
+
+        for term in [left, right] {
+            match term {
+                Term::Agent(agent) => self.remove_agent(agent),
+                Term::Port(_) => self.remove_port(*term.id(), Occurrence::Top(key)),
+            }
+        }
+    }
+
+    fn add_port(&mut self, port: &Term, occurrence: Occurrence) {
+        self.ports.entry(*port.id()).or_default().push(occurrence);
+        if let Term::Port(Port {
+            name: Some(name), ..
+        }) = port
+        {
+            self.names.insert(*port.id(), name.clone());
+        }
+    }
+
+    fn remove_port(&mut self, id: usize, occurrence: Occurrence) {
+        let Some(occurrences) = self.ports.get_mut(&id) else {
+            return;
+        };
+
+        occurrences.retain(|&other| other != occurrence);
+        if occurrences.is_empty() {
+            self.ports.remove(&id);
+            self.names.remove(&id);
+        }
+    }
+
+    fn add_agent(&mut self, agent: &Agent, principal: Principal) {
+        let ports = agent
+            .ports
+            .iter()
+            .enumerate()
+            .map(|(index, port)| match port {
+                Term::Agent(child) => {
+                    self.add_agent(child, Principal::Aux(agent.id, index));
+                    Aux::Agent(child.id)
+                }
+                Term::Port(_) => {
+                    self.add_port(port, Occurrence::Aux(agent.id, index));
+                    Aux::Port(*port.id())
+                }
+            })
+            .collect();
+
+        let info = AgentInfo {
+            kind: agent.kind,
+            data: agent.data,
+            ports,
+            principal,
+        };
+        self.agents.insert(agent.id, info);
+    }
+
+    fn remove_agent(&mut self, agent: &Agent) {
+        self.agents.remove(&agent.id);
+
+        for (index, port) in agent.ports.iter().enumerate() {
+            match port {
+                Term::Agent(child) => self.remove_agent(child),
+                Term::Port(_) => self.remove_port(*port.id(), Occurrence::Aux(agent.id, index)),
+            }
+        }
+    }
+
+    /// Follows a wire from where a port shows up to the end on the other
+    /// side of it.
+    fn follow(&self, port_id: usize, from: Option<Occurrence>) -> End {
+        let Some(&occurrence) = self.ports[&port_id]
+            .iter()
+            .find(|&&occurrence| Some(occurrence) != from)
+        else {
+            return End::Free;
+        };
+
+        match occurrence {
+            Occurrence::Aux(agent, index) => End::Agent(agent, index + 1),
+            Occurrence::Top(key) => {
+                let (left, right) = self.tops[&key];
+                let opposite = if left == Side::Port(port_id) {
+                    right
+                } else {
+                    left
+                };
+
+                match opposite {
+                    Side::Agent(agent) => End::Agent(agent, 0),
+                    Side::Port(port_id) => self.follow(port_id, Some(occurrence)),
+                }
+            }
+        }
+    }
+
+    /// What an interface port is connected to.
+    pub(super) fn interface_neighbor(&self, port_id: usize) -> End {
+        self.follow(port_id, None)
+    }
+
+    /// What a port of an agent is connected to.
+    pub(super) fn neighbor(&self, agent_id: usize, slot: usize) -> End {
+        let agent = &self.agents[&agent_id];
+
+        if slot > 0 {
+            return match agent.ports[slot - 1] {
+                Aux::Agent(child) => End::Agent(child, 0),
+                Aux::Port(port_id) => {
+                    self.follow(port_id, Some(Occurrence::Aux(agent_id, slot - 1)))
+                }
+            };
+        }
+
+        match agent.principal {
+            Principal::Aux(parent, index) => End::Agent(parent, index + 1),
+            Principal::Pair(other, _) => End::Agent(other, 0),
+            Principal::Port(port_id) => self.follow(port_id, Some(Occurrence::Top(port_id))),
+        }
+    }
+
+    pub(super) fn agent(&self, agent_id: usize) -> &AgentInfo {
+        &self.agents[&agent_id]
+    }
+
+    /// The ID of the action for the active pair this agent is in, if it's in
+    /// one that the rulebook has a rule for. A pair without one never reduces,
+    /// so it blocks whatever is behind it.
+    pub(super) fn pair(&self, agent_id: usize) -> Option<usize> {
+        match self.agents[&agent_id].principal {
+            Principal::Pair(_, action) => action,
+            _ => None,
+        }
+    }
+
+    /// The IDs and names of the named interface ports.
+    pub(super) fn named_ports(&self) -> Vec<(usize, &str)> {
+        self.names
+            .iter()
+            .filter(|(port_id, _)| self.ports[port_id].len() == 1)
+            .map(|(&port_id, name)| (port_id, name.as_str()))
+            .collect()
+    }
+
+    /// Finds the active pair that has to be reduced before this agent's
+    /// principal port can face anything else, by following principal ports
+    /// until two of them meet. Returns the ID of the pair's action.
+    fn blocking_pair(&self, mut agent_id: usize) -> Option<usize> {
+        let mut seen = HashSet::default();
+
+        loop {
+            // a vicious circle can't ever be reduced
+            if !seen.insert(agent_id) {
+                return None;
+            }
+
+            if let Some(key) = self.pair(agent_id) {
+                return Some(key);
+            }
+
+            match self.neighbor(agent_id, 0) {
+                End::Agent(other, _) => agent_id = other,
+                End::Free => return None,
+            }
+        }
+    }
+
+    /// Finds the active pairs that the named interface ports are waiting on,
+    /// looking `depth` agents deep.
+    fn needed_pairs(&self, depth: usize) -> HashSet<usize> {
+        let mut stack: Vec<_> = self
+            .named_ports()
+            .into_iter()
+            .map(|(port_id, _)| (self.interface_neighbor(port_id), depth))
+            .collect();
+
+        let mut needed = HashSet::default();
+        // the deepest that each end has been looked at from
+        let mut seen = HashMap::default();
+        while let Some((end, depth)) = stack.pop() {
+            if seen.get(&end).is_some_and(|&seen| seen >= depth) {
+                continue;
+            }
+            seen.insert(end, depth);
+
+            match end {
+                End::Free => {}
+                End::Agent(agent_id, 0) => {
+                    if depth == 0 {
+                        continue;
+                    }
+
+                    let arity = self.agents[&agent_id].arity();
+                    for slot in 1..=arity {
+                        stack.push((self.neighbor(agent_id, slot), depth - 1));
+                    }
+                }
+                End::Agent(agent_id, _) => needed.extend(self.blocking_pair(agent_id)),
+            }
+        }
+
+        needed
+    }
+}
+
+impl Runtime {
+    /// Reduces only the active pairs that the named interface ports depend
+    /// on, until each one faces the principal port of an agent, and so do the
+    /// auxiliary ports of those agents, `depth` agents deep. Returns the
+    /// number of interactions it took.
+    ///
+    /// Active pairs that aren't needed are left for a later call, or for
+    /// [`Runtime::reduce`].
+    pub fn reduce_lazily(&mut self, depth: usize) -> usize {
+        let start = self.interactions;
+
+        loop {
+            let needed = self.demand().needed_pairs(depth);
+            if needed.is_empty() {
+                self.demand = None;
+                return self.interactions - start;
+            }
+
+            self.reduce_pairs(needed);
+        }
+    }
+
+    /// The index of the net, which is built the first time it's needed and
+    /// kept up to date from then on, until it's dropped again.
+    pub(super) fn demand(&mut self) -> &Demand {
+        if self.demand.is_none() {
+            self.demand = Some(Demand::new(self));
+        }
+
+        self.demand.as_ref().unwrap()
+    }
+
+    /// Reduces the active pairs with these action IDs, and nothing else.
+    pub(super) fn reduce_pairs(&mut self, ids: HashSet<usize>) {
+        self.action_stack
+            .retain(|Action::Reduce(id)| !ids.contains(id));
+        for id in ids {
+            self.apply(Action::Reduce(id));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        inpla,
+        net::{connection::Connection, term::AgentKind},
+    };
+
+    /// A runtime for an Inpla program, along with the kind of each agent.
+    fn runtime(src: &str) -> (Runtime, impl Fn(&str) -> AgentKind) {
+        let program = inpla::parse_program(src).unwrap();
+        let agents = program.agents;
+        let kind = move |name: &str| {
+            AgentKind::Dynamic(agents.iter().position(|agent| agent == name).unwrap())
+        };
+
+        (
+            Runtime::new(program.connections, program.rulebook, program.ctx),
+            kind,
+        )
+    }
+
+    /// An endless list of `Z`s.
+    const STREAM: &str = "
+        Gen(r) >< Z => r ~ Cons(Z, w), Gen(w) ~ Z;
+        Gen(r) ~ Z;
+        r;
+    ";
+
+    #[test]
+    fn reduces_an_endless_stream_to_a_depth() {
+        let (mut runtime, kind) = runtime(STREAM);
+
+        // each element takes one interaction, and there's one element for
+        // each level of depth below the first
+        assert_eq!(runtime.reduce_lazily(0), 1);
+        assert_eq!(runtime.reduce_lazily(0), 0);
+        assert_eq!(runtime.reduce_lazily(4), 4);
+        assert_eq!(runtime.reduce_lazily(4), 0);
+
+        let query = runtime.query("r").unwrap();
+        let cons = query
+            .iter()
+            .flat_map(|Connection(left, right)| [left, right])
+            .filter(|term| matches!(term, Term::Agent(agent) if agent.kind == kind("Cons")))
+            .count();
+        assert_eq!(cons, 5);
+    }
+
+    #[test]
+    fn leaves_pairs_that_arent_needed() {
+        let (mut runtime, _) = runtime(
+            "
+            A(r) >< B => r ~ C;
+            A(r) ~ B;
+            Eraser ~ Dup(x, y);
+            x ~ y;
+            r;
+            ",
+        );
+
+        assert_eq!(runtime.reduce_lazily(0), 1);
+        assert_eq!(runtime.action_stack.len(), 1);
+
+        runtime.reduce();
+        assert_eq!(runtime.interactions(), 3);
+    }
+
+    /// The index with each port's occurrences in order, so that indices built
+    /// in different orders can be compared.
+    fn sorted(demand: &Demand) -> String {
+        let mut ports: Vec<_> = demand.ports.iter().collect();
+        ports.sort_unstable();
+        let ports: Vec<_> = ports
+            .into_iter()
+            .map(|(port_id, occurrences)| {
+                let mut occurrences = occurrences.clone();
+                occurrences.sort_unstable();
+                (port_id, occurrences)
+            })
+            .collect();
+
+        let mut agents: Vec<_> = demand.agents.iter().collect();
+        agents.sort_unstable_by_key(|(agent_id, _)| **agent_id);
+        let mut tops: Vec<_> = demand.tops.iter().collect();
+        tops.sort_unstable_by_key(|(key, _)| **key);
+        let mut names: Vec<_> = demand.names.iter().collect();
+        names.sort_unstable();
+
+        format!("{agents:?} {ports:?} {tops:?} {names:?}")
+    }
+
+    #[test]
+    fn keeps_the_index_up_to_date() {
+        let (mut runtime, _) = runtime(STREAM);
+
+        for _ in 0..5 {
+            let needed = runtime.demand().needed_pairs(8);
+            runtime.reduce_pairs(needed);
+
+            let kept = sorted(runtime.demand.as_ref().unwrap());
+            assert_eq!(kept, sorted(&Demand::new(&runtime)));
+        }
+
+        runtime.reduce_lazily(8);
+        assert!(runtime.demand.is_none());
+    }
+}
@@ -0,0 +1,364 @@
+pub mod explore;
+pub mod gc;
+pub mod history;
+pub mod lazy;
+pub mod parallel;
+pub mod readback;
+pub mod snapshot;
+pub mod span;
+
+use crate::{
+    map::ConnectionMap,
+    net::{
+        connection::Connection,
+        deadlock::{self, Deadlock},
+        id::Renumbering,
+        interface,
+        term::{Port, Term},
+    },
+    rule::{context::RewriteContext, rulebook::Rulebook},
+};
+use history::{Change, History};
+use lazy::Demand;
+
+enum Action {
+    Reduce(usize),
+}
+
+pub struct Runtime {
+    // connections should only be Port=Agent, Port=Port or Agent=Agent; never
+    // Agent=Port. a port shows up in at most one connection, and a port that is
+    // connected to itself is a closed loop.
+    connections: ConnectionMap<Term, Term>,
+    action_stack: Vec<Action>,
+    rulebook: Rulebook,
+    ctx: RewriteContext,
+    interactions: usize,
+    /// Scratch space for running compiled rules, kept around so it doesn't
+    /// have to be allocated for every interaction.
+    slots: Vec<Option<Term>>,
+    new_connections: Vec<Connection>,
+    /// Every change to the map, once [`Runtime::record_history`] has been
+    /// called.
+    history: Option<History>,
+    /// An index of the map, kept up to date while a lazy reduction or a
+    /// readback is using it.
+    demand: Option<Demand>,
+}
+
+impl Runtime {
+    pub fn new(
+        connections: impl IntoIterator<Item = Connection>,
+        rulebook: Rulebook,
+        ctx: RewriteContext,
+    ) -> Self {
+        let mut runtime = Self {
+            connections: ConnectionMap::<_, _>::new(),
+            action_stack: Vec::new(),
+            rulebook,
+            ctx,
+            interactions: 0,
+            slots: Vec::new(),
+            new_connections: Vec::new(),
+            history: None,
+            demand: None,
+        };
+
+        for Connection(left, right) in connections {
+            runtime.push_connection(left, right);
+        }
+
+        runtime
+    }
+
+    /// Pushes a connection into the map, merging it with whatever is already on
+    /// the other end of any port it mentions.
+    ///
+    /// Every port occurs exactly twice in a net, so the map holds at most one
+    /// entry per port. Merging a port takes that entry out and continues with
+    /// the term on its far side, so a long chain of wires is resolved one wire
+    /// at a time without recursion.
+    fn push_connection(&mut self, mut left: Term, mut right: Term) {
+        loop {
+            match (left, right) {
+                (left @ Term::Agent(_), right @ Term::Agent(_)) => {
+                    self.action_stack.push(Action::Reduce(*left.id()));
+                    self.insert_connection(left, right);
+                    return;
+                }
+                (agent @ Term::Agent(_), port @ Term::Port(_)) => {
+                    left = port;
+                    right = agent;
+                }
+                (port @ Term::Port(_), other @ Term::Port(_)) if port == other => {
+                    // both ends of the wire are the same port, so it's a closed
+                    // loop. we keep it around as `p = p` so it still shows up in
+                    // the normal form.
+                    self.insert_connection(port, other);
+                    return;
+                }
+                (port @ Term::Port(_), other) => {
+                    if let Some(opposite) = self.take_opposite(*port.id()) {
+                        left = opposite;
+                        right = other;
+                        continue;
+                    }
+
+                    if let Term::Port(_) = other {
+                        if let Some(opposite) = self.take_opposite(*other.id()) {
+                            left = port;
+                            right = opposite;
+                            continue;
+                        }
+                    }
+
+                    self.insert_connection(port, other);
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Removes the connection that the given port is part of, returning the
+    /// term on the other side of it.
+    ///
+    /// Both occurrences of the port are used up once it's been merged, so its
+    /// ID gets retired.
+    fn take_opposite(&mut self, port_id: usize) -> Option<Term> {
+        let (left, right) = match self.connections.remove_by_left_key(&port_id) {
+            Some(connection) => connection,
+            None => self.connections.remove_by_right_key(&port_id)?,
+        };
+
+        if let Some(history) = &mut self.history {
+            history.record(Change::Removed(left.clone(), right.clone()));
+        }
+        if let Some(demand) = &mut self.demand {
+            demand.remove(&left, &right);
+        }
+        self.ctx.id_alloc.retire_id(port_id);
+
+        Some(if *left.id() == port_id { right } else { left })
+    }
+
+    fn insert_connection(&mut self, left: Term, right: Term) {
+        if let Some(history) = &mut self.history {
+            history.record(Change::Inserted(left.clone(), right.clone()));
+        }
+        if let Some(demand) = &mut self.demand {
+            demand.insert(&self.rulebook, &left, &right);
+        }
+
+        self.connections.insert(left, right).unwrap();
+    }
+
+    /// Renumbers the net so that its IDs are dense again, and replaces the ID
+    /// allocator with one that continues after them.
+    ///
+    /// Any recorded history starts over from here, since its IDs would no
+    /// longer match.
+    pub fn compact(&mut self) {
+        self.restart_history();
+
+        let mut renumbering = Renumbering::new();
+        let connections = std::mem::replace(&mut self.connections, ConnectionMap::new());
+
+        for (left, right) in connections {
+            let left = left.map_ids(&mut |id| renumbering.get(id));
+            let right = right.map_ids(&mut |id| renumbering.get(id));
+            self.connections.insert(left, right).unwrap();
+        }
+
+        for action in &mut self.action_stack {
+            match action {
+                Action::Reduce(id) => *id = renumbering.get(*id),
+            }
+        }
+
+        self.ctx.id_alloc = renumbering.into_allocator();
+    }
+
+    /// Reduces active pairs until there are none left that have a rule.
+    pub fn reduce(&mut self) {
+        while let Some(action) = self.action_stack.pop() {
+            self.apply(action);
+        }
+    }
+
+    fn apply(&mut self, action: Action) {
+        match action {
+            Action::Reduce(id) => {
+                let pair = self
+                    .connections
+                    .get_by_left_key(&id)
+                    .expect("invalid runtime state: action stack had invalid term ID");
+
+                let (Term::Agent(left), Term::Agent(right)) = pair else {
+                    panic!("invalid runtime state: reduce action pointed to a port");
+                };
+
+                // a pair without a rule is stuck, so it stays in the map
+                // without an action, where `deadlocks` can find it
+                if !self.rulebook.has_rule(left, right) {
+                    return;
+                }
+
+                let (left, right) = self.connections.remove_by_left_key(&id).unwrap();
+                if let Some(history) = &mut self.history {
+                    history.begin(self.interactions);
+                    history.record(Change::Removed(left.clone(), right.clone()));
+                }
+                if let Some(demand) = &mut self.demand {
+                    demand.remove(&left, &right);
+                }
+
+                let (Term::Agent(left), Term::Agent(right)) = (left, right) else {
+                    unreachable!();
+                };
+
+                let mut new_connections = std::mem::take(&mut self.new_connections);
+
+                match self.rulebook.compiled_rule(&left, &right) {
+                    Some(rule) => {
+                        let (left, right) = if left.kind <= right.kind {
+                            (left, right)
+                        } else {
+                            (right, left)
+                        };
+
+                        rule.execute(
+                            &self.ctx,
+                            left,
+                            right,
+                            &mut self.slots,
+                            &mut new_connections,
+                        );
+                    }
+                    None => {
+                        let result = self.rulebook.rewrite(&self.ctx, left, right);
+                        new_connections.extend(result.new_connections);
+                    }
+                }
+                self.interactions += 1;
+
+                for Connection(left, right) in new_connections.drain(..) {
+                    self.push_connection(left, right);
+                }
+                self.new_connections = new_connections;
+            }
+        }
+    }
+
+    /// The number of interactions performed so far.
+    pub fn interactions(&self) -> usize {
+        self.interactions
+    }
+
+    /// The free ports of the net, sorted by ID. They keep their names through
+    /// reduction, so results can be found by name.
+    pub fn interface(&self) -> Vec<&Port> {
+        interface::interface(self.connections.iter().map(|(left, right)| (left, right)))
+    }
+
+    /// Copies out the part of the net that's connected to the free port called
+    /// `name`, or `None` if there isn't one. This is usually done once the net
+    /// has been reduced, to read off a result.
+    pub fn query(&self, name: &str) -> Option<Vec<Connection>> {
+        interface::subnet(
+            self.connections.iter().map(|(left, right)| (left, right)),
+            name,
+        )
+    }
+
+    /// Finds vicious circles, closed loops of wire and active pairs without a
+    /// rule in the net. See [`deadlock::find_deadlocks`].
+    pub fn deadlocks(&self) -> Vec<Deadlock<'_>> {
+        deadlock::find_deadlocks(
+            self.connections.iter().map(|(left, right)| (left, right)),
+            &self.rulebook,
+        )
+    }
+
+    pub fn normalize(mut self) -> impl IntoIterator<Item = (Term, Term)> {
+        self.reduce();
+        self.connections
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::net::{
+        id::IdAllocator,
+        term::{Agent, AgentKind},
+    };
+
+    fn runtime(connections: Vec<Connection>, ctx: RewriteContext) -> Runtime {
+        Runtime::new(connections, Rulebook::default(), ctx)
+    }
+
+    #[test]
+    fn long_wire_chain_is_resolved() {
+        let ctx = RewriteContext::new(IdAllocator::new());
+        let root = ctx.create_port().with_name("root");
+        let wires: Vec<_> = (0..100_000).map(|_| ctx.create_port()).collect();
+
+        // pushed from the far end, so every connection merges with the chain
+        // that's already there
+        let mut connections = vec![Connection(
+            wires.last().unwrap().clone(),
+            Term::Agent(Agent::new_eraser(ctx.id_alloc.create_id())),
+        )];
+        for pair in wires.windows(2).rev() {
+            connections.push(Connection(pair[0].clone(), pair[1].clone()));
+        }
+        connections.push(Connection(root, wires[0].clone()));
+
+        let normal_form: Vec<_> = runtime(connections, ctx).normalize().into_iter().collect();
+
+        assert_eq!(normal_form.len(), 1);
+        let (left, right) = &normal_form[0];
+        assert!(matches!(left, Term::Port(port) if port.name.as_deref() == Some("root")));
+        assert!(matches!(right, Term::Agent(agent) if agent.kind == AgentKind::Eraser));
+    }
+
+    #[test]
+    fn wire_chain_closed_into_a_loop() {
+        let ctx = RewriteContext::new(IdAllocator::new());
+        let wires: Vec<_> = (0..1000).map(|_| ctx.create_port()).collect();
+
+        let mut connections: Vec<_> = wires
+            .windows(2)
+            .map(|pair| Connection(pair[0].clone(), pair[1].clone()))
+            .collect();
+        connections.push(Connection(wires.last().unwrap().clone(), wires[0].clone()));
+
+        let normal_form: Vec<_> = runtime(connections, ctx).normalize().into_iter().collect();
+
+        assert_eq!(normal_form.len(), 1);
+        let (left, right) = &normal_form[0];
+        assert!(matches!((left, right), (Term::Port(_), Term::Port(_))));
+        assert_eq!(left.id(), right.id());
+    }
+
+    #[test]
+    fn loop_made_by_a_rule_survives_normalization() {
+        // Constructor(a, a) ~ Constructor(b, b) annihilates into a = b twice,
+        // which is a closed loop
+        let ctx = RewriteContext::new(IdAllocator::new());
+        let a = ctx.create_port();
+        let b = ctx.create_port();
+        let left = ctx.create_agent(AgentKind::Constructor, &[a.clone(), a]);
+        let right = ctx.create_agent(AgentKind::Constructor, &[b.clone(), b]);
+
+        let mut runtime = runtime(vec![left.connect(right)], ctx);
+        runtime.reduce();
+        assert_eq!(runtime.interactions(), 1);
+
+        let normal_form: Vec<_> = runtime.normalize().into_iter().collect();
+        assert_eq!(normal_form.len(), 1);
+        let (left, right) = &normal_form[0];
+        assert!(matches!((left, right), (Term::Port(_), Term::Port(_))));
+        assert_eq!(left.id(), right.id());
+    }
+}
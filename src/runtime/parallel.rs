@@ -0,0 +1,471 @@
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use rustc_hash::FxHashMap as HashMap;
+
+use super::Runtime;
+use crate::{
+    map::ConnectionMap,
+    net::{
+        connection::Connection,
+        term::{Agent, Term},
+    },
+    rule::{context::RewriteContext, rulebook::Rulebook},
+};
+
+type Redex = (Agent, Agent);
+type Shard = Mutex<HashMap<usize, (Term, Term)>>;
+
+/// Ports that are waiting for their other occurrence, along with the term on
+/// the far side of each. It's split into shards so that threads linking
+/// unrelated ports don't contend on the same lock.
+struct WireTable {
+    shards: Box<[Shard]>,
+}
+
+impl WireTable {
+    fn new(shard_count: usize) -> Self {
+        let shard_count = shard_count.next_power_of_two();
+
+        Self {
+            shards: (0..shard_count).map(|_| Mutex::default()).collect(),
+        }
+    }
+
+    fn shard(&self, port_id: usize) -> &Shard {
+        &self.shards[port_id & (self.shards.len() - 1)]
+    }
+
+    /// Connects a port to a term. If the other occurrence of the port is
+    /// already waiting, it's taken out of the table and the two terms that
+    /// should now be connected are returned. Otherwise, the term waits on the
+    /// port.
+    fn exchange(&self, port: Term, term: Term) -> Option<(Term, Term)> {
+        let mut shard = self.shard(*port.id()).lock().unwrap();
+
+        match shard.remove(port.id()) {
+            Some((_, waiting)) => Some((waiting, term)),
+            None => {
+                shard.insert(*port.id(), (port, term));
+                None
+            }
+        }
+    }
+
+    fn into_connections(self) -> impl Iterator<Item = Connection> {
+        Vec::from(self.shards)
+            .into_iter()
+            .flat_map(|shard| shard.into_inner().unwrap().into_values())
+            .map(Connection::from)
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct WorkerStats {
+    /// Interactions performed by this worker.
+    pub interactions: usize,
+    /// Redexes this worker took from another worker's queue.
+    pub steals: usize,
+    /// Time spent rewriting and linking, as opposed to looking for work.
+    pub busy: Duration,
+}
+
+#[derive(Clone, Debug)]
+pub struct ParallelStats {
+    /// Wall-clock time spent in the parallel phase.
+    pub elapsed: Duration,
+    pub workers: Vec<WorkerStats>,
+    /// Interactions left over for the sequential runtime after the workers
+    /// finished. See [`ParallelRuntime::normalize`].
+    pub sequential_interactions: usize,
+    /// Wall-clock time spent merging wires and reducing the leftovers.
+    pub sequential_elapsed: Duration,
+}
+
+/// Divides one duration by another, or gives zero if there's nothing to
+/// divide by.
+fn ratio(numerator: Duration, denominator: Duration) -> f64 {
+    if denominator.is_zero() {
+        return 0.0;
+    }
+
+    numerator.as_secs_f64() / denominator.as_secs_f64()
+}
+
+impl ParallelStats {
+    pub fn interactions(&self) -> usize {
+        self.workers
+            .iter()
+            .map(|worker| worker.interactions)
+            .sum::<usize>()
+            + self.sequential_interactions
+    }
+
+    /// Busy time summed over all workers, divided by the wall-clock time,
+    /// which is how many workers were busy on average during the parallel
+    /// phase.
+    ///
+    /// This isn't a speedup over the sequential runtime: a busy worker can
+    /// still be slower than [`Runtime::reduce`] because of locking, and time
+    /// spent descheduled in the middle of an interaction counts as busy. To
+    /// measure a speedup, see [`ParallelStats::speedup`].
+    ///
+    /// Gives zero if no time passed at all.
+    pub fn utilization(&self) -> f64 {
+        let busy: Duration = self.workers.iter().map(|worker| worker.busy).sum();
+        ratio(busy, self.elapsed)
+    }
+
+    /// How many times faster the whole of [`ParallelRuntime::normalize`] was
+    /// than the sequential runtime took to reduce the same net, which
+    /// [`measure_speedup`] times. Gives zero if no time passed at all.
+    pub fn speedup(&self, sequential: Duration) -> f64 {
+        ratio(sequential, self.elapsed + self.sequential_elapsed)
+    }
+}
+
+/// Reduces a net with [`Runtime::reduce`], and then again with `threads`
+/// workers, returning the parallel runtime's stats and how many times faster
+/// it was. `net` makes a fresh copy of the net for each run.
+pub fn measure_speedup(
+    net: impl Fn() -> (Vec<Connection>, Rulebook, RewriteContext),
+    threads: usize,
+) -> (ParallelStats, f64) {
+    let (connections, rulebook, ctx) = net();
+    let mut runtime = Runtime::new(connections, rulebook, ctx);
+    let started = Instant::now();
+    runtime.reduce();
+    let sequential = started.elapsed();
+
+    let (connections, rulebook, ctx) = net();
+    let (_, stats) = ParallelRuntime::new(connections, rulebook, ctx, threads).normalize();
+    let speedup = stats.speedup(sequential);
+
+    (stats, speedup)
+}
+
+/// A runtime that reduces redexes on several threads at once.
+///
+/// Each worker owns a queue of redexes, and takes from the back of its own
+/// queue or the front of another worker's. Since interaction nets are
+/// confluent, the order doesn't change the normal form.
+///
+/// Each worker also has its own [worker](crate::net::id::IdAllocator::worker)
+/// allocator, so the IDs it retires go back to it rather than to a free list
+/// that every worker contends on. The only other things the workers share are
+/// the shards of the wire table, each other's queues when stealing, and a
+/// count of pending redexes.
+pub struct ParallelRuntime {
+    wires: WireTable,
+    queues: Box<[Mutex<VecDeque<Redex>>]>,
+    /// Redexes that have been queued but not yet fully reduced. The workers
+    /// are done once this hits zero.
+    pending: AtomicUsize,
+    /// Redexes without a rule, which are left as they are.
+    stuck: Mutex<Vec<Redex>>,
+    rulebook: Rulebook,
+    ctx: RewriteContext,
+    /// The context that each worker rewrites with.
+    contexts: Box<[RewriteContext]>,
+}
+
+impl ParallelRuntime {
+    pub fn new(
+        connections: impl IntoIterator<Item = Connection>,
+        rulebook: Rulebook,
+        ctx: RewriteContext,
+        threads: usize,
+    ) -> Self {
+        assert!(threads > 0, "need at least one thread");
+
+        let mut runtime = Self {
+            wires: WireTable::new(threads * 64),
+            queues: (0..threads).map(|_| Mutex::default()).collect(),
+            pending: AtomicUsize::new(0),
+            stuck: Mutex::default(),
+            rulebook,
+            contexts: (0..threads)
+                .map(|_| RewriteContext::new(ctx.id_alloc.worker()))
+                .collect(),
+            ctx,
+        };
+
+        let mut redexes = Vec::new();
+        for Connection(left, right) in connections {
+            runtime.link(&runtime.ctx, left, right, &mut redexes);
+        }
+
+        *runtime.pending.get_mut() = redexes.len();
+        for (index, redex) in redexes.into_iter().enumerate() {
+            runtime.queues[index % threads]
+                .get_mut()
+                .unwrap()
+                .push_back(redex);
+        }
+
+        runtime
+    }
+
+    /// Connects two terms, adding any active pair that it creates to
+    /// `redexes`.
+    fn link(
+        &self,
+        ctx: &RewriteContext,
+        mut left: Term,
+        mut right: Term,
+        redexes: &mut Vec<Redex>,
+    ) {
+        loop {
+            match (left, right) {
+                (Term::Agent(left), Term::Agent(right)) => {
+                    redexes.push((left, right));
+                    return;
+                }
+                (agent @ Term::Agent(_), port @ Term::Port(_)) => {
+                    left = port;
+                    right = agent;
+                }
+                (port @ Term::Port(_), other) => {
+                    let port_id = *port.id();
+                    let Some((waiting, other)) = self.wires.exchange(port, other) else {
+                        return;
+                    };
+
+                    ctx.id_alloc.retire_id(port_id);
+
+                    left = waiting;
+                    right = other;
+                }
+            }
+        }
+    }
+
+    fn work(&self, index: usize) -> WorkerStats {
+        let ctx = &self.contexts[index];
+        let mut stats = WorkerStats::default();
+        let mut redexes = Vec::new();
+
+        loop {
+            let redex = self.queues[index].lock().unwrap().pop_back();
+            let Some((left, right)) = redex.or_else(|| self.steal(index, &mut stats)) else {
+                if self.pending.load(Ordering::Acquire) == 0 {
+                    return stats;
+                }
+
+                thread::yield_now();
+                continue;
+            };
+
+            if !self.rulebook.has_rule(&left, &right) {
+                self.stuck.lock().unwrap().push((left, right));
+                self.pending.fetch_sub(1, Ordering::AcqRel);
+                continue;
+            }
+
+            let started = Instant::now();
+
+            let result = self.rulebook.rewrite(ctx, left, right);
+            for Connection(left, right) in result.new_connections {
+                self.link(ctx, left, right, &mut redexes);
+            }
+
+            if !redexes.is_empty() {
+                self.pending.fetch_add(redexes.len(), Ordering::AcqRel);
+                self.queues[index].lock().unwrap().extend(redexes.drain(..));
+            }
+            self.pending.fetch_sub(1, Ordering::AcqRel);
+
+            stats.interactions += 1;
+            stats.busy += started.elapsed();
+        }
+    }
+
+    fn steal(&self, index: usize, stats: &mut WorkerStats) -> Option<Redex> {
+        let count = self.queues.len();
+
+        let redex = (1..count)
+            .map(|offset| &self.queues[(index + offset) % count])
+            .find_map(|queue| queue.lock().unwrap().pop_front())?;
+
+        stats.steals += 1;
+        Some(redex)
+    }
+
+    /// Reduces the net on all threads until there are no redexes left.
+    ///
+    /// Two threads can link the ends of a wire at the same time without
+    /// either seeing the other, which leaves it split across two ports. Once
+    /// the workers are done, the remaining wires go through the sequential
+    /// runtime, which merges them (and reduces anything that creates). Redexes
+    /// without a rule are set aside by the workers and end up in the result
+    /// unreduced.
+    pub fn normalize(self) -> (ConnectionMap<Term, Term>, ParallelStats) {
+        let started = Instant::now();
+
+        let workers = thread::scope(|scope| {
+            let runtime = &self;
+            let handles: Vec<_> = (0..self.queues.len())
+                .map(|index| scope.spawn(move || runtime.work(index)))
+                .collect();
+
+            handles
+                .into_iter()
+                .map(|handle| handle.join().unwrap())
+                .collect()
+        });
+
+        let elapsed = started.elapsed();
+
+        let Self {
+            wires,
+            stuck,
+            rulebook,
+            ctx,
+            contexts,
+            ..
+        } = self;
+
+        for worker in contexts {
+            ctx.id_alloc.join(worker.id_alloc);
+        }
+
+        let started = Instant::now();
+        let stuck = stuck.into_inner().unwrap().into_iter();
+        let connections = wires
+            .into_connections()
+            .chain(stuck.map(|(left, right)| Connection::from_agents(left, right)));
+        let mut runtime = Runtime::new(connections, rulebook, ctx);
+        runtime.reduce();
+
+        let stats = ParallelStats {
+            elapsed,
+            workers,
+            sequential_interactions: runtime.interactions(),
+            sequential_elapsed: started.elapsed(),
+        };
+
+        (runtime.connections, stats)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        hvm,
+        net::{canonical::CanonicalNet, id::IdAllocator, text::parse_connection},
+    };
+
+    /// A tree of constructors `depth` deep, with erasers for leaves.
+    fn tree(depth: usize) -> String {
+        match depth {
+            0 => "*".to_string(),
+            _ => format!("({} {})", tree(depth - 1), tree(depth - 1)),
+        }
+    }
+
+    /// Duplicates one big tree and erases another, along with some Church
+    /// numerals, so there's plenty to do in parallel.
+    fn book() -> String {
+        format!(
+            "
+            @c2 = ({{(a b) (b c)}} (a c))
+            @c3 = ({{(a b) {{(b c) (c d)}}}} (a d))
+            @main = (r (x (y z)))
+                & @c3 ~ (@c3 (@c2 r))
+                & {{x y}} ~ {}
+                & * ~ {}
+                & #7 ~ {{z *}}
+            ",
+            tree(10),
+            tree(10),
+        )
+    }
+
+    fn sequential() -> (CanonicalNet, usize) {
+        let book = hvm::parse_book(&book()).unwrap();
+        let mut runtime = Runtime::new(book.connections, book.rulebook, book.ctx);
+        runtime.reduce();
+        let interactions = runtime.interactions();
+
+        let normal_form = CanonicalNet::new(runtime.normalize().into_iter().map(Connection::from));
+        (normal_form, interactions)
+    }
+
+    #[test]
+    fn same_normal_form_as_sequential() {
+        let (expected, interactions) = sequential();
+
+        for threads in [1, 2, 4, 8] {
+            let book = hvm::parse_book(&book()).unwrap();
+            let runtime = ParallelRuntime::new(book.connections, book.rulebook, book.ctx, threads);
+            let (connections, stats) = runtime.normalize();
+
+            let found = CanonicalNet::new(connections.into_iter().map(Connection::from));
+            assert_eq!(found, expected, "with {threads} threads");
+            assert_eq!(stats.workers.len(), threads);
+            assert_eq!(stats.interactions(), interactions, "with {threads} threads");
+        }
+    }
+
+    #[test]
+    fn leaves_redexes_without_a_rule() {
+        let mut rulebook = Rulebook::default();
+        rulebook.declare_agent(0, 1);
+        let connections = [
+            r#"Dynamic[0]#1($2:"a") = Constructor#3($4:"b", $5:"c")"#,
+            "Eraser#6() = Eraser#7()",
+        ]
+        .map(|src| parse_connection(src).unwrap());
+
+        let ctx = RewriteContext::new(IdAllocator::new_at(100));
+        let runtime = ParallelRuntime::new(connections, rulebook, ctx, 2);
+        let (connections, stats) = runtime.normalize();
+
+        assert_eq!(stats.interactions(), 1);
+        let pairs: Vec<_> = connections
+            .iter()
+            .map(|(left, right)| {
+                let (Term::Agent(left), Term::Agent(right)) = (left, right) else {
+                    panic!("expected an active pair");
+                };
+                (left.id.min(right.id), left.id.max(right.id))
+            })
+            .collect();
+        assert_eq!(pairs, [(1, 3)]);
+    }
+
+    #[test]
+    fn measures_a_speedup_over_the_sequential_runtime() {
+        let (_, interactions) = sequential();
+        let net = || {
+            let book = hvm::parse_book(&book()).unwrap();
+            (book.connections, book.rulebook, book.ctx)
+        };
+
+        let (stats, speedup) = measure_speedup(net, 2);
+        assert_eq!(stats.interactions(), interactions);
+        assert!(speedup.is_finite() && speedup > 0.0);
+        assert!(stats.utilization().is_finite());
+    }
+
+    #[test]
+    fn ratios_of_no_time_are_zero() {
+        let stats = ParallelStats {
+            elapsed: Duration::ZERO,
+            workers: vec![WorkerStats::default(); 2],
+            sequential_interactions: 0,
+            sequential_elapsed: Duration::ZERO,
+        };
+
+        assert_eq!(stats.utilization(), 0.0);
+        assert_eq!(stats.speedup(Duration::from_millis(1)), 0.0);
+    }
+}
@@ -0,0 +1,269 @@
+//! Reading back a result one agent at a time, reducing only as much as it
+//! takes to expose the next one. This is how an infinite structure, like the
+//! stream of numbers from a generator, can be consumed with `take(n)`.
+//!
+//! Agents are read in prefix order from an interface port: an agent, then
+//! whatever is connected to each of its auxiliary ports in turn. A list made of
+//! `Cons(head, tail)` agents reads back as `Cons`, the head, `Cons`, the next
+//! head, and so on.
+//!
+//! It shares the index that [lazy reduction](super::lazy) keeps up to date, so
+//! reading `n` agents only costs as much as the interactions it takes, plus
+//! indexing the net once.
+
+use rustc_hash::FxHashSet as HashSet;
+
+use super::{lazy::End, Runtime};
+use crate::net::term::AgentKind;
+
+/// What was found at a place in the result.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Node {
+    /// An agent whose principal port faces the place being read. What's
+    /// connected to its auxiliary ports is read next.
+    Agent {
+        /// The ID the agent has in the runtime.
+        id: usize,
+        kind: AgentKind,
+        data: u64,
+        arity: usize,
+    },
+    /// A wire to an auxiliary port of an agent that has already been read, or
+    /// is yet to be, so the result shares structure here rather than being a
+    /// tree.
+    Wire,
+    /// A wire straight to an interface port.
+    Free,
+    /// Something that can't be reduced any further is in the way, like a
+    /// vicious circle or an agent waiting on an interface port.
+    Blocked,
+}
+
+/// A place in the result that's waiting to be read.
+enum Place {
+    Interface(usize),
+    Aux(usize, usize),
+}
+
+/// An iterator over a result, from [`Runtime::read_back`].
+pub struct Readback<'a> {
+    runtime: &'a mut Runtime,
+    places: Vec<Place>,
+    read: HashSet<usize>,
+}
+
+impl Iterator for Readback<'_> {
+    type Item = Node;
+
+    fn next(&mut self) -> Option<Node> {
+        let place = self.places.pop()?;
+
+        loop {
+            let demand = self.runtime.demand();
+            let end = match place {
+                Place::Interface(port_id) => demand.interface_neighbor(port_id),
+                Place::Aux(agent_id, slot) => demand.neighbor(agent_id, slot),
+            };
+
+            let mut agent_id = match end {
+                End::Free => return Some(Node::Free),
+                End::Agent(agent_id, 0) => {
+                    let agent = demand.agent(agent_id);
+                    let arity = agent.arity();
+
+                    self.read.insert(agent_id);
+                    self.places
+                        .extend((1..=arity).rev().map(|slot| Place::Aux(agent_id, slot)));
+
+                    return Some(Node::Agent {
+                        id: agent_id,
+                        kind: agent.kind,
+                        data: agent.data,
+                        arity,
+                    });
+                }
+                End::Agent(agent_id, _) => agent_id,
+            };
+
+            // follow principal ports until they lead back into the result,
+            // or to an active pair that has to be reduced first
+            let mut seen = HashSet::default();
+            let pair = loop {
+                if self.read.contains(&agent_id) || !seen.insert(agent_id) {
+                    break None;
+                }
+                if let Some(key) = demand.pair(agent_id) {
+                    break Some(key);
+                }
+
+                match demand.neighbor(agent_id, 0) {
+                    End::Agent(other, _) => agent_id = other,
+                    End::Free => return Some(Node::Blocked),
+                }
+            };
+
+            let Some(key) = pair else {
+                return Some(if self.read.contains(&agent_id) {
+                    Node::Wire
+                } else {
+                    Node::Blocked
+                });
+            };
+
+            self.runtime.reduce_pairs(HashSet::from_iter([key]));
+        }
+    }
+}
+
+impl Drop for Readback<'_> {
+    fn drop(&mut self) {
+        // nothing keeps the index up to date once the readback is done with
+        // the runtime
+        self.runtime.demand = None;
+    }
+}
+
+impl Runtime {
+    /// Reads back what's connected to the interface port called `name`,
+    /// reducing only what it needs to as it goes. Returns `None` if there's
+    /// no interface port by that name.
+    pub fn read_back(&mut self, name: &str) -> Option<Readback<'_>> {
+        let port_id = self
+            .demand()
+            .named_ports()
+            .into_iter()
+            .filter(|(_, port_name)| *port_name == name)
+            .min()
+            .map(|(port_id, _)| port_id);
+        let Some(port_id) = port_id else {
+            self.demand = None;
+            return None;
+        };
+
+        Some(Readback {
+            runtime: self,
+            places: vec![Place::Interface(port_id)],
+            read: HashSet::default(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        inpla,
+        net::{id::IdAllocator, text::parse_connection},
+        rule::{context::RewriteContext, rulebook::Rulebook},
+    };
+
+    fn runtime(sources: &[&str]) -> Runtime {
+        let connections: Vec<_> = sources
+            .iter()
+            .map(|src| parse_connection(src).unwrap())
+            .collect();
+
+        Runtime::new(
+            connections,
+            Rulebook::default(),
+            RewriteContext::new(IdAllocator::new_at(100)),
+        )
+    }
+
+    fn kinds(nodes: impl Iterator<Item = Node>) -> Vec<Option<AgentKind>> {
+        nodes
+            .map(|node| match node {
+                Node::Agent { kind, .. } => Some(kind),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn reads_the_start_of_an_endless_stream() {
+        let program = inpla::parse_program(
+            "
+            Gen(r) >< Z => r ~ Cons(Z, w), Gen(w) ~ Z;
+            Gen(r) ~ Z;
+            r;
+            ",
+        )
+        .unwrap();
+        let kind = |name: &str| {
+            let id = program.agents.iter().position(|agent| agent == name);
+            Some(AgentKind::Dynamic(id.unwrap()))
+        };
+        let (cons, z) = (kind("Cons"), kind("Z"));
+        let mut runtime = Runtime::new(program.connections, program.rulebook, program.ctx);
+
+        let nodes = kinds(runtime.read_back("r").unwrap().take(7));
+        assert_eq!(nodes, [cons, z, cons, z, cons, z, cons]);
+        // only the four elements that were read were made
+        assert_eq!(runtime.interactions(), 4);
+    }
+
+    #[test]
+    fn reads_data_and_arity() {
+        let mut runtime = runtime(&[
+            r#"Constructor#1($2:"out", Eraser#3()) = Constructor#4(Number{7}#5(), $6)"#,
+            "$6 = Eraser#7()",
+        ]);
+
+        let nodes: Vec<_> = runtime
+            .read_back("out")
+            .unwrap()
+            .map(|node| match node {
+                Node::Agent {
+                    kind, data, arity, ..
+                } => (kind, data, arity),
+                node => panic!("expected an agent, not {node:?}"),
+            })
+            .collect();
+        assert_eq!(nodes, [(AgentKind::Number, 7, 0)]);
+    }
+
+    #[test]
+    fn reads_wires_free_ports_and_blocked_places() {
+        let mut runtime = runtime(&[
+            r#"$1:"shared" = Constructor#2($3, $3)"#,
+            r#"$4:"free" = $5:"other""#,
+            r#"Constructor#6($7, $8:"circle") = $7"#,
+        ]);
+
+        let shared: Vec<_> = runtime.read_back("shared").unwrap().skip(1).collect();
+        assert_eq!(shared, [Node::Wire, Node::Wire]);
+        assert_eq!(
+            runtime.read_back("free").unwrap().collect::<Vec<_>>(),
+            [Node::Free]
+        );
+        assert_eq!(
+            runtime.read_back("circle").unwrap().collect::<Vec<_>>(),
+            [Node::Blocked]
+        );
+        assert!(runtime.read_back("missing").is_none());
+    }
+
+    #[test]
+    fn reads_far_into_a_stream() {
+        let program = inpla::parse_program(
+            "
+            Gen(r) >< Z => r ~ Cons(Z, w), Gen(w) ~ Z;
+            Gen(r) ~ Z;
+            r;
+            ",
+        )
+        .unwrap();
+        let mut runtime = Runtime::new(program.connections, program.rulebook, program.ctx);
+
+        let mut readback = runtime.read_back("r").unwrap();
+        assert_eq!(readback.by_ref().take(20_000).count(), 20_000);
+        // the index is kept while reading, and dropped after
+        assert!(readback.runtime.demand.is_some());
+        drop(readback);
+        assert!(runtime.demand.is_none());
+        assert_eq!(runtime.interactions(), 10_000);
+
+        assert!(runtime.read_back("missing").is_none());
+        assert!(runtime.demand.is_none());
+    }
+}
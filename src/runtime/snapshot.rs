@@ -0,0 +1,321 @@
+//! Saving a running net to a file and picking it back up later.
+//!
+//! A snapshot is line based. It starts with a header, then the state of the ID
+//! allocator, then every connection in the map and finally the action stack
+//! from bottom to top:
+//!
+//! ```text
+//! inet-snapshot 1
+//! interactions 2
+//! next-index 9
+//! free 3 0
+//! generations 1 0 0 1
+//! connection $4:"ed_port" = Constructor#6:"e"($4:"ed_port", $5:"out_port")
+//! reduce 6
+//! ```
+//!
+//! Terms use the format from [`crate::net::text`], so IDs are kept exactly.
+
+use std::{
+    io::{self, BufRead, Write},
+    str::FromStr,
+};
+
+use rustc_hash::FxHashSet as HashSet;
+
+use super::{Action, Runtime};
+use crate::{
+    map::ConnectionMap,
+    net::{
+        connection::Connection,
+        id::{AllocatorState, IdAllocator},
+        term::Term,
+        text,
+    },
+    rule::{context::RewriteContext, rulebook::Rulebook},
+};
+
+const HEADER: &str = "inet-snapshot 1";
+
+#[derive(Debug)]
+pub enum SnapshotError {
+    Io(io::Error),
+    /// A line couldn't be understood. Lines are numbered from one.
+    Parse {
+        line: usize,
+        message: String,
+    },
+}
+
+impl From<io::Error> for SnapshotError {
+    fn from(value: io::Error) -> Self {
+        Self::Io(value)
+    }
+}
+
+fn write_numbers(writer: &mut impl Write, key: &str, numbers: &[usize]) -> io::Result<()> {
+    write!(writer, "{key}")?;
+    for number in numbers {
+        write!(writer, " {number}")?;
+    }
+    writeln!(writer)
+}
+
+fn parse_number<T: FromStr>(value: &str) -> Result<T, String> {
+    let value = value.trim();
+    value
+        .parse()
+        .map_err(|_| format!("`{value}` is not a number"))
+}
+
+fn parse_numbers<T: FromStr>(value: &str) -> Result<Vec<T>, String> {
+    value.split_whitespace().map(parse_number).collect()
+}
+
+impl Runtime {
+    /// Writes everything about the runtime except its rulebook, which has to
+    /// be supplied again when restoring.
+    pub fn save(&self, mut writer: impl Write) -> io::Result<()> {
+        let state = self.ctx.id_alloc.state();
+
+        writeln!(writer, "{HEADER}")?;
+        writeln!(writer, "interactions {}", self.interactions)?;
+        writeln!(writer, "next-index {}", state.next_index)?;
+        write_numbers(&mut writer, "free", &state.free)?;
+        write_numbers(&mut writer, "generations", &state.generations)?;
+
+        let mut line = String::new();
+        for (left, right) in self.connections.iter() {
+            line.clear();
+            text::write_connection(&mut line, left, right).unwrap();
+            writeln!(writer, "connection {line}")?;
+        }
+
+        for action in &self.action_stack {
+            match action {
+                Action::Reduce(id) => writeln!(writer, "reduce {id}")?,
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Restores a runtime written by [`Runtime::save`], exactly as it was.
+    pub fn restore(reader: impl BufRead, rulebook: Rulebook) -> Result<Self, SnapshotError> {
+        let mut connections = ConnectionMap::new();
+        // checked once every connection has been read
+        let mut reduce_lines = Vec::new();
+        let mut interactions = 0;
+        let mut state = AllocatorState::default();
+
+        let mut lines = reader.lines();
+        match lines.next().transpose()? {
+            Some(header) if header == HEADER => {}
+            _ => {
+                return Err(SnapshotError::Parse {
+                    line: 1,
+                    message: format!("expected `{HEADER}`"),
+                })
+            }
+        }
+
+        for (index, line) in lines.enumerate() {
+            let line = line?;
+            let error = |message: String| SnapshotError::Parse {
+                line: index + 2,
+                message,
+            };
+
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let (key, value) = line.split_once(' ').unwrap_or((&line, ""));
+
+            match key {
+                "interactions" => interactions = parse_number(value).map_err(error)?,
+                "next-index" => state.next_index = parse_number(value).map_err(error)?,
+                "free" => state.free = parse_numbers(value).map_err(error)?,
+                "generations" => state.generations = parse_numbers(value).map_err(error)?,
+                "connection" => {
+                    let Connection(left, right) = text::parse_connection(value).map_err(|err| {
+                        error(format!("{} at column {}", err.message, err.position))
+                    })?;
+
+                    connections
+                        .insert(left, right)
+                        .map_err(|err| error(format!("duplicate {err:?} term")))?;
+                }
+                "reduce" => reduce_lines.push((index + 2, parse_number(value).map_err(error)?)),
+                _ => return Err(error(format!("unknown key `{key}`"))),
+            }
+        }
+
+        let mut queued = HashSet::default();
+        let mut action_stack = Vec::with_capacity(reduce_lines.len());
+        for (line, id) in reduce_lines {
+            let error = |message: &str| SnapshotError::Parse {
+                line,
+                message: format!("{message}: {id}"),
+            };
+
+            match connections.get_by_left_key(&id) {
+                Some((Term::Agent(_), Term::Agent(_))) => {}
+                _ => return Err(error("reduce ID isn't an active pair")),
+            }
+            if !queued.insert(id) {
+                return Err(error("active pair is reduced twice"));
+            }
+
+            action_stack.push(Action::Reduce(id));
+        }
+
+        Ok(Self {
+            connections,
+            action_stack,
+            rulebook,
+            ctx: RewriteContext::new(IdAllocator::from_state(state)),
+            interactions,
+            slots: Vec::new(),
+            new_connections: Vec::new(),
+            history: None,
+            demand: None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::net::{canonical::CanonicalNet, term::AgentKind};
+
+    /// Duplicates a small tree into a constructor on the free port `root`.
+    fn runtime() -> Runtime {
+        use AgentKind::*;
+
+        let ctx = RewriteContext::new(IdAllocator::new());
+        let era = || ctx.create_agent(Eraser, &[]);
+        let tree = ctx.create_agent(
+            Constructor,
+            &[era(), ctx.create_agent(Constructor, &[era(), era()])],
+        );
+        let (a, b) = (ctx.create_port(), ctx.create_port());
+        let dup = ctx.create_agent(Duplicator, &[a.clone(), b.clone()]);
+        let root = ctx.create_port().with_name("root");
+        let pair = ctx.create_agent(Constructor, &[a, b]).with_name("pair");
+
+        Runtime::new(
+            [tree.connect(dup), root.connect(pair)],
+            Rulebook::default(),
+            ctx,
+        )
+    }
+
+    fn save(runtime: &Runtime) -> String {
+        let mut saved = Vec::new();
+        runtime.save(&mut saved).unwrap();
+        String::from_utf8(saved).unwrap()
+    }
+
+    /// The lines of a snapshot, with connections sorted since the map has no
+    /// order.
+    fn lines(snapshot: &str) -> (Vec<&str>, Vec<&str>) {
+        let (mut connections, others): (Vec<_>, Vec<_>) = snapshot
+            .lines()
+            .partition(|line| line.starts_with("connection "));
+        connections.sort_unstable();
+
+        (connections, others)
+    }
+
+    fn restore(snapshot: &str) -> Result<Runtime, SnapshotError> {
+        Runtime::restore(snapshot.as_bytes(), Rulebook::default())
+    }
+
+    fn normal_form(runtime: Runtime) -> CanonicalNet {
+        CanonicalNet::new(runtime.normalize().into_iter().map(Connection::from))
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut original = runtime();
+        let snapshot = save(&original);
+
+        let mut restored = restore(&snapshot).unwrap();
+        assert_eq!(lines(&save(&restored)), lines(&snapshot));
+
+        original.reduce();
+        restored.reduce();
+        assert_eq!(restored.interactions(), original.interactions());
+        assert_eq!(lines(&save(&restored)), lines(&save(&original)));
+        assert_eq!(normal_form(restored), normal_form(original));
+    }
+
+    #[test]
+    fn round_trip_after_reducing() {
+        let mut original = runtime();
+        original.reduce();
+        let snapshot = save(&original);
+
+        let restored = restore(&snapshot).unwrap();
+        assert_eq!(restored.interactions(), original.interactions());
+        assert_eq!(lines(&save(&restored)), lines(&snapshot));
+
+        // the allocator carries on from the same place
+        assert_eq!(
+            restored.ctx.id_alloc.create_id(),
+            original.ctx.id_alloc.create_id()
+        );
+    }
+
+    fn parse_error(snapshot: &str) -> (usize, String) {
+        match restore(snapshot) {
+            Err(SnapshotError::Parse { line, message }) => (line, message),
+            Err(SnapshotError::Io(err)) => panic!("unexpected io error: {err}"),
+            Ok(_) => panic!("expected a parse error"),
+        }
+    }
+
+    #[test]
+    fn rejects_reduce_of_a_missing_pair() {
+        let snapshot = save(&runtime()) + "reduce 12345\n";
+        let last_line = snapshot.lines().count();
+
+        let (line, message) = parse_error(&snapshot);
+        assert_eq!(line, last_line);
+        assert!(message.contains("isn't an active pair"), "{message}");
+    }
+
+    #[test]
+    fn rejects_reduce_of_a_port() {
+        // the free port is on the left of its connection, as `$id:"root"`
+        let snapshot = save(&runtime());
+        let port_id: String = snapshot
+            .lines()
+            .find_map(|line| line.strip_prefix("connection $"))
+            .unwrap()
+            .chars()
+            .take_while(char::is_ascii_digit)
+            .collect();
+
+        let (_, message) = parse_error(&format!("{snapshot}reduce {port_id}\n"));
+        assert!(message.contains("isn't an active pair"), "{message}");
+    }
+
+    #[test]
+    fn rejects_a_pair_reduced_twice() {
+        let snapshot = save(&runtime());
+        let reduce = snapshot
+            .lines()
+            .find(|line| line.starts_with("reduce "))
+            .unwrap();
+
+        let (_, message) = parse_error(&format!("{snapshot}{reduce}\n"));
+        assert!(message.contains("twice"), "{message}");
+    }
+
+    #[test]
+    fn rejects_a_bad_header() {
+        assert_eq!(parse_error("inet-snapshot 0\n").0, 1);
+    }
+}
@@ -0,0 +1,96 @@
+use super::Runtime;
+
+#[derive(Clone, Debug, Default)]
+pub struct SpanStats {
+    /// Number of rounds it took to reach normal form, i.e. the span.
+    pub rounds: usize,
+    /// Total number of interactions, i.e. the work.
+    pub interactions: usize,
+    /// The most interactions that happened in a single round.
+    pub widest_round: usize,
+}
+
+impl SpanStats {
+    /// Average number of interactions per round. This is the speedup that an
+    /// ideal machine with unlimited cores could get over a sequential one.
+    ///
+    /// A net that was already in normal form took no rounds and has nothing
+    /// to run in parallel, so this is zero for it.
+    pub fn parallelism(&self) -> f64 {
+        if self.rounds == 0 {
+            return 0.0;
+        }
+
+        self.interactions as f64 / self.rounds as f64
+    }
+}
+
+impl Runtime {
+    /// Reduces in synchronous rounds: every active pair that exists at the
+    /// start of a round is reduced in that round, and any active pairs that
+    /// they create wait for the next.
+    ///
+    /// This measures how parallel a net is without actually running it on
+    /// multiple threads.
+    pub fn reduce_in_rounds(&mut self) -> SpanStats {
+        let mut stats = SpanStats::default();
+
+        while !self.action_stack.is_empty() {
+            let round = std::mem::take(&mut self.action_stack);
+
+            stats.rounds += 1;
+            stats.interactions += round.len();
+            stats.widest_round = stats.widest_round.max(round.len());
+
+            for action in round {
+                self.apply(action);
+            }
+        }
+
+        stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        net::{id::IdAllocator, term::AgentKind},
+        rule::{context::RewriteContext, rulebook::Rulebook},
+        runtime::Runtime,
+    };
+
+    #[test]
+    fn normal_form_has_no_parallelism() {
+        let ctx = RewriteContext::new(IdAllocator::new());
+        let root = ctx.create_port();
+        let era = ctx.create_agent(AgentKind::Eraser, &[]);
+
+        let mut runtime = Runtime::new([root.connect(era)], Rulebook::default(), ctx);
+        let stats = runtime.reduce_in_rounds();
+
+        assert_eq!(stats.rounds, 0);
+        assert_eq!(stats.interactions, 0);
+        assert_eq!(stats.parallelism(), 0.0);
+    }
+
+    #[test]
+    fn erasing_a_tree_takes_a_round_per_level() {
+        // erasing a full binary tree of constructors erases every level at
+        // once, so it takes one round per level plus one for the leaves
+        let ctx = RewriteContext::new(IdAllocator::new());
+        let mut tree = ctx.create_agent(AgentKind::Eraser, &[]);
+        for _ in 0..4 {
+            let copy = tree.clone().map_ids(&mut |_| ctx.id_alloc.create_id());
+            tree = ctx.create_agent(AgentKind::Constructor, &[tree, copy]);
+        }
+        let era = ctx.create_agent(AgentKind::Eraser, &[]);
+
+        let mut runtime = Runtime::new([tree.connect(era)], Rulebook::default(), ctx);
+        let stats = runtime.reduce_in_rounds();
+
+        assert_eq!(stats.rounds, 5);
+        assert_eq!(stats.interactions, 15 + 16);
+        assert_eq!(stats.widest_round, 16);
+        assert_eq!(stats.parallelism(), 31.0 / 5.0);
+    }
+}
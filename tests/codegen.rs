@@ -0,0 +1,84 @@
+//! Compiles the code generated from `fixtures/rules`, like a build script
+//! would, and checks that it rewrites like the rules it was generated from.
+
+use inet_rs::{
+    net::{canonical::CanonicalNet, id::IdAllocator, term::Agent},
+    rule::{
+        codegen, compiled::CompiledRule, context::RewriteContext, parse::parse_rules,
+        rulebook::Rulebook, Rule,
+    },
+};
+
+mod generated {
+    include!("fixtures/rules.rs");
+}
+
+use generated::Rules;
+
+const RULES: &str = include_str!("fixtures/rules");
+const GENERATED: &str = include_str!("fixtures/rules.rs");
+
+#[test]
+fn generated_code_is_up_to_date() {
+    let templates = parse_rules(RULES).unwrap();
+    let code = codegen::generate(&templates, "Rules").unwrap();
+
+    if std::env::var_os("UPDATE_FIXTURES").is_some() {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/rules.rs");
+        std::fs::write(path, &code).unwrap();
+    }
+
+    assert!(
+        code == GENERATED,
+        "tests/fixtures/rules.rs is out of date, so run this test again with UPDATE_FIXTURES=1"
+    );
+}
+
+/// An agent like one in a rule, with named interface ports.
+fn instance(ctx: &RewriteContext, agent: &Agent, prefix: &str) -> Agent {
+    let ports: Vec<_> = (0..agent.ports.len())
+        .map(|index| ctx.create_port().with_name(format!("{prefix}{index}")))
+        .collect();
+
+    Agent::new(ctx.id_alloc.create_id(), agent.kind, ports).with_data(agent.data)
+}
+
+#[test]
+fn generated_rules_rewrite_like_their_templates() {
+    let templates = parse_rules(RULES).unwrap();
+    assert_eq!(Rules::all().len(), templates.len());
+
+    for (rule, template) in Rules::all().into_iter().zip(templates) {
+        assert!(rule.pattern() == template.pattern());
+
+        let ctx = RewriteContext::new(IdAllocator::new());
+        let left = instance(&ctx, template.left(), "l");
+        let right = instance(&ctx, template.right(), "r");
+        let other_ctx = RewriteContext::new(IdAllocator::from_state(ctx.id_alloc.state()));
+
+        let expected = CompiledRule::compile(template).rewrite(&ctx, left.clone(), right.clone());
+        let actual = rule.rewrite(&other_ctx, left, right);
+
+        assert!(
+            CanonicalNet::new(actual.new_connections)
+                == CanonicalNet::new(expected.new_connections),
+            "{rule:?} rewrites differently from its rule"
+        );
+    }
+}
+
+#[test]
+fn add_to_adds_native_rules() {
+    let mut rulebook = Rulebook::default();
+    Rules::add_to(&mut rulebook);
+
+    for rule in Rules::all() {
+        let added = rulebook
+            .rules()
+            .find(|(pattern, _)| **pattern == rule.pattern());
+        assert!(
+            matches!(added, Some((_, Rule::Native(_)))),
+            "{rule:?} wasn't added"
+        );
+    }
+}
@@ -0,0 +1,135 @@
+// Generated by inet-rs from a rule file. Don't edit it by hand.
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Rules {
+    /// `E = E -> ()`
+    EraEra,
+    /// `D(b0, b1) = C(a0, a1) -> a0 = D(c0, c1), a1 = D(d0, d1), b0 = C(c0, d0), b1 = C(c1, d1)`
+    DupCtr,
+    /// `C(a, b) = Dynamic[0](out) -> out = Dynamic[0](x), a = Number{1}, b = BinaryOp[Add](x, y), E = y`
+    CtrDyn0,
+    /// `Dynamic[1](a, b) = Dynamic[2](c) -> a = C(D(b, c), E)`
+    Dyn1Dyn2,
+}
+
+impl Rules {
+    pub fn all() -> Vec<Self> {
+        vec![
+            Self::EraEra,
+            Self::DupCtr,
+            Self::CtrDyn0,
+            Self::Dyn1Dyn2,
+        ]
+    }
+
+    pub fn pattern(&self) -> ::inet_rs::rule::rulebook::ActivePairPattern {
+        use ::inet_rs::{net::term::AgentKind, rule::rulebook::ActivePairPattern};
+
+        match self {
+            Self::EraEra => ActivePairPattern::new(AgentKind::Eraser, AgentKind::Eraser),
+            Self::DupCtr => ActivePairPattern::new(AgentKind::Duplicator, AgentKind::Constructor),
+            Self::CtrDyn0 => ActivePairPattern::new(AgentKind::Constructor, AgentKind::Dynamic(0)),
+            Self::Dyn1Dyn2 => ActivePairPattern::new(AgentKind::Dynamic(1), AgentKind::Dynamic(2)),
+        }
+    }
+
+    /// Agents passed into this function are sorted by their AgentKind.
+    pub fn rewrite(
+        &self,
+        ctx: &::inet_rs::rule::context::RewriteContext,
+        a: ::inet_rs::net::term::Agent,
+        b: ::inet_rs::net::term::Agent,
+    ) -> ::inet_rs::rule::RewriteResult {
+        #[allow(unused_imports)]
+        use ::inet_rs::{
+            net::{
+                connection::Connection,
+                term::{Agent, AgentKind, Term},
+            },
+            rule::RewriteResult,
+        };
+
+        match self {
+            Self::EraEra => {
+                ctx.id_alloc.retire_id(a.id);
+                ctx.id_alloc.retire_id(b.id);
+
+                RewriteResult::empty()
+            }
+            Self::DupCtr => {
+                ctx.id_alloc.retire_id(a.id);
+                ctx.id_alloc.retire_id(b.id);
+
+                let [a0, a1] = a.ports_array().unwrap();
+                let [b0, b1] = b.ports_array().unwrap();
+                let w0 = ctx.create_port();
+                let w1 = ctx.create_port();
+                let w2 = ctx.create_port();
+                let w3 = ctx.create_port();
+
+                RewriteResult {
+                    new_connections: vec![
+                        Connection(b0, Term::Agent(Agent::new(ctx.id_alloc.create_id(), AgentKind::Duplicator, [w0.clone(), w1.clone()]))),
+                        Connection(b1, Term::Agent(Agent::new(ctx.id_alloc.create_id(), AgentKind::Duplicator, [w2.clone(), w3.clone()]))),
+                        Connection(a0, Term::Agent(Agent::new(ctx.id_alloc.create_id(), AgentKind::Constructor, [w0, w2]))),
+                        Connection(a1, Term::Agent(Agent::new(ctx.id_alloc.create_id(), AgentKind::Constructor, [w1, w3]))),
+                    ],
+                }
+            }
+            Self::CtrDyn0 => {
+                ctx.id_alloc.retire_id(a.id);
+                ctx.id_alloc.retire_id(b.id);
+
+                let [a0, a1] = a.ports_array().unwrap();
+                let [b0] = b.ports_array().unwrap();
+                let w0 = ctx.create_port();
+                let w1 = ctx.create_port();
+
+                RewriteResult {
+                    new_connections: vec![
+                        Connection(b0, Term::Agent(Agent::new(ctx.id_alloc.create_id(), AgentKind::Dynamic(0), [w0.clone()]))),
+                        Connection(a0, Term::Agent(Agent::new(ctx.id_alloc.create_id(), AgentKind::Number, Vec::<Term>::new()).with_data(1))),
+                        Connection(a1, Term::Agent(Agent::new(ctx.id_alloc.create_id(), AgentKind::BinaryOp(::inet_rs::net::term::Operator::Add), [w0, w1.clone()]))),
+                        Connection(Term::Agent(Agent::new(ctx.id_alloc.create_id(), AgentKind::Eraser, Vec::<Term>::new())), w1),
+                    ],
+                }
+            }
+            Self::Dyn1Dyn2 => {
+                ctx.id_alloc.retire_id(a.id);
+                ctx.id_alloc.retire_id(b.id);
+
+                let [a0, a1] = a.ports_array().unwrap();
+                let [b0] = b.ports_array().unwrap();
+
+                RewriteResult {
+                    new_connections: vec![
+                        Connection(a0, Term::Agent(Agent::new(ctx.id_alloc.create_id(), AgentKind::Constructor, [Term::Agent(Agent::new(ctx.id_alloc.create_id(), AgentKind::Duplicator, [a1, b0])), Term::Agent(Agent::new(ctx.id_alloc.create_id(), AgentKind::Eraser, Vec::<Term>::new()))]))),
+                    ],
+                }
+            }
+        }
+    }
+
+    /// Adds every rule to a rulebook, replacing any it already has for the
+    /// same patterns.
+    pub fn add_to(rulebook: &mut ::inet_rs::rule::rulebook::Rulebook) {
+        use ::inet_rs::rule::Rule;
+
+        rulebook.add_rule(
+            Self::EraEra.pattern(),
+            Rule::Native(|ctx, a, b| Self::EraEra.rewrite(ctx, a, b)),
+        );
+        rulebook.add_rule(
+            Self::DupCtr.pattern(),
+            Rule::Native(|ctx, a, b| Self::DupCtr.rewrite(ctx, a, b)),
+        );
+        rulebook.add_rule(
+            Self::CtrDyn0.pattern(),
+            Rule::Native(|ctx, a, b| Self::CtrDyn0.rewrite(ctx, a, b)),
+        );
+        rulebook.add_rule(
+            Self::Dyn1Dyn2.pattern(),
+            Rule::Native(|ctx, a, b| Self::Dyn1Dyn2.rewrite(ctx, a, b)),
+        );
+    }
+}